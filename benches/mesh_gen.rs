@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 use std::rc::Rc;
-use criterion::{Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use matrixagon2::{AppConfig, PackedChunkVertex};
 use matrixagon2::component::camera::Length3D;
 use matrixagon2::component::terrain::{BlockData, MeshType, TextureMapper, TransparencyType};
-use matrixagon2::component::terrain::chunk_gen_mf::ChunkGeneratorMF;
+use matrixagon2::component::terrain::chunk_gen::ChunkGeneratorMF;
+use matrixagon2::component::terrain::chunk_mesh::{ChunkGeneratable, ChunkMesh, ChunkRadius, UpdateChunk};
 use matrixagon2::component::terrain::terrain_gen::TerrainGenerator;
-use matrixagon2::chunk_mesh::{ChunkGeneratable, ChunkMesh, ChunkRadius, UpdateChunk};
 
 
 const BLOCK_INDEX: [BlockData; 8] = [
@@ -69,9 +71,9 @@ pub fn benchmark_chunk_mesh_generation(c: &mut Criterion) {
                 Rc::new(HashMap::from([
                     (String::from("grass_top"), 0),
                 ])),
-                Rc::new(TerrainGenerator::new())
+                Rc::new(TerrainGenerator::new(AppConfig::DEFAULT_SEED))
             );
-            chunk_generator.generate_mesh(Length3D::origin());
+            chunk_generator.generate_mesh([0; 3]);
         })
     );
 }
@@ -87,18 +89,52 @@ pub fn benchmark_chunk_aggregate_mesh(c: &mut Criterion) {
                     Vec::from(BLOCK_INDEX), Rc::new(HashMap::from([
                         (String::from("grass_top"), 0),
                     ])),
-                    Rc::new(TerrainGenerator::new())
+                    Rc::new(TerrainGenerator::new(AppConfig::DEFAULT_SEED))
                 ),
             );
             chunk_mesh_mf.update(UpdateChunk::Forced);
+            chunk_mesh_mf.load_all();
         })
     );
 }
 
+// meshing a single chunk into either vertex format, and packing on its own
+pub fn benchmark_vertex_formats(c: &mut Criterion) {
+    let chunk_generator = ChunkGeneratorMF::new(
+        Vec::from(BLOCK_INDEX),
+        Rc::new(HashMap::from([
+            (String::from("grass_top"), 0),
+        ])),
+        Rc::new(TerrainGenerator::new(AppConfig::DEFAULT_SEED))
+    );
+    let unpacked = chunk_generator.generate_unpacked_mesh([0; 3]);
+    let vertices = unpacked.iter().map(|(verts, _, _, _)| verts.len()).sum::<usize>();
+
+    let mut group = c.benchmark_group("Single MF Chunk @(0,0,0) - Vertex Formats");
+    group.throughput(Throughput::Elements(vertices as u64));
+    group.bench_function(
+        "Mesh Generation (ChunkVertex)",
+        |b| b.iter_with_large_drop(|| chunk_generator.generate_unpacked_mesh(black_box([0; 3])))
+    );
+    group.bench_function(
+        "Mesh Generation (PackedChunkVertex)",
+        |b| b.iter_with_large_drop(|| chunk_generator.generate_mesh(black_box([0; 3])))
+    );
+    group.bench_function(
+        "ChunkVertex::pack",
+        |b| b.iter_with_large_drop(|| {
+            black_box(&unpacked).iter()
+                .map(|(verts, _, face, _)| verts.iter().map(|vert| vert.pack(*face)).collect::<Vec<PackedChunkVertex>>())
+                .collect::<Vec<_>>()
+        })
+    );
+    group.finish();
+}
+
 criterion_group!(
     name = benches;
     config = Criterion::default();
-    targets = benchmark_chunk_mesh_generation
+    targets = benchmark_chunk_mesh_generation, benchmark_vertex_formats
 );
 criterion_group!(
     name = benches_heavy;
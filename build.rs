@@ -0,0 +1,81 @@
+use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+
+// compiles every .vert and .frag in src/shader with glslc into OUT_DIR, the spir-v is embedded
+// through the generated shaders.rs. glslc is looked up in GLSLC, the vulkan sdk and then the PATH
+fn find_glslc() -> Option<PathBuf> {
+    let exe = if cfg!(windows) {"glslc.exe"} else {"glslc"};
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::var_os("GLSLC").map(PathBuf::from).into_iter()
+        .chain(env::var_os("VULKAN_SDK").map(|sdk| Path::new(&sdk).join("bin").join(exe)))
+        .chain(env::split_paths(&path).map(|dir| dir.join(exe)))
+        .find(|candidate| Command::new(candidate).arg("--version").output().is_ok_and(|output| output.status.success()))
+}
+
+// the loader finds drivers through their icd manifests, or the registry on windows. the tests rendering with the
+// gpu are ignored without any
+fn find_vulkan_driver() -> bool {
+    if env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os == "windows") {
+        return true;
+    }
+    if env::var_os("VK_DRIVER_FILES").or(env::var_os("VK_ICD_FILENAMES")).is_some() {
+        return true;
+    }
+    let data_dirs = env::var_os("XDG_DATA_DIRS").unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+    env::split_paths(&data_dirs)
+        .chain(env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .chain([PathBuf::from("/etc")])
+        .any(|dir| fs::read_dir(dir.join("vulkan/icd.d")).is_ok_and(|mut manifests| manifests.next().is_some()))
+}
+
+fn main() {
+    println!("cargo::rerun-if-changed=src/shader");
+    println!("cargo::rerun-if-env-changed=GLSLC");
+    println!("cargo::rerun-if-env-changed=VULKAN_SDK");
+    println!("cargo::rerun-if-env-changed=VK_DRIVER_FILES");
+    println!("cargo::rerun-if-env-changed=VK_ICD_FILENAMES");
+    println!("cargo::rustc-check-cfg=cfg(vulkan_driver)");
+
+    if find_vulkan_driver() {
+        println!("cargo::rustc-cfg=vulkan_driver");
+    }
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("shaders");
+    fs::create_dir_all(&out_dir).expect("Failed to create the shader output directory");
+
+    let mut shaders = fs::read_dir("src/shader").expect("Failed to read the shader directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "vert" || ext == "frag"))
+        .collect::<Vec<_>>();
+    shaders.sort();
+
+    let glslc = find_glslc()
+        .expect("Failed to find glslc, which is needed to compile the shaders. Set GLSLC or VULKAN_SDK, or add it to the PATH");
+    println!("cargo::rustc-env=MTXG2_GLSLC={}", glslc.display());  // for the shader hot reload
+
+    let mut entries = String::new();
+    for shader in &shaders {
+        let name = shader.file_name().unwrap().to_str().unwrap();
+        let spv = out_dir.join(format!("{name}.spv"));
+
+        let output = Command::new(&glslc)
+            .arg(shader)
+            .arg("-o")
+            .arg(&spv)
+            .output()
+            .expect("Failed to run glslc");
+        if !output.status.success() {
+            panic!("Failed to compile shader {name}:\n{}", String::from_utf8_lossy(&output.stderr));
+        }
+        entries += &format!("    ({name:?}, include_bytes!({:?})),\n", spv.to_str().unwrap());
+    }
+
+    let generated = format!(
+        "// generated by build.rs, the spir-v of every shader in src/shader\n\
+         pub(crate) const COMPILED_SHADERS: [(&str, &[u8]); {}] = [\n{entries}];\n",
+        shaders.len()
+    );
+    fs::write(out_dir.with_file_name("shaders.rs"), generated).expect("Failed to write shaders.rs");
+}
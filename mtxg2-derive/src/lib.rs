@@ -1,103 +1,221 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 
-use syn::{DeriveInput, parse_macro_input, Type, TypeArray, TypePath, Expr, ExprLit, Lit, LitInt, Ident};
-use quote::quote;
-
-
-
-fn convert_into_vulkan_type(data_type: String, len: usize) -> ash::vk::Format {
-    match (data_type.as_str(), len) {
-        ("f32", 4) => ash::vk::Format::R32G32B32A32_SFLOAT,
-        ("f32", 3) => ash::vk::Format::R32G32B32_SFLOAT,
-        ("f32", 2) => ash::vk::Format::R32G32_SFLOAT,
-        ("f32", 1) => ash::vk::Format::R32_SFLOAT,
-        ("u8", 4) => ash::vk::Format::R8G8B8A8_UNORM,
-        ("u8", 3) => ash::vk::Format::R8G8B8_UNORM,
-        ("u8", 2) => ash::vk::Format::R8G8_UNORM,
-        ("u8", 1) => ash::vk::Format::R8_UNORM,
-        _ => unimplemented!("Vertex Derive / Type Conversion: Unknown Possible Valid Type {:?} {:?}", data_type, len)
-    }
+use std::collections::HashMap;
+use syn::{DeriveInput, parse_macro_input, Type, TypeArray, TypePath, Expr, ExprLit, Lit, LitInt, Attribute, Error};
+use quote::{format_ident, quote};
+
+mod uniform;
+
+
+// how the shader reads the field, the vulkan format suffix
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Interpretation {
+    Unorm,
+    Snorm,
+    Uint,
+    Sint,
+    Sfloat,
 }
 
+impl Interpretation {
+    fn parse(ident: &str) -> Option<Self> {
+        match ident {
+            "unorm" => Some(Self::Unorm),
+            "snorm" => Some(Self::Snorm),
+            "uint" => Some(Self::Uint),
+            "sint" => Some(Self::Sint),
+            "sfloat" => Some(Self::Sfloat),
+            _ => None,
+        }
+    }
 
-#[proc_macro_derive(Vertex)]
-pub fn derive_vertex(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
+    // the shader side type of a vector with the given components
+    fn glsl_type(&self, len: usize) -> String {
+        let (scalar, prefix) = match self {
+            Self::Unorm | Self::Snorm | Self::Sfloat => ("float", ""),
+            Self::Uint => ("uint", "u"),
+            Self::Sint => ("int", "i"),
+        };
+        if len == 1 {
+            scalar.to_string()
+        } else {
+            format!("{prefix}vec{len}")
+        }
+    }
 
-    let name = input.ident;
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::Unorm => "UNORM",
+            Self::Snorm => "SNORM",
+            Self::Uint => "UINT",
+            Self::Sint => "SINT",
+            Self::Sfloat => "SFLOAT",
+        }
+    }
+}
 
-    let data = if let syn::Data::Struct(data) = input.data {
-        data
-    } else {
-        unimplemented!();
+fn convert_into_vulkan_type(ty: &Type, data_type: String, len: usize, interpretation: Option<Interpretation>) -> Result<(proc_macro2::TokenStream, String), Error> {
+    use Interpretation::*;
+
+    // the first interpretation is the default one of the type
+    let (bits, valid): (usize, &[Interpretation]) = match data_type.as_str() {
+        "f32" => (32, &[Sfloat]),
+        "u8" => (8, &[Unorm, Uint]),
+        "u16" => (16, &[Uint, Unorm, Sfloat]),  // sfloat for half floats stored as bits
+        "i16" => (16, &[Sint, Snorm]),
+        "u32" => (32, &[Uint]),
+        "i32" => (32, &[Sint]),
+        _ => return Err(Error::new_spanned(ty, "Vertex Derive / Type: Only f32, u8, u16, i16, u32 and i32 scalars and arrays of them are supported")),
+    };
+    if !(1..=4).contains(&len) {
+        return Err(Error::new_spanned(ty, format!("Vertex Derive / Type: Arrays need 1 to 4 components, not {len}")));
+    }
+    let interpretation = interpretation.unwrap_or(valid[0]);
+    if !valid.contains(&interpretation) {
+        return Err(Error::new_spanned(ty, format!("Vertex Derive / Type: {data_type} cannot be read as {}", interpretation.suffix().to_lowercase())));
+    }
+
+    let channels: String = ["R", "G", "B", "A"][..len].iter()
+        .map(|c| format!("{c}{bits}"))
+        .collect();
+    let format = format_ident!("{}_{}", channels, interpretation.suffix());
+    Ok((quote! {ash::vk::Format::#format}, interpretation.glsl_type(len)))
+}
+
+// the scalar type and component count of a field
+fn field_type(ty: &Type) -> Result<(String, usize), Error> {
+    let unsupported = || Error::new_spanned(ty, "Vertex Derive / Type: Only scalars and arrays of them are supported");
+    let scalar = |ty: &Type| match ty {
+        Type::Path(TypePath {path, ..}) => path.get_ident().map(|ident| ident.to_string()),
+        _ => None,
     };
 
-    let vertex_attribute_locations = data.fields.iter().map(|f| {
-        match &f.ty {
-            Type::Array(TypeArray { elem, len: Expr::Lit(ExprLit {attrs: _, lit: Lit::Int(length)}), .. }) => {
-                if let Type::Path(TypePath {path, ..}) = (**elem).clone() {
-                    if let Some(homogenous_type) = path.get_ident() {
-                        // println!("Vertex Derive / Array: {:?} {:?}", homogenous_type.to_string(), length.base10_parse::<usize>().unwrap());
-                        let vk_type = convert_into_vulkan_type(homogenous_type.to_string(), length.base10_parse::<usize>().unwrap());
-                        let vk_format: proc_macro2::TokenStream = format!("ash::vk::Format::{:?}", vk_type).parse().unwrap();
-                        // println!("Vertex Derive / Array: {:?}", vk_format);
-                        let field_name = &f.ident.as_ref().expect("Field names are expected.");
-                        quote! {
-                            ash::vk::VertexInputAttributeDescription {
-                                binding: 0u32,
-                                location: 0u32,
-                                format: #vk_format,
-                                offset: {
-                                    let f_u8_ptr = std::ptr::addr_of!((*b_ptr).#field_name) as *const u8;
-                                    f_u8_ptr.offset_from(b_u8_ptr) as u32
-                                },
-                            }
-                        }
-                    } else {
-                        unimplemented!("Vertex Derive / Array: Not a type");
+    match ty {
+        Type::Array(TypeArray {elem, len: Expr::Lit(ExprLit {lit: Lit::Int(len), ..}), ..}) => {
+            Ok((scalar(elem).ok_or_else(unsupported)?, len.base10_parse::<usize>()?))
+        }
+        ty => Ok((scalar(ty).ok_or_else(unsupported)?, 1)),
+    }
+}
+
+#[derive(Default)]
+struct VertexAttributes {
+    interpretation: Option<Interpretation>,
+    location: Option<u32>,
+    binding: Option<u32>,
+    instance: bool,
+}
+
+// bindings can only be set on the struct, since all of its fields are read at the same rate, while
+// interpretations only make sense on fields
+fn parse_vertex_attributes(attrs: &[Attribute], on_struct: bool) -> Result<VertexAttributes, Error> {
+    let mut parsed = VertexAttributes::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("vertex")) {
+        attr.parse_nested_meta(|meta| {
+            let ident = meta.path.get_ident().map(|ident| ident.to_string()).unwrap_or_default();
+            match ident.as_str() {
+                "location" => {
+                    let location = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                    if parsed.location.replace(location).is_some() {
+                        return Err(meta.error("Vertex Derive / Attribute: The location is set more than once"));
                     }
-                } else {
-                    unimplemented!("Vertex Derive / Array: Not a type");
                 }
-            }
-            Type::Path(TypePath {path, ..}) => {
-                if let Some(scalar_type) = path.get_ident() {
-                    // println!("Vertex Derive / Type: {:?}", scalar_type.to_string());
-                    let vk_type = convert_into_vulkan_type(scalar_type.to_string(), 1);
-                    let vk_format: proc_macro2::TokenStream = format!("ash::vk::Format::{:?}", vk_type).parse().unwrap();
-                    // println!("Vertex Derive / Array: {:?}", vk_format);
-                    let field_name = &f.ident.as_ref().expect("Field names are expected.");
-                    quote! {
-                        ash::vk::VertexInputAttributeDescription {
-                            binding: 0u32,
-                            location: 0u32,
-                            format: #vk_format,
-                            offset: {
-                                let f_u8_ptr = std::ptr::addr_of!((*b_ptr).#field_name) as *const u8;
-                                f_u8_ptr.offset_from(b_u8_ptr) as u32
-                            },
+                "binding" | "instance" if !on_struct => {
+                    return Err(meta.error("Vertex Derive / Attribute: Bindings are only valid on the struct, a binding cannot mix per vertex and per instance fields"));
+                }
+                "binding" => {
+                    let binding = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                    if parsed.binding.replace(binding).is_some() {
+                        return Err(meta.error("Vertex Derive / Attribute: The binding is set more than once"));
+                    }
+                }
+                "instance" => parsed.instance = true,
+                _ => match Interpretation::parse(&ident) {
+                    Some(_) if on_struct => {
+                        return Err(meta.error("Vertex Derive / Attribute: Interpretations are only valid on fields"));
+                    }
+                    Some(interpretation) => {
+                        if parsed.interpretation.replace(interpretation).is_some() {
+                            return Err(meta.error("Vertex Derive / Attribute: A field can only be read in one way"));
                         }
                     }
-                } else {
-                    unimplemented!("Vertex Derive / Type: Not a type");
+                    None => return Err(meta.error("Vertex Derive / Attribute: Unknown vertex attribute")),
                 }
             }
-            _ => {
-                unimplemented!("Vertex Derive / Unknown: {:?}", &f.ty);
-            }
+            Ok(())
+        })?;
+    }
+    Ok(parsed)
+}
+
+
+// #[vertex(instance, binding = 1, location = 4)] on the struct, the locations count up from the
+// given one (0 by default) unless a field sets its own. fields take #[vertex(unorm/snorm/uint/sint/sfloat)]
+// to change how the shader reads them and #[vertex(location = 2)]
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand_vertex(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn expand_vertex(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+    let struct_attributes = parse_vertex_attributes(&input.attrs, true)?;
+    let binding = struct_attributes.binding.unwrap_or(0);
+    let input_rate: proc_macro2::TokenStream = if struct_attributes.instance {
+        quote! {ash::vk::VertexInputRate::INSTANCE}
+    } else {
+        quote! {ash::vk::VertexInputRate::VERTEX}
+    };
+
+    let syn::Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(name, "Vertex Derive: Only structs with named fields are supported"));
+    };
+
+    let mut glsl_input = format!("// generated by #[derive(Vertex)] from {name}, do not edit\n");
+    let mut next_location = struct_attributes.location.unwrap_or(0);
+    let mut used_locations = HashMap::new();
+    let mut vertex_attribute_locations = Vec::new();
+    for f in &data.fields {
+        let Some(field_name) = &f.ident else {
+            return Err(Error::new_spanned(f, "Vertex Derive: Only structs with named fields are supported"));
+        };
+        let field_attributes = parse_vertex_attributes(&f.attrs, false)?;
+        let location = field_attributes.location.unwrap_or(next_location);
+        next_location = location+1;
+        if let Some(other) = used_locations.insert(location, field_name) {
+            return Err(Error::new_spanned(f, format!("Vertex Derive / Location: Location {location} is already used by {other}")));
         }
-    });
+
+        let (data_type, len) = field_type(&f.ty)?;
+        let (vk_format, glsl_type) = convert_into_vulkan_type(&f.ty, data_type, len, field_attributes.interpretation)?;
+        glsl_input += &format!("layout(location = {location}) in {glsl_type} {field_name};\n");
+        vertex_attribute_locations.push(quote! {
+            ash::vk::VertexInputAttributeDescription {
+                binding: #binding,
+                location: #location,
+                format: #vk_format,
+                offset: {
+                    let f_u8_ptr = std::ptr::addr_of!((*b_ptr).#field_name) as *const u8;
+                    f_u8_ptr.offset_from(b_u8_ptr) as u32
+                },
+            }
+        });
+    }
 
     let field_count = vertex_attribute_locations.len();
 
 
-    let expanded = quote! {
+    Ok(quote! {
         impl matrixagon_util::VulkanVertexState<{#field_count}> for #name {
             const BINDING_DESCRIPTION: ash::vk::VertexInputBindingDescription = ash::vk::VertexInputBindingDescription {
-                binding: 0,
+                binding: #binding,
                 stride: std::mem::size_of::<#name>() as u32,
-                input_rate: ash::vk::VertexInputRate::VERTEX,
+                input_rate: #input_rate,
             };
 
             const ATTRIBUTE_DESCRIPTION: [ash::vk::VertexInputAttributeDescription; #field_count] = unsafe {
@@ -107,18 +225,11 @@ pub fn derive_vertex(input: TokenStream) -> TokenStream {
                 // cast to u8 pointers so we get offset in bytes
                 let b_u8_ptr = b_ptr as *const u8;
 
-                let mut locations = [#(#vertex_attribute_locations),*];
-
-                let count = #field_count;
-                let mut i = 0usize;
-                while i < count {
-                    locations[i].location = i as u32;
-                    i += 1;
-                }
-
-                locations
+                [#(#vertex_attribute_locations),*]
             };
 
+            const GLSL_INPUT: &'static str = #glsl_input;
+
             const VERTEX_INPUT_STATE: ash::vk::PipelineVertexInputStateCreateInfo = ash::vk::PipelineVertexInputStateCreateInfo {
                 s_type: ash::vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
                 p_next: std::ptr::null(),
@@ -129,8 +240,16 @@ pub fn derive_vertex(input: TokenStream) -> TokenStream {
                 p_vertex_attribute_descriptions: &Self::ATTRIBUTE_DESCRIPTION as *const ash::vk::VertexInputAttributeDescription,
             };
         }
-    };
-
-    TokenStream::from(expanded)
+    })
 }
 
+
+// std140 unless the struct is marked #[uniform(std430)], see UniformBlock
+#[proc_macro_derive(UniformBlock, attributes(uniform))]
+pub fn derive_uniform_block(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    uniform::derive_uniform_block(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
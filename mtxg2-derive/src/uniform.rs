@@ -0,0 +1,141 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, Error, Expr, ExprLit, Lit, Type, TypeArray, TypePath};
+
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Layout {
+    Std140,
+    Std430,
+}
+
+// the glsl members a field can map to, matrices are laid out like arrays of their column vectors
+#[derive(Copy, Clone, Debug)]
+enum Member {
+    Vector(usize),  // a scalar when 1
+    Array(usize, usize),  // vector components, length
+}
+
+impl Member {
+    // f32, i32 and u32 are the only 4 byte scalars that share their representation with glsl
+    fn parse(ty: &Type) -> Result<Self, Error> {
+        let is_scalar = |ty: &Type| match ty {
+            Type::Path(TypePath {path, ..}) => path.get_ident()
+                .is_some_and(|ident| ["f32", "i32", "u32"].contains(&ident.to_string().as_str())),
+            _ => false,
+        };
+        let array_len = |len: &Expr| match len {
+            Expr::Lit(ExprLit {lit: Lit::Int(len), ..}) => len.base10_parse::<usize>().ok(),
+            _ => None,
+        };
+        let unsupported = || Error::new_spanned(ty, "UniformBlock Derive: Only f32, i32 and u32 scalars, vectors, matrices and arrays of them are supported");
+
+        match ty {
+            ty if is_scalar(ty) => Ok(Self::Vector(1)),
+            Type::Array(TypeArray {elem, len, ..}) => {
+                let len = array_len(len).ok_or_else(unsupported)?;
+                match Self::parse(elem)? {
+                    // [T; 2..=4] is a vector, any other length is an array of scalars
+                    Self::Vector(1) if (2..=4).contains(&len) => Ok(Self::Vector(len)),
+                    Self::Vector(components) => Ok(Self::Array(components, len)),
+                    Self::Array(..) => Err(unsupported()),
+                }
+            }
+            _ => Err(unsupported()),
+        }
+    }
+
+    fn vector_align(components: usize) -> usize {
+        match components {
+            1 => 4,
+            2 => 8,
+            _ => 16,
+        }
+    }
+
+    // alignment, size and array stride in bytes
+    fn layout(&self, layout: Layout) -> (usize, usize, usize) {
+        match *self {
+            Self::Vector(components) => (Self::vector_align(components), 4*components, 0),
+            Self::Array(components, len) => {
+                let mut align = Self::vector_align(components);
+                if layout == Layout::Std140 {
+                    align = align.max(16);
+                }
+                let stride = round_up(4*components, align);
+                (align, stride*len, stride)
+            }
+        }
+    }
+}
+
+fn round_up(value: usize, align: usize) -> usize {
+    value.div_ceil(align)*align
+}
+
+
+pub(crate) fn derive_uniform_block(input: DeriveInput) -> Result<TokenStream, Error> {
+    let name = &input.ident;
+
+    let mut layout = Layout::Std140;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("uniform")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("std140") {
+                layout = Layout::Std140;
+            } else if meta.path.is_ident("std430") {
+                layout = Layout::Std430;
+            } else {
+                return Err(meta.error("UniformBlock Derive: Expected std140 or std430"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let fields = match &input.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new_spanned(name, "UniformBlock Derive: Only structs can be uniform blocks")),
+    };
+
+    let mut offset = 0;
+    let mut block_align = if layout == Layout::Std140 {16} else {4};
+    let mut writes = Vec::new();
+    let mut offsets = Vec::new();
+    for field in fields {
+        let field_name = field.ident.as_ref()
+            .ok_or_else(|| Error::new_spanned(field, "UniformBlock Derive: Fields have to be named"))?;
+        let member = Member::parse(&field.ty)?;
+        let (align, size, stride) = member.layout(layout);
+
+        offset = round_up(offset, align);
+        block_align = block_align.max(align);
+
+        writes.push(match member {
+            // the accepted member types are made of 4 byte components without padding
+            Member::Vector(_) => quote! {
+                unsafe { matrixagon_util::write_uniform_member(block, #offset, &self.#field_name); }
+            },
+            Member::Array(..) => quote! {
+                for (i, element) in self.#field_name.iter().enumerate() {
+                    unsafe { matrixagon_util::write_uniform_member(block, #offset+i*#stride, element); }
+                }
+            },
+        });
+        let field_str = field_name.to_string();
+        offsets.push(quote! {(#field_str, #offset)});
+
+        offset += size;
+    }
+    let size = round_up(offset, block_align);
+
+    Ok(quote! {
+        impl matrixagon_util::UniformBlock for #name {
+            const SIZE: usize = #size;
+            const OFFSETS: &'static [(&'static str, usize)] = &[#(#offsets),*];
+
+            fn write_block(&self, block: &mut [u8]) {
+                #(#writes)*
+            }
+        }
+    })
+}
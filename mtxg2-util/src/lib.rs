@@ -1,20 +1,31 @@
 use std::rc::Rc;
-use ash::{Device, vk};
-pub use matrixagon_derive::Vertex;
+use ash::vk;
+pub use matrixagon_derive::{UniformBlock, Vertex};
 
 
 
-pub struct IndexedBuffer {
-    device: Rc<Device>,
+// hands a replaced buffer and its backing memory back to whoever allocated it, the releaser is
+// responsible for not freeing it while the GPU may still read from it
+pub trait BufferRelease {
+    type Memory: Copy;
 
-    vbo: Option<([vk::Buffer; 1], vk::DeviceMemory)>,
-    ibo: Option<(vk::Buffer, vk::DeviceMemory, u32)>,
+    /// # Safety
+    /// The buffer may still be in use by the GPU when it is handed over, so the releaser must not free it before
+    /// the fence of the last submission using it has signalled. The buffer is not used by the caller afterwards.
+    unsafe fn release_buffer(&self, buf: vk::Buffer, mem: Self::Memory);
 }
 
-impl IndexedBuffer {
-    pub fn new(device: Rc<Device>) -> Self {
+pub struct IndexedBuffer<R: BufferRelease> {
+    releaser: Rc<R>,
+
+    vbo: Option<([vk::Buffer; 1], R::Memory)>,
+    ibo: Option<(vk::Buffer, R::Memory, u32)>,
+}
+
+impl<R: BufferRelease> IndexedBuffer<R> {
+    pub fn new(releaser: Rc<R>) -> Self {
         Self {
-            device, vbo: None, ibo: None
+            releaser, vbo: None, ibo: None
         }
     }
 
@@ -26,32 +37,26 @@ impl IndexedBuffer {
         }
     }
 
-    pub unsafe fn recreate_vbo(&mut self, buf: [vk::Buffer; 1], mem: vk::DeviceMemory) {
+    pub unsafe fn recreate_vbo(&mut self, buf: [vk::Buffer; 1], mem: R::Memory) {
         if let Some((old_buf, old_mem)) = self.vbo {
-            self.device.device_wait_idle().unwrap();
-            self.device.destroy_buffer(old_buf[0], None);
-            self.device.free_memory(old_mem, None);
+            self.releaser.release_buffer(old_buf[0], old_mem);
         }
         self.vbo = Some((buf, mem));
     }
 
-    pub unsafe fn recreate_ibo(&mut self, buf: vk::Buffer, mem: vk::DeviceMemory, len: u32) {
+    pub unsafe fn recreate_ibo(&mut self, buf: vk::Buffer, mem: R::Memory, len: u32) {
         if let Some((old_buf, old_mem, _)) = self.ibo {
-            self.device.device_wait_idle().unwrap();
-            self.device.destroy_buffer(old_buf, None);
-            self.device.free_memory(old_mem, None);
+            self.releaser.release_buffer(old_buf, old_mem);
         }
         self.ibo = Some((buf, mem, len));
     }
 
     pub unsafe fn destroy(&self) {
         if let Some((old_buf, old_mem)) = self.vbo {
-            self.device.destroy_buffer(old_buf[0], None);
-            self.device.free_memory(old_mem, None);
+            self.releaser.release_buffer(old_buf[0], old_mem);
         }
         if let Some((old_buf, old_mem, _)) = self.ibo {
-            self.device.destroy_buffer(old_buf, None);
-            self.device.free_memory(old_mem, None);
+            self.releaser.release_buffer(old_buf, old_mem);
         }
     }
 }
@@ -61,151 +66,34 @@ pub trait VulkanVertexState<const A: usize> {
     const BINDING_DESCRIPTION: vk::VertexInputBindingDescription;
     const ATTRIBUTE_DESCRIPTION: [vk::VertexInputAttributeDescription; A];
     const VERTEX_INPUT_STATE: vk::PipelineVertexInputStateCreateInfo;
+    // the matching `layout(location = N) in ...` declarations for the vertex shader
+    const GLSL_INPUT: &'static str;
 }
 
 
-#[macro_export]
-macro_rules! create_renderpass {
-    {
-        [$device:expr];
-        Attachments {$(
-            $attachment_names:ident: {
-                format: $format:expr,
-                samples: $samples:ident,
-                load: $load:ident,
-                store: $store:ident,
-                stencil_load: $stencil_load:ident,
-                stencil_store: $stencil_store:ident,
-                initial: $initial:ident,
-                final: $final:ident,
-            }
-        )*}
-        Subpasses {$(
-            $subpass_names:ident: {
-                input: $($input_attachment_ref:ident~$input_attachment_ref_layout:ident)*,
-                color: $($color_attachment_ref:ident~$color_attachment_ref_layout:ident)*,
-                resolve: $($resolve_attachment_ref:ident~$resolve_attachment_ref_layout:ident)*,
-                preserve: $($preserve_attachment_ref:ident)*,
-                depth: $($depth_attachment_ref:ident~$depth_attachment_ref_layout:ident)?,
-            }
-        )*}
-        Dependencies {$(
-            $($subpass_in:ident)?->$($subpass_out:ident)?: {
-                src_stage: $($src_stage_flags:ident)|*,
-                dst_stage:  $($dst_stage_flags:ident)|*,
-                src_access: $($src_access_flags:ident)|*,
-                dst_access: $($dst_access_flags:ident)|*,
-            }
-        )*}
-    } => {{
-        let mut __attachment_count = 0;
-        $(
-            let $attachment_names = (
-                __attachment_count,
-                ash::vk::AttachmentDescription {
-                    format: $format,
-                    samples: ash::vk::SampleCountFlags::$samples,
-                    load_op: ash::vk::AttachmentLoadOp::$load, store_op: ash::vk::AttachmentStoreOp::$store,
-                    stencil_load_op: ash::vk::AttachmentLoadOp::$stencil_load, stencil_store_op: ash::vk::AttachmentStoreOp::$stencil_store,
-                    initial_layout: ash::vk::ImageLayout::$initial,
-                    final_layout: ash::vk::ImageLayout::$final,
-                    ..Default::default()
-                }
-            );
-            __attachment_count += 1;
-        )*
-
-        let mut __subpass_count = 0;
-        $(
-            let mut __input = create_renderpass!(@ATTACHMENT_REFS $($input_attachment_ref~$input_attachment_ref_layout)*);
-            let mut __color = create_renderpass!(@ATTACHMENT_REFS $($color_attachment_ref~$color_attachment_ref_layout)*);
-            let mut __resolve = create_renderpass!(@ATTACHMENT_REFS $($resolve_attachment_ref~$resolve_attachment_ref_layout)*);
-            let mut __preserve = create_renderpass!(@PRESERVE_ATTACHMENTS $($preserve_attachment_ref)*);
-            $(
-            let __depth = create_renderpass!(@SINGLE_ATTACHMENT_REF $depth_attachment_ref~$depth_attachment_ref_layout);
-            )?
-            let $subpass_names = (
-                __subpass_count,
-                {
-                    let mut subpass_builder = ash::vk::SubpassDescription::builder()
-                        .pipeline_bind_point(ash::vk::PipelineBindPoint::GRAPHICS);
-                    if __input.len() > 0 {
-                        subpass_builder = subpass_builder.input_attachments(&__input)
-                    }
-                    if __color.len() > 0 {
-                        subpass_builder = subpass_builder.color_attachments(&__color)
-                    }
-                    if __resolve.len() > 0 {
-                        subpass_builder = subpass_builder.resolve_attachments(&__resolve)
-                    }
-                    if __preserve.len() > 0 {
-                        subpass_builder = subpass_builder.preserve_attachments(&__preserve)
-                    }
-                    subpass_builder
-                    $(
-                    .depth_stencil_attachment({$depth_attachment_ref; &__depth})
-                    )?
-                    .build()
-                }
-
-            );
-
-            __subpass_count += 1;
-        )*
-
-        let __dependencies = vec![$(
-        {
-            let mut __src_subpass = ash::vk::SUBPASS_EXTERNAL;
-            let mut __dst_subpass = ash::vk::SUBPASS_EXTERNAL;
-            $(
-            __src_subpass = $subpass_in.0;  // subpass_in should refer to a subpass variable
-            )?
-            $(
-            __dst_subpass = $subpass_out.0;  // subpass_out should refer to a subpass variable
-            )?
-
-            vk::SubpassDependency {
-                src_subpass: __src_subpass, dst_subpass: __dst_subpass,
-                src_stage_mask: create_renderpass!(@STAGE_FLAGS $($src_stage_flags)|*),
-                dst_stage_mask: create_renderpass!(@STAGE_FLAGS $($dst_stage_flags)|*),
-                src_access_mask: create_renderpass!(@ACCESS_FLAGS $($src_access_flags)|*),
-                dst_access_mask: create_renderpass!(@ACCESS_FLAGS $($dst_access_flags)|*),
-                ..Default::default()
-            }
-        },
-        )*];
-
-        let __attachments = vec![$($attachment_names.1),*];
-        let __subpasses = vec![$($subpass_names.1),*];
-
-        let __renderpass_info = ash::vk::RenderPassCreateInfo::builder()
-            .attachments(&__attachments)
-            .subpasses(&__subpasses)
-            .dependencies(&__dependencies)
-            .build();
-        $device.create_render_pass(&__renderpass_info, None).unwrap()
-    }};
-    (@ATTACHMENT_REFS $($attachment_ref:ident~$attachment_ref_layout:ident)*) => {{
-        [$(
-           ash::vk::AttachmentReference {
-               attachment: $attachment_ref.0,
-               layout: ash::vk::ImageLayout::$attachment_ref_layout
-           }
-        ),*]
-    }};
-    (@SINGLE_ATTACHMENT_REF $attachment_ref:ident~$attachment_ref_layout:ident) => {{
-       ash::vk::AttachmentReference {
-           attachment: $attachment_ref.0,
-           layout: ash::vk::ImageLayout::$attachment_ref_layout
-       }
-    }};
-    (@PRESERVE_ATTACHMENTS $($attachment_ref:ident)*) => {{
-        [$($attachment_ref.0),*]
-    }};
-    (@STAGE_FLAGS $($stage_flags:ident)|*) => {{
-        ash::vk::PipelineStageFlags::empty() $(| ash::vk::PipelineStageFlags::$stage_flags)*
-    }};
-    (@ACCESS_FLAGS $($access_flags:ident)|*) => {{
-        ash::vk::AccessFlags::empty() $(| ash::vk::AccessFlags::$access_flags)*
-    }};
+// a struct that is written into a uniform (or storage) buffer with the std140 or std430 padding
+// rules, instead of its rust layout
+pub trait UniformBlock {
+    const SIZE: usize;  // in bytes, a multiple of the block alignment
+    const OFFSETS: &'static [(&'static str, usize)];  // field names and their byte offsets
+
+    fn write_block(&self, block: &mut [u8]);
+
+    fn descriptor_buffer_info(buffer: vk::Buffer) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer,
+            offset: 0,
+            range: Self::SIZE as vk::DeviceSize,
+        }
+    }
+}
+
+// only for the code generated by the UniformBlock derive
+/// # Safety
+/// `T` must not contain padding bytes, as every byte of it is read. The derive only calls it for the scalars,
+/// vectors and matrices it accepts, which are made of 4 byte components.
+#[doc(hidden)]
+pub unsafe fn write_uniform_member<T: Copy>(block: &mut [u8], offset: usize, member: &T) {
+    let bytes = std::slice::from_raw_parts(member as *const T as *const u8, std::mem::size_of::<T>());
+    block[offset..offset+bytes.len()].copy_from_slice(bytes);
 }
@@ -0,0 +1,52 @@
+use matrixagon_util::UniformBlock;
+
+
+#[derive(UniformBlock)]
+struct Std140Block {
+    scale: f32,
+    offset: [f32; 3],
+    tint: [f32; 3],
+    layer: u32,
+    weights: [f32; 3],
+    rotation: [[f32; 2]; 2],
+    model: [[f32; 4]; 4],
+}
+
+#[derive(UniformBlock)]
+#[uniform(std430)]
+struct Std430Block {
+    scale: f32,
+    weights: [f32; 3],
+    rotation: [[f32; 2]; 2],
+    counts: [i32; 6],
+}
+
+#[test]
+fn std140_offsets() {
+    assert_eq!(Std140Block::OFFSETS, &[
+        ("scale", 0), ("offset", 16), ("tint", 32), ("layer", 44), ("weights", 48), ("rotation", 64), ("model", 96),
+    ]);
+    assert_eq!(Std140Block::SIZE, 160);
+
+    let block = Std140Block {
+        scale: 2.0, offset: [1.0; 3], tint: [0.5; 3], layer: 7, weights: [3.0; 3],
+        rotation: [[1.0, 2.0], [3.0, 4.0]], model: [[0.0; 4]; 4],
+    };
+    let mut bytes = vec![0xFFu8; Std140Block::SIZE];
+    block.write_block(&mut bytes);
+
+    let read = |offset: usize| f32::from_ne_bytes(bytes[offset..offset+4].try_into().unwrap());
+    assert_eq!(read(0), 2.0);
+    assert_eq!(u32::from_ne_bytes(bytes[44..48].try_into().unwrap()), 7);
+    // matrix columns are padded to a vec4 each
+    assert_eq!([read(64), read(68), read(80), read(84)], [1.0, 2.0, 3.0, 4.0]);
+}
+
+#[test]
+fn std430_offsets() {
+    assert_eq!(Std430Block::OFFSETS, &[("scale", 0), ("weights", 16), ("rotation", 32), ("counts", 48)]);
+    assert_eq!(Std430Block::SIZE, 80);
+
+    let buffer_info = Std430Block::descriptor_buffer_info(ash::vk::Buffer::null());
+    assert_eq!(buffer_info.range, 80);
+}
@@ -0,0 +1,73 @@
+use ash::vk;
+use matrixagon_util::{Vertex, VulkanVertexState};
+
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Vertex)]
+struct MixedVertex {
+    pos: [f32; 3],
+    #[vertex(unorm)]
+    uv: [u16; 2],
+    #[vertex(snorm)]
+    normal: [i16; 4],
+    #[vertex(location = 5)]
+    layer: u32,
+    color: [u8; 4],
+    #[vertex(sfloat)]
+    height: u16,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Vertex)]
+#[vertex(instance, binding = 1, location = 8)]
+struct InstanceData {
+    offset: [i32; 3],
+    #[vertex(uint)]
+    flags: u8,
+}
+
+#[test]
+fn vertex_attributes() {
+    let formats = MixedVertex::ATTRIBUTE_DESCRIPTION.map(|attr| (attr.location, attr.format, attr.offset));
+    assert_eq!(formats, [
+        (0, vk::Format::R32G32B32_SFLOAT, 0),
+        (1, vk::Format::R16G16_UNORM, 12),
+        (2, vk::Format::R16G16B16A16_SNORM, 16),
+        (5, vk::Format::R32_UINT, 24),
+        (6, vk::Format::R8G8B8A8_UNORM, 28),
+        (7, vk::Format::R16_SFLOAT, 32),
+    ]);
+    assert_eq!(MixedVertex::BINDING_DESCRIPTION.binding, 0);
+    assert_eq!(MixedVertex::BINDING_DESCRIPTION.input_rate, vk::VertexInputRate::VERTEX);
+    assert_eq!(MixedVertex::BINDING_DESCRIPTION.stride, 36);
+
+    assert_eq!(MixedVertex::GLSL_INPUT, "\
+// generated by #[derive(Vertex)] from MixedVertex, do not edit
+layout(location = 0) in vec3 pos;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 normal;
+layout(location = 5) in uint layer;
+layout(location = 6) in vec4 color;
+layout(location = 7) in float height;
+");
+}
+
+#[test]
+fn instance_attributes() {
+    let formats = InstanceData::ATTRIBUTE_DESCRIPTION.map(|attr| (attr.binding, attr.location, attr.format));
+    assert_eq!(formats, [
+        (1, 8, vk::Format::R32G32B32_SINT),
+        (1, 9, vk::Format::R8_UINT),
+    ]);
+    assert_eq!(InstanceData::BINDING_DESCRIPTION.binding, 1);
+    assert_eq!(InstanceData::BINDING_DESCRIPTION.input_rate, vk::VertexInputRate::INSTANCE);
+    assert!(InstanceData::GLSL_INPUT.ends_with("layout(location = 8) in ivec3 offset;\nlayout(location = 9) in uint flags;\n"));
+}
+
+#[test]
+fn vertex_derive_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/vertex_fail/*.rs");
+}
@@ -0,0 +1,12 @@
+use matrixagon_util::Vertex;
+
+#[repr(C)]
+#[derive(Vertex)]
+struct DuplicateLocation {
+    pos: [f32; 3],
+    uv: [f32; 2],
+    #[vertex(location = 1)]
+    layer: u32,
+}
+
+fn main() {}
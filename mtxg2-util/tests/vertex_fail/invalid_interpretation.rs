@@ -0,0 +1,10 @@
+use matrixagon_util::Vertex;
+
+#[repr(C)]
+#[derive(Vertex)]
+struct InvalidInterpretation {
+    #[vertex(snorm)]
+    pos: [f32; 3],
+}
+
+fn main() {}
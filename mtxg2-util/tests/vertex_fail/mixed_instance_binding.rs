@@ -0,0 +1,11 @@
+use matrixagon_util::Vertex;
+
+#[repr(C)]
+#[derive(Vertex)]
+struct MixedInstanceBinding {
+    pos: [f32; 3],
+    #[vertex(instance, binding = 1)]
+    offset: [i32; 3],
+}
+
+fn main() {}
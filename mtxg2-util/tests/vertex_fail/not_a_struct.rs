@@ -0,0 +1,8 @@
+use matrixagon_util::Vertex;
+
+#[derive(Vertex)]
+enum NotAStruct {
+    Pos([f32; 3]),
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use matrixagon_util::Vertex;
+
+#[repr(C)]
+#[derive(Vertex)]
+struct UnknownAttribute {
+    #[vertex(normalized)]
+    uv: [u16; 2],
+}
+
+fn main() {}
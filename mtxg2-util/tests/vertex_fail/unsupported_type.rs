@@ -0,0 +1,9 @@
+use matrixagon_util::Vertex;
+
+#[repr(C)]
+#[derive(Vertex)]
+struct UnsupportedType {
+    pos: [f64; 3],
+}
+
+fn main() {}
@@ -0,0 +1,236 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+use matrixagon2::{AppConfig, WindowMode};
+
+
+#[derive(Debug)]
+pub enum Command {
+    Run(AppConfig),
+    DryRun(AppConfig),  // prints the resolved config without opening a window
+    Help,
+}
+
+pub fn usage() -> String {
+    String::from("\
+Usage: mtxg2gp [OPTIONS]
+
+Options:
+  --validation <on|off>         Vulkan validation layers [default: on]
+  --debug <CATEGORIES>          Comma separated debug output: vk-setup, vk-swapchain, mtxg, mtxg-render,
+                                all or none [default: vk-setup,mtxg]
+  --fullscreen [MODE]           windowed, borderless or exclusive [default: windowed, borderless if no MODE]
+  --monitor <INDEX>             Monitor used for fullscreen [default: primary]
+  --resolution <WxH>            Window size, or the video mode for exclusive fullscreen
+  --mouse-lock <on|off>         Lock the cursor to the window center [default: on]
+  --seed <SEED>                 World generation seed [default: 50]
+  --render-distance <CHUNKS>    Render distance in chunks [default: from the settings file]
+  --chunk-cache <MIB>           Memory budget for the meshes of recently unloaded chunks [default: 256]
+  --settings <PATH>             Settings file, saved from the in-game settings menu [default: settings.cfg]
+  --asset-root <PATH>           Directory containing block_textures/ [default: the crate's src/resource]
+  --pipeline-cache <PATH>       File the compiled pipelines are cached in [default: pipeline_cache.bin]
+  --shader-hot-reload <on|off>  Recompile and reload the shaders when their sources change, needs glslc [default: off]
+  --screenshot-dir <PATH>       Directory F2 saves screenshots to [default: screenshots]
+  --dry-run                     Print the resolved config and exit
+  -h, --help                    Print this help and exit
+")
+}
+
+pub fn parse_args<I: IntoIterator<Item=String>>(args: I) -> Result<Command, String> {
+    let mut config = AppConfig::default();
+    let mut dry_run = false;
+
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        // both "--flag value" and "--flag=value" are accepted
+        let (flag, mut inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = |name: &str| -> Result<String, String> {
+            inline.take().or_else(|| args.next()).ok_or(format!("Missing value for {name}"))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--dry-run" => dry_run = true,
+            "--validation" => config.validate = parse_switch(&flag, &value(&flag)?)?,
+            "--debug" => {
+                let dbgv = &mut config.debug_visibility;
+                dbgv.vk_setup_output = false;
+                dbgv.vk_swapchain_output = false;
+                dbgv.mtxg_output = false;
+                dbgv.mtxg_render_output = false;
+
+                for category in value(&flag)?.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                    match category {
+                        "vk-setup" => dbgv.vk_setup_output = true,
+                        "vk-swapchain" => dbgv.vk_swapchain_output = true,
+                        "mtxg" => dbgv.mtxg_output = true,
+                        "mtxg-render" => dbgv.mtxg_render_output = true,
+                        "all" => {
+                            dbgv.vk_setup_output = true;
+                            dbgv.vk_swapchain_output = true;
+                            dbgv.mtxg_output = true;
+                            dbgv.mtxg_render_output = true;
+                        }
+                        "none" => {}
+                        _ => return Err(format!("Unknown debug category {category:?}")),
+                    }
+                }
+            }
+            "--fullscreen" => {
+                // the mode is optional, so only consume the next argument if it isn't another flag
+                let mode = match inline.take() {
+                    Some(mode) => Some(mode),
+                    None => args.next_if(|next| !next.starts_with('-')),
+                };
+                config.display.mode = match mode.as_deref() {
+                    None | Some("borderless") => WindowMode::Borderless,
+                    Some("windowed") => WindowMode::Windowed,
+                    Some("exclusive") => WindowMode::Exclusive,
+                    Some(mode) => return Err(format!("Unknown fullscreen mode {mode:?}")),
+                };
+            }
+            "--monitor" => config.display.monitor = Some(parse_num(&flag, &value(&flag)?)?),
+            "--resolution" => {
+                let res = value(&flag)?;
+                let (w, h) = res.split_once('x')
+                    .ok_or(format!("Expected WxH for {flag}, got {res:?}"))?;
+                let size = (parse_num(&flag, w)?, parse_num(&flag, h)?);
+                if size.0 == 0 || size.1 == 0 {
+                    return Err(format!("{flag} must not be zero sized"));
+                }
+                config.display.windowed_size = size;
+                config.display.resolution = Some(size);
+            }
+            "--mouse-lock" => config.mouse_lock = parse_switch(&flag, &value(&flag)?)?,
+            "--seed" => config.seed = parse_num(&flag, &value(&flag)?)?,
+            "--render-distance" => {
+                let render_distance = parse_num(&flag, &value(&flag)?)?;
+                if render_distance == 0 {
+                    return Err(format!("{flag} must be at least 1"));
+                }
+                config.render_distance = Some(render_distance);
+            }
+            "--chunk-cache" => config.chunk_cache_mib = parse_num(&flag, &value(&flag)?)?,
+            "--settings" => config.settings_path = PathBuf::from(value(&flag)?),
+            "--asset-root" => config.asset_root = PathBuf::from(value(&flag)?),
+            "--pipeline-cache" => config.pipeline_cache_path = PathBuf::from(value(&flag)?),
+            "--shader-hot-reload" => config.shader_hot_reload = parse_switch(&flag, &value(&flag)?)?,
+            "--screenshot-dir" => config.screenshot_dir = PathBuf::from(value(&flag)?),
+            _ => return Err(format!("Unknown argument {flag:?}")),
+        }
+
+        if inline.is_some() {
+            return Err(format!("{flag} does not take a value"));
+        }
+    }
+
+    Ok(if dry_run { Command::DryRun(config) } else { Command::Run(config) })
+}
+
+pub fn dump_config(config: &AppConfig) -> String {
+    let dbgv = config.debug_visibility;
+    let mut out = String::new();
+    writeln!(out, "validation = {}", config.validate).unwrap();
+    writeln!(out, "debug.vk_setup = {}", dbgv.vk_setup_output).unwrap();
+    writeln!(out, "debug.vk_swapchain = {}", dbgv.vk_swapchain_output).unwrap();
+    writeln!(out, "debug.mtxg = {}", dbgv.mtxg_output).unwrap();
+    writeln!(out, "debug.mtxg_render = {}", dbgv.mtxg_render_output).unwrap();
+    writeln!(out, "display.mode = {:?}", config.display.mode).unwrap();
+    writeln!(out, "display.monitor = {:?}", config.display.monitor).unwrap();
+    writeln!(out, "display.windowed_size = {:?}", config.display.windowed_size).unwrap();
+    writeln!(out, "display.resolution = {:?}", config.display.resolution).unwrap();
+    writeln!(out, "mouse_lock = {}", config.mouse_lock).unwrap();
+    writeln!(out, "frames_in_flight = {}", config.frames_in_flight).unwrap();
+    writeln!(out, "present_setting = {:?}", config.present_setting).unwrap();
+    writeln!(out, "seed = {}", config.seed).unwrap();
+    writeln!(out, "render_distance = {:?}", config.render_distance).unwrap();
+    writeln!(out, "lod_rings = {:?}", config.lod_rings).unwrap();
+    writeln!(out, "chunk_cache_mib = {}", config.chunk_cache_mib).unwrap();
+    writeln!(out, "settings = {}", config.settings_path.display()).unwrap();
+    writeln!(out, "asset_root = {}", config.asset_root.display()).unwrap();
+    writeln!(out, "pipeline_cache = {}", config.pipeline_cache_path.display()).unwrap();
+    writeln!(out, "shader_hot_reload = {}", config.shader_hot_reload).unwrap();
+    writeln!(out, "screenshot_dir = {}", config.screenshot_dir.display()).unwrap();
+    out
+}
+
+fn parse_switch(flag: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" | "1" => Ok(true),
+        "off" | "false" | "0" => Ok(false),
+        _ => Err(format!("Expected on or off for {flag}, got {value:?}")),
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid number for {flag}: {value:?}"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    fn parse_config(args: &[&str]) -> AppConfig {
+        match parse(args).unwrap() {
+            Command::Run(config) | Command::DryRun(config) => config,
+            Command::Help => panic!("Unexpected help"),
+        }
+    }
+
+    #[test]
+    fn help_and_dry_run() {
+        assert!(matches!(parse(&["--dry-run", "--help"]), Ok(Command::Help)));
+        assert!(usage().contains("--render-distance"));
+
+        let Ok(Command::DryRun(config)) = parse(&["--dry-run", "--seed=7"]) else { panic!("Expected a dry run") };
+        let dump = dump_config(&config);
+        assert!(dump.contains("seed = 7\n"));
+        assert!(dump.contains("validation = true\n"));
+        assert!(dump.contains("display.mode = Windowed\n"));
+    }
+
+    #[test]
+    fn all_options() {
+        let config = parse_config(&[
+            "--validation", "off", "--debug", "vk-swapchain,mtxg-render", "--fullscreen", "--monitor", "1",
+            "--resolution", "1920x1080", "--mouse-lock=off", "--seed", "1234",
+            "--render-distance", "8", "--chunk-cache", "64", "--asset-root", "/assets", "--settings", "a.cfg",
+            "--pipeline-cache", "cache/pc.bin", "--shader-hot-reload", "on", "--screenshot-dir", "shots",
+        ]);
+        assert!(!config.validate);
+        assert!(!config.debug_visibility.vk_setup_output && config.debug_visibility.vk_swapchain_output);
+        assert!(!config.debug_visibility.mtxg_output && config.debug_visibility.mtxg_render_output);
+        assert_eq!(config.display.mode, WindowMode::Borderless);
+        assert_eq!(config.display.monitor, Some(1));
+        assert_eq!(config.display.resolution, Some((1920, 1080)));
+        assert!(!config.mouse_lock);
+        assert_eq!(config.seed, 1234);
+        assert_eq!(config.render_distance, Some(8));
+        assert_eq!(config.chunk_cache_mib, 64);
+        assert_eq!(config.settings_path, PathBuf::from("a.cfg"));
+        assert_eq!(config.asset_root, PathBuf::from("/assets"));
+        assert_eq!(config.pipeline_cache_path, PathBuf::from("cache/pc.bin"));
+        assert!(config.shader_hot_reload);
+        assert_eq!(config.screenshot_dir, PathBuf::from("shots"));
+
+        assert_eq!(parse_config(&["--fullscreen", "exclusive"]).display.mode, WindowMode::Exclusive);
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(parse(&["--seed"]).is_err());
+        assert!(parse(&["--seed", "abc"]).is_err());
+        assert!(parse(&["--render-distance", "0"]).is_err());
+        assert!(parse(&["--resolution", "1920"]).is_err());
+        assert!(parse(&["--debug", "verbose"]).is_err());
+        assert!(parse(&["--dry-run=yes"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+    }
+}
@@ -1,17 +1,31 @@
 #[macro_use]
 extern crate matrixagon2;
 
-use matrixagon2::debug::DebugVisibility;
+mod cli;
+
+use std::process::ExitCode;
 use matrixagon2::MatrixagonApp;
+use crate::cli::Command;
 
-fn main() {
-    let debug_visibility = DebugVisibility {
-        vk_setup_output: true,
-        vk_swapchain_output: false,
-        mtxg_output: true,
-        mtxg_render_output: false,
+fn main() -> ExitCode {
+    let config = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::DryRun(config)) => {
+            print!("{}", cli::dump_config(&config));
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Help) => {
+            print!("{}", cli::usage());
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::usage());
+            return ExitCode::FAILURE;
+        }
     };
-    let mtxg = MatrixagonApp::init(true, debug_visibility, false, true);
+
+    let mtxg = MatrixagonApp::init(config);
     // mtxg.load_shader(StandardRasterizer::new());
     mtxg.run();
+    ExitCode::SUCCESS
 }
@@ -1,17 +1,19 @@
 use std::rc::Rc;
 use ash::{Device, vk};
+use matrixagon_util::UniformBlock;
 use winit::event::VirtualKeyCode;
 use crate::component::{Component, RenderData, RenderDataPurpose};
-use crate::handler::VulkanInstance;
+use crate::memory::{Allocation, MemoryAllocator};
 use crate::util::{CmdBufContext, Mat4, matrix_prod};
 use crate::world::{CardinalDir, WorldEvent};
-use std::{ffi, mem};
+use std::ffi;
 use std::ops::{Add, Sub};
 use uom::si;
 use uom::num_traits::Zero;
 use uom::si::f32::{Angle, Length};
 use crate::measurement::blox;
-use crate::util::{create_host_buffer, matrix_ident, update_buffer};
+use crate::settings::Settings;
+use crate::util::{create_uniform_buffer, matrix_ident, update_uniform_buffer};
 
 
 #[derive(Copy, Clone, Debug)]
@@ -64,6 +66,56 @@ impl Sub for Length3D {
 }
 
 
+// integer precise world position, the chux chunk plus the offset in blocks within it, so that the
+// precision does not degrade with the distance from the world origin like it does for Length3D
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WorldPos {
+    pub(crate) chunk: [i64; 3],
+    pub(crate) local: [f32; 3],  // always within [0, CHUNK_BLOCKS)
+}
+
+impl WorldPos {
+    pub(crate) const CHUNK_BLOCKS: i64 = 32;  // blocks per chux
+
+    pub fn from_blocks(x: f64, y: f64, z: f64) -> Self {
+        let split = |v: f64| {
+            let chunk = v.div_euclid(Self::CHUNK_BLOCKS as f64);
+            (chunk as i64, (v-chunk*Self::CHUNK_BLOCKS as f64) as f32)
+        };
+        let ((cx, lx), (cy, ly), (cz, lz)) = (split(x), split(y), split(z));
+
+        let mut pos = Self {chunk: [cx, cy, cz], local: [lx, ly, lz]};
+        pos.translate([0.0; 3]);  // rounding can land the offset right on the chunk border
+        pos
+    }
+
+    pub fn from_length(pos: Length3D) -> Self {
+        Self::from_blocks(pos.x.get::<blox>() as f64, pos.y.get::<blox>() as f64, pos.z.get::<blox>() as f64)
+    }
+
+    // lossy far away from the origin, only good enough to pick chunks with
+    pub fn to_length(&self) -> Length3D {
+        let axis = |i: usize| Length::new::<blox>((self.chunk[i] as f64*Self::CHUNK_BLOCKS as f64+self.local[i] as f64) as f32);
+        Length3D::new(axis(0), axis(1), axis(2))
+    }
+
+    // in blocks, carries whole chunks over into the chunk coordinate
+    pub(crate) fn translate(&mut self, delta: [f32; 3]) {
+        for ((chunk, local), delta) in self.chunk.iter_mut().zip(&mut self.local).zip(delta) {
+            let moved = *local+delta;
+            let carry = (moved/Self::CHUNK_BLOCKS as f32).floor();
+            *chunk += carry as i64;
+            *local = (moved-carry*Self::CHUNK_BLOCKS as f32).clamp(0.0, (Self::CHUNK_BLOCKS as f32).next_down());
+        }
+    }
+
+    // block coordinate of the chunk corner, which rendering is made relative to
+    pub(crate) fn origin_block(&self) -> [i64; 3] {
+        self.chunk.map(|c| c*Self::CHUNK_BLOCKS)
+    }
+}
+
+
 #[derive(Copy, Clone)]
 pub(crate) struct Rotation {
     x: Angle, y: Angle, z: Angle
@@ -84,9 +136,10 @@ pub(crate) struct CameraComponent {
     // high-level parameters
     trans_speed: f32,
     rot_speed: f32,
+    invert_y: bool,
     delta_trans_speed: f32,
     // camera state
-    t: Length3D,  // translations are in blocks
+    t: WorldPos,  // the view is relative to the corner of the chunk the camera is in
     r: Rotation,
     translations: Vec<VirtualKeyCode>,
     rotated: bool,
@@ -104,20 +157,22 @@ pub(crate) struct CameraComponent {
 }
 
 impl CameraComponent {
-    pub(crate) fn new(vi: Rc<VulkanInstance>, device: Rc<Device>,
-                      aspect_ratio: f32, fov: f32, trans_speed: f32, rot_speed: f32,
-                      init_pos: Length3D
+    pub(crate) fn new(alloc: Rc<MemoryAllocator>, device: Rc<Device>, frames_in_flight: usize,
+                      aspect_ratio: f32, settings: Settings, init_pos: Length3D
     ) -> CameraComponent {
         // let init_rot = (180.0f32).to_radians();
+        let init_pos = WorldPos::from_length(init_pos);
         CameraComponent {
-            descriptor: unsafe { CameraDescriptor::new(vi.clone(), device.clone()) },
-            trans_speed, delta_trans_speed: trans_speed, rot_speed, t: init_pos, r: Rotation::default(),
+            descriptor: unsafe { CameraDescriptor::new(alloc.clone(), device.clone(), frames_in_flight) },
+            trans_speed: settings.move_speed, delta_trans_speed: settings.move_speed,
+            rot_speed: settings.mouse_sensitivity, invert_y: settings.invert_y,
+            t: init_pos, r: Rotation::default(),
             translations: Vec::new(), rotated: false, direction: Self::determine_dir(Angle::zero()),
             rot_x: Self::rot_x_mat(0.0),
             rot_y: Self::rot_y_mat(0.0),
             rot_z: Self::rot_z_mat(0.0),
-            trans: Self::trans_mat(init_pos),
-            far: 100000.0, near: 0.1, aspect_ratio, fov: fov.to_radians(),
+            trans: Self::trans_mat(init_pos.local),
+            far: 100000.0, near: 0.1, aspect_ratio, fov: settings.fov.to_radians(),
         }
     }
 
@@ -142,14 +197,17 @@ impl CameraComponent {
     pub(crate) fn move_forward(&mut self, deg: Angle) {
         // by default, 0 degrees means right
         let angle = deg+Angle::new::<si::angle::degree>(90.0);
-        self.t.x = self.t.x+Length::new::<blox>((self.delta_trans_speed*(angle+self.r.y).cos()).get::<si::ratio::ratio>());
-        self.t.z = self.t.z+Length::new::<blox>((self.delta_trans_speed*(angle+self.r.y).sin()).get::<si::ratio::ratio>());
-        self.trans = Self::trans_mat(self.t);
+        self.t.translate([
+            (self.delta_trans_speed*(angle+self.r.y).cos()).get::<si::ratio::ratio>(),
+            0.0,
+            (self.delta_trans_speed*(angle+self.r.y).sin()).get::<si::ratio::ratio>(),
+        ]);
+        self.trans = Self::trans_mat(self.t.local);
     }
 
     pub(crate) fn move_vertical(&mut self, multiplier: i64) {
-        self.t.y = self.t.y+Length::new::<blox>(multiplier as f32*self.delta_trans_speed);
-        self.trans = Self::trans_mat(self.t);
+        self.t.translate([0.0, multiplier as f32*self.delta_trans_speed, 0.0]);
+        self.trans = Self::trans_mat(self.t.local);
     }
 
     pub(crate) fn determine_dir(angle: Angle) -> CardinalDir {
@@ -191,15 +249,24 @@ impl CameraComponent {
         matrix_prod(matrix_prod(matrix_prod(trans, rot_z), rot_y), rot_x)
     }
 
-    fn trans_mat(t: Length3D) -> Mat4 {
+    fn trans_mat(t: [f32; 3]) -> Mat4 {
         [
             [ 1.0, 0.0, 0.0, 0.0],
             [ 0.0, 1.0, 0.0, 0.0],
             [ 0.0, 0.0, 1.0, 0.0],
-            [-t.x.get::<blox>(),-t.y.get::<blox>(),-t.z.get::<blox>(), 1.0],
+            [-t[0],-t[1],-t[2], 1.0],
         ]
     }
 
+    fn ubo(&self) -> CameraUBO {
+        let [x, y, z] = self.t.origin_block();
+        CameraUBO {
+            view: self.view_mat(),
+            proj: self.proj_mat(),
+            origin: [x as i32, y as i32, z as i32, 0],
+        }
+    }
+
     // rotation matrices are passed in as radians
     fn rot_x_mat(rx: f32) -> Mat4 {
         [
@@ -242,6 +309,7 @@ impl Component for CameraComponent {
                 self.delta_trans_speed = delta.as_secs_f32() * self.trans_speed;
             }
             WorldEvent::MouseMotion((x, y)) => {
+                let y = if self.invert_y { -y } else { y };
                 self.rotate(Rotation {
                     x: Angle::new::<si::angle::degree>(y as f32),
                     y: Angle::new::<si::angle::degree>(x as f32),
@@ -274,6 +342,17 @@ impl Component for CameraComponent {
                     _ => {}
                 }
             }
+            WorldEvent::WindowResized((width, height)) => {
+                self.aspect_ratio = width as f32/height as f32;
+                self.descriptor.update(self.ubo());
+            }
+            WorldEvent::SettingsChanged(settings) => {
+                self.trans_speed = settings.move_speed;
+                self.rot_speed = settings.mouse_sensitivity;
+                self.invert_y = settings.invert_y;
+                self.fov = settings.fov.to_radians();
+                self.descriptor.update(self.ubo());
+            }
             WorldEvent::Start => {
                 dir_changed = true;
                 trans_changed = true;
@@ -286,7 +365,7 @@ impl Component for CameraComponent {
             new_events.push(WorldEvent::UserFaceDir(self.direction));
         }
         if trans_changed {
-            new_events.push(WorldEvent::UserPosition(self.t));
+            new_events.push(WorldEvent::UserPosition(self.t.to_length()));
         }
 
         new_events
@@ -317,82 +396,107 @@ impl Component for CameraComponent {
 
             self.rotated = false;
 
-            let data = CameraUBO {
-                view: self.view_mat(),
-                proj: self.proj_mat(),
-            };
-            self.descriptor.update(data);
+            self.descriptor.update(self.ubo());
         }
     }
 
     unsafe fn load_descriptors(&mut self, _: CmdBufContext) -> Vec<RenderData> {
-        let data = CameraUBO {
-            view: self.view_mat(),
-            proj: self.proj_mat(),
-        };
-        self.descriptor.update(data);
+        self.descriptor.update(self.ubo());
 
         vec![RenderData::InitialDescriptorBuffer(
-            vec![self.descriptor.descriptor_buffer_info()], RenderDataPurpose::CameraViewProjection
+            self.descriptor.descriptor_buffer_infos(), RenderDataPurpose::CameraViewProjection
         )]
     }
 
+    unsafe fn write_uniforms(&mut self, frame: usize) {
+        self.descriptor.write(frame);
+    }
+
     unsafe fn destroy(&mut self) {
         self.descriptor.destroy();
     }
 }
 
 
-// TODO: UNIFORM OBJECTS HAVE TO BE ALIGNED
-#[derive(Copy, Clone)]
-struct CameraUBO {
+#[derive(Copy, Clone, UniformBlock)]
+pub(crate) struct CameraUBO {
     pub(crate) view: [[f32;4];4],
     pub(crate) proj: [[f32;4];4],
+    // view origin in blocks, vertices are placed relative to it through their chunk origin. only the
+    // difference is used in the shader, so the truncation to 32 bits wraps around harmlessly
+    pub(crate) origin: [i32;4],
 }
 
 impl Default for CameraUBO {
     fn default() -> Self {
-        Self { view: matrix_ident(), proj: matrix_ident() }
+        Self { view: matrix_ident(), proj: matrix_ident(), origin: [0; 4] }
     }
 }
 
 
 // CAMERA DESCRIPTOR
  struct CameraDescriptor {
-    device: Rc<Device>,
-    // descriptor fields of uniform buffer
-    ubo_buf: vk::Buffer,
-    ubo_mem: vk::DeviceMemory,
-    ubo_ptr: *mut ffi::c_void,
-    ubo_size: vk::DeviceSize,
+    alloc: Rc<MemoryAllocator>,
+    // latest camera state, only copied into a frame's uniform buffer once that frame is free
+    ubo: CameraUBO,
+    // descriptor fields of uniform buffer (one per frame in flight)
+    ubo_bufs: Vec<(vk::Buffer, Allocation, *mut ffi::c_void)>,
 }
 
 impl CameraDescriptor {
-     unsafe fn new(vi: Rc<VulkanInstance>, device: Rc<Device>) -> CameraDescriptor {
-        let (ubo_buf, ubo_mem, ubo_ptr, ubo_size) =
-            create_host_buffer(vi.clone(), device.clone(), &[CameraUBO::default()], vk::BufferUsageFlags::UNIFORM_BUFFER, false);
+     unsafe fn new(alloc: Rc<MemoryAllocator>, device: Rc<Device>, frames_in_flight: usize) -> CameraDescriptor {
+        let mut ubo_bufs = Vec::new();
+        for _ in 0..frames_in_flight {
+            let (ubo_buf, ubo_alloc, ubo_ptr, _) = create_uniform_buffer(alloc.clone(), device.clone(), &CameraUBO::default());
+            ubo_bufs.push((ubo_buf, ubo_alloc, ubo_ptr));
+        }
 
         CameraDescriptor {
-            device, ubo_buf, ubo_mem, ubo_ptr, ubo_size,
+            alloc, ubo: CameraUBO::default(), ubo_bufs,
         }
     }
 
     fn update(&mut self, ubo: CameraUBO) {
-        unsafe {
-            update_buffer(self.ubo_ptr, &[ubo], self.ubo_size);
-        }
+        self.ubo = ubo;
     }
 
-    fn descriptor_buffer_info(&self) -> vk::DescriptorBufferInfo {
-        vk::DescriptorBufferInfo {
-            buffer: self.ubo_buf,
-            offset: 0 as vk::DeviceSize,
-            range: mem::size_of::<CameraUBO>() as vk::DeviceSize,
-        }
+    unsafe fn write(&self, frame: usize) {
+        update_uniform_buffer(self.ubo_bufs[frame].2, &self.ubo);
+    }
+
+    fn descriptor_buffer_infos(&self) -> Vec<vk::DescriptorBufferInfo> {
+        self.ubo_bufs.iter()
+            .map(|(ubo_buf, _, _)| CameraUBO::descriptor_buffer_info(*ubo_buf))
+            .collect()
     }
 
     unsafe fn destroy(&self) {
-        self.device.destroy_buffer(self.ubo_buf, None);
-        self.device.free_memory(self.ubo_mem, None);
+        for (ubo_buf, ubo_alloc, _) in &self.ubo_bufs {
+            self.alloc.destroy_buffer(*ubo_buf, *ubo_alloc);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn world_pos_stays_precise() {
+        let mut pos = WorldPos::from_blocks(-0.5, 40.0, 100_000_000.25);
+        assert_eq!(pos.chunk, [-1, 1, 3_125_000]);
+        assert_eq!(pos.local, [31.5, 8.0, 0.25]);
+
+        // far beyond where f32 can still resolve a quarter block
+        for _ in 0..1000 {
+            pos.translate([0.0, 0.0, 0.25]);
+        }
+        assert_eq!(pos.chunk[2], 3_125_007);
+        assert_eq!(pos.local[2], 26.25);
+        assert_eq!(pos.origin_block()[2], 100_000_224);
+
+        pos.translate([-64.0, 0.0, 0.0]);
+        assert_eq!((pos.chunk[0], pos.local[0]), (-3, 31.5));
     }
 }
@@ -1,12 +1,15 @@
 use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::path::PathBuf;
 use std::rc::Rc;
 use ash::{Device, vk};
-use egui::{ClippedPrimitive, Context, ImageData, Mesh, RawInput, TextureFilter, TextureId};
+use egui::{ClippedPrimitive, Color32, Context, Event, ImageData, Mesh, PointerButton, Pos2, RawInput, Rect, Response, Slider, TextureFilter, TextureId};
 use egui::epaint::{ImageDelta, Primitive, Vertex};
 use uom::fmt::DisplayStyle;
-use crate::component::{Component, RenderData, RenderDataPurpose};
-use crate::handler::VulkanInstance;
+use crate::component::{ClippedDraw, Component, RenderData, RenderDataPurpose};
+use crate::memory::{Allocation, MemoryAllocator};
 use crate::measurement::blox;
+use crate::settings::Settings;
 use crate::util::{CmdBufContext, create_host_buffer, create_local_image};
 use crate::world::{CardinalDir, WorldEvent};
 
@@ -17,8 +20,15 @@ pub(crate) struct DebugUIData {
     fps: String,
     pos: String,
     spectator_mode: String,
+    chunk_cache: String,
+    shaders: String,  // only shown once the shaders were hot reloaded
+    shader_error: Option<String>,
 
     fps_hist: VecDeque<f32>,
+
+    settings: Settings,
+    settings_open: bool,
+    commit_settings: bool,  // an edit of the settings ended, e.g. a slider was released
 }
 
 impl Default for DebugUIData {
@@ -28,7 +38,13 @@ impl Default for DebugUIData {
             fps: String::from(".fps: <UNDEFINED>"),
             pos: String::from(".pos: <UNDEFINED>"),
             spectator_mode: String::from(".spectator_mode: <UNDEFINED>"),
+            chunk_cache: String::from(".chunk_cache: <UNDEFINED>"),
+            shaders: String::new(),
+            shader_error: None,
             fps_hist: VecDeque::new(),
+            settings: Settings::default(),
+            settings_open: false,
+            commit_settings: false,
         }
     }
 }
@@ -37,34 +53,90 @@ pub(crate) struct DebugUI {
     ui_handler: EguiHandler,
     render_data: Vec<(Vec<Vertex>, Vec<u32>, vk::Rect2D, TextureId)>,
     pub(crate) ui_data: DebugUIData,
+
+    settings_path: PathBuf,
+    committed_settings: Settings,  // last settings sent out, the ones being edited may be ahead
+    changed_settings: Option<Settings>,  // emitted with the next batch of events
 }
 
 impl DebugUI {
     const FPS_SAMPLES: usize = 200;
 
-    pub(crate) fn new(vi: Rc<VulkanInstance>, device: Rc<Device>, init_raw_input: RawInput) -> Self {
+    pub(crate) fn new(alloc: Rc<MemoryAllocator>, device: Rc<Device>, ctx: CmdBufContext, init_raw_input: RawInput,
+                      settings: Settings, settings_path: PathBuf) -> Self {
         let mut s = Self {
-            ui_handler: EguiHandler::new(vi.clone(), device.clone(), init_raw_input),
+            ui_handler: EguiHandler::new(alloc.clone(), device.clone(), ctx, init_raw_input),
             render_data: Vec::new(),
-            ui_data: DebugUIData::default(),
+            ui_data: DebugUIData {
+                settings,
+                ..Default::default()
+            },
+            settings_path,
+            committed_settings: settings,
+            changed_settings: None,
         };
         unsafe {
-            // needs to ensure ui_handler is display() ed before to obtain texture
-            let mut render_data = s.ui_handler.display(s.ui_data.clone(), Self::ui_program());
-            s.render_data.append(&mut render_data);
+            s.render_data = s.ui_handler.display(&mut s.ui_data, Self::ui_program());
         }
         s
     }
 
+    fn commit_settings(&mut self) {
+        if self.ui_data.settings != self.committed_settings {
+            self.committed_settings = self.ui_data.settings;
+            self.changed_settings.replace(self.committed_settings);
+        }
+    }
+
+    // sliders are only committed once they are let go of, or changed without dragging (e.g. by the keyboard)
+    fn edit_ended(response: &Response) -> bool {
+        response.drag_released() || (response.changed() && !response.dragged())
+    }
+
     // >>> UI PROGRAM <<<
-    fn ui_program() -> impl FnOnce(&Context, DebugUIData) {
-        |ctx: &Context, data: DebugUIData| {
-            egui::CentralPanel::default().show(&ctx, |ui| {
-                ui.label(data.face_direction);
-                ui.label(data.fps);
-                ui.label(data.pos);
-                ui.label(data.spectator_mode);
-            });
+    fn ui_program() -> impl FnOnce(&Context, &mut DebugUIData) {
+        |ctx: &Context, data: &mut DebugUIData| {
+            egui::Window::new("Debug")
+                .title_bar(false)
+                .resizable(false)
+                .movable(false)
+                .fixed_pos(Pos2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label(&data.face_direction);
+                    ui.label(&data.fps);
+                    ui.label(&data.pos);
+                    ui.label(&data.spectator_mode);
+                    ui.label(&data.chunk_cache);
+                    if !data.shaders.is_empty() {
+                        ui.label(&data.shaders);
+                    }
+                    if let Some(err) = &data.shader_error {
+                        ui.colored_label(Color32::RED, err);
+                    }
+                });
+
+            if data.settings_open {
+                egui::Window::new("Settings (F1)")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                    .show(ctx, |ui| {
+                        let settings = &mut data.settings;
+                        let responses = [
+                            ui.add(Slider::new(&mut settings.render_distance, Settings::RENDER_DISTANCE_RANGE).text("Render distance")),
+                            ui.add(Slider::new(&mut settings.fov, Settings::FOV_RANGE).text("FOV")),
+                            ui.add(Slider::new(&mut settings.mouse_sensitivity, Settings::MOUSE_SENSITIVITY_RANGE)
+                                .logarithmic(true)
+                                .text("Mouse sensitivity")),
+                            ui.checkbox(&mut settings.invert_y, "Invert Y"),
+                        ];
+                        if ui.button("Reset to defaults").clicked() {
+                            *settings = Settings::default();
+                            data.commit_settings = true;
+                        }
+                        data.commit_settings |= responses.iter().any(DebugUI::edit_ended);
+                    });
+            }
         }
     }
 }
@@ -73,23 +145,45 @@ impl Component for DebugUI {
     fn render(&self) -> Vec<RenderData> {
         // TODO: do we need to make sure the buffer object lasts long through DebugUI?
 
-        // TODO: assuming a single render data
-        let (vert, indx, scissor, _) = &self.render_data[0];
+        // every primitive ends up in the same buffers, each one is drawn from its own slice of them
+        let mut vert = Vec::new();
+        let mut indx = Vec::new();
+        let mut draws = Vec::new();
+        for (prim_vert, prim_indx, scissor, txtr_id) in &self.render_data {
+            let Some(txtr) = self.ui_handler.textures.get(txtr_id) else { continue };
+            draws.push(ClippedDraw {
+                first_index: indx.len() as u32,
+                index_count: prim_indx.len() as u32,
+                vertex_offset: vert.len() as i32,
+                scissor: *scissor,
+                texture: txtr.key,
+                texture_info: vk::DescriptorImageInfo {
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    image_view: txtr.img_view,
+                    sampler: txtr.sampler,
+                },
+            });
+            vert.extend_from_slice(prim_vert);
+            indx.extend_from_slice(prim_indx);
+        }
+        if indx.is_empty() {
+            return vec![RenderData::SetClippedDraws(draws, RenderDataPurpose::DebugUI)];
+        }
 
         // no staging buffer for DebugUI, since it is debug and you would want the fastest update (and its just UI)
 
-        let (vertex_buffer, vertex_buffer_mem, _, _) = unsafe {
-            create_host_buffer(self.ui_handler.vi.clone(), self.ui_handler.device.clone(), vert, vk::BufferUsageFlags::VERTEX_BUFFER, true)
+        let (vertex_buffer, vertex_buffer_alloc, _, _) = unsafe {
+            create_host_buffer(self.ui_handler.alloc.clone(), self.ui_handler.device.clone(), &vert, vk::BufferUsageFlags::VERTEX_BUFFER)
         };
 
-        let (index_buffer, index_buffer_mem, _, _) = unsafe {
-            create_host_buffer(self.ui_handler.vi.clone(), self.ui_handler.device.clone(), indx, vk::BufferUsageFlags::INDEX_BUFFER, true)
+        let (index_buffer, index_buffer_alloc, _, _) = unsafe {
+            create_host_buffer(self.ui_handler.alloc.clone(), self.ui_handler.device.clone(), &indx, vk::BufferUsageFlags::INDEX_BUFFER)
         };
 
         vec![
-            RenderData::RecreateVertexBuffer(vertex_buffer, vertex_buffer_mem, RenderDataPurpose::DebugUI),
-            RenderData::RecreateIndexBuffer(index_buffer, index_buffer_mem, indx.len() as u32, RenderDataPurpose::DebugUI),
-            RenderData::SetScissorDynamicState(*scissor, RenderDataPurpose::DebugUI),
+            RenderData::RecreateVertexBuffer(vertex_buffer, vertex_buffer_alloc, RenderDataPurpose::DebugUI),
+            RenderData::RecreateIndexBuffer(index_buffer, index_buffer_alloc, indx.len() as u32, RenderDataPurpose::DebugUI),
+            RenderData::SetClippedDraws(draws, RenderDataPurpose::DebugUI),
         ]
     }
 
@@ -124,6 +218,36 @@ impl Component for DebugUI {
                                            pos.y.round::<blox>().into_format_args(blox, DisplayStyle::Abbreviation),
                                            pos.z.round::<blox>().into_format_args(blox, DisplayStyle::Abbreviation));
             }
+            WorldEvent::SettingsMenu(open) => {
+                self.ui_data.settings_open = open;
+                if !open {
+                    self.ui_data.settings.save(&self.settings_path);
+                    self.commit_settings();
+                }
+            }
+            WorldEvent::CursorMoved((x, y)) => {
+                self.ui_handler.pointer_moved(x, y);
+            }
+            WorldEvent::LeftButtonPressed if self.ui_data.settings_open => {
+                self.ui_handler.pointer_button(true);
+            }
+            WorldEvent::LeftButtonReleased if self.ui_data.settings_open => {
+                self.ui_handler.pointer_button(false);
+            }
+            WorldEvent::ChunkCacheStats(stats) => {
+                self.ui_data.chunk_cache = format!("Chunk Cache: {} hits / {} misses, {} chunks ({:.1}/{:.0} MiB)",
+                                                   stats.hits, stats.misses, stats.entries,
+                                                   stats.bytes as f32/(1024.0*1024.0),
+                                                   stats.capacity as f32/(1024.0*1024.0));
+            }
+            WorldEvent::ShadersReloaded(Ok(ref shaders)) => {
+                self.ui_data.shaders = format!("Shaders: reloaded {}", shaders.join(", "));
+                self.ui_data.shader_error = None;
+            }
+            WorldEvent::ShadersReloaded(Err(ref err)) => {
+                self.ui_data.shaders = String::from("Shaders: failed to reload, using the previous pipelines");
+                self.ui_data.shader_error = Some(err.clone());
+            }
             WorldEvent::SpectatorMode(enabled) => {
                 self.ui_data.spectator_mode = if enabled {
                     String::from("Spectator Mode: TRUE")
@@ -134,39 +258,24 @@ impl Component for DebugUI {
             _ => {}
         }
 
+        if let WorldEvent::WindowResized((width, height)) = event {
+            self.ui_handler.resize(width, height);
+        }
+
         // TODO: for creating raw input
         // self.ui_handler.modify_raw_input(event);
 
-        vec![]
+        match self.changed_settings.take() {
+            Some(settings) => vec![WorldEvent::SettingsChanged(settings)],
+            None => vec![],
+        }
     }
 
     fn update(&mut self) {
-        unsafe { self.render_data = self.ui_handler.display(self.ui_data.clone(), Self::ui_program()); }
-    }
-
-    unsafe fn load_descriptors(&mut self, ctx: CmdBufContext) -> Vec<RenderData> {
-        // TODO: assume egui will only create new descriptor texture once (for now)
-        // TODO: ... also needs the full output from running the closure
-
-        ctx.record(|cmd_buf| {
-            for (_, ui_txtr) in &self.ui_handler.textures {
-                self.ui_handler.transfer_img_barrier(cmd_buf, ui_txtr);
-            }
-        });
-        self.ui_handler.create_img_views();
-
-        self.ui_handler.textures.iter()
-            .map(|(_, txtr_descriptor)| {
-                RenderData::InitialDescriptorImage(
-                    vec![vk::DescriptorImageInfo {
-                        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                        image_view: txtr_descriptor.img_view.unwrap(),
-                        sampler: txtr_descriptor.sampler,
-                    }],
-                    RenderDataPurpose::DebugUI,
-                )
-            })
-            .collect::<Vec<RenderData>>()
+        unsafe { self.render_data = self.ui_handler.display(&mut self.ui_data, Self::ui_program()); }
+        if mem::take(&mut self.ui_data.commit_settings) {
+            self.commit_settings();
+        }
     }
 
     unsafe fn destroy(&mut self) {
@@ -177,44 +286,77 @@ impl Component for DebugUI {
 
 
 struct UITextureDescriptor {
+    key: u64,  // a replaced texture gets a new key, so descriptors already in use are never rewritten
     sampler: vk::Sampler,
-    img_view: Option<vk::ImageView>,
-    host_buf: vk::Buffer,
-    host_buf_mem: vk::DeviceMemory,
+    img_view: vk::ImageView,
     local_img: vk::Image,
-    local_img_mem: vk::DeviceMemory,
-    extent: vk::Extent3D,
+    local_img_alloc: Allocation,
 }
 
 
 struct EguiHandler {
     // Vulkan loaders
-    vi: Rc<VulkanInstance>,
+    alloc: Rc<MemoryAllocator>,
     device: Rc<Device>,
+    ctx_vk: CmdBufContext,
 
     // Egui state components
     ctx: egui::Context,
     raw_input: RawInput,
+    cursor: Pos2,
 
     // Egui renders
     textures: HashMap<TextureId, UITextureDescriptor>,
+    next_texture_key: u64,
 }
 
 impl EguiHandler {
-    fn new(vi: Rc<VulkanInstance>, device: Rc<Device>, init_raw_input: RawInput) -> Self {
+    fn new(alloc: Rc<MemoryAllocator>, device: Rc<Device>, ctx_vk: CmdBufContext, init_raw_input: RawInput) -> Self {
         Self {
-            vi, device,
+            alloc, device, ctx_vk,
             ctx: egui::Context::default(),
             raw_input: init_raw_input,
+            cursor: Pos2::ZERO,
             textures: HashMap::new(),
+            next_texture_key: 0,
+        }
+    }
+    fn pixels_per_point(&self) -> f32 {
+        self.raw_input.viewport().native_pixels_per_point.unwrap_or(1.0)
+    }
+
+    // the UI covers the whole window, its windows place themselves within it
+    fn resize(&mut self, width: u32, height: u32) {
+        let window_size = Pos2::from((width as f32, height as f32));
+        if let Some(viewport) = self.raw_input.viewports.get_mut(&self.raw_input.viewport_id) {
+            viewport.inner_rect = Some(Rect {min: Pos2::ZERO, max: window_size});
         }
+        self.raw_input.screen_rect = Some(Rect {
+            min: Pos2::ZERO,
+            max: (window_size.to_vec2()/self.pixels_per_point()).to_pos2(),
+        });
+    }
+
+    fn pointer_moved(&mut self, x: f64, y: f64) {
+        self.cursor = Pos2::from((x as f32, y as f32))/self.pixels_per_point();
+        self.raw_input.events.push(Event::PointerMoved(self.cursor));
+    }
+
+    fn pointer_button(&mut self, pressed: bool) {
+        self.raw_input.events.push(Event::PointerButton {
+            pos: self.cursor,
+            button: PointerButton::Primary,
+            pressed,
+            modifiers: self.raw_input.modifiers,
+        });
     }
 
     // fn modify_raw_input(&mut self, event: WorldEvent) {
     //
     // }
 
-    unsafe fn display(&mut self, data: DebugUIData, cb: impl FnOnce(&Context, DebugUIData)) -> Vec<(Vec<Vertex>, Vec<u32>, vk::Rect2D, TextureId)> {
+    // one mesh per primitive, with the scissor of its clip rect
+    unsafe fn display(&mut self, data: &mut DebugUIData, cb: impl FnOnce(&Context, &mut DebugUIData)) -> Vec<(Vec<Vertex>, Vec<u32>, vk::Rect2D, TextureId)> {
         // TODO: aggregate all the events here and create raw input only in here
         // TODO: custom closure that also pass in dynamic info (vector of trait object that describes
         // TODO: ... what data it is and check compatibility between component updates and UI's compatibility
@@ -222,54 +364,63 @@ impl EguiHandler {
         let full_output = self.ctx.run(self.raw_input.clone(), |ctx| {
             cb(ctx, data);
         });
+        self.raw_input.events.clear();
         // non render output from egui
         let _non_render_output = full_output.platform_output;
 
         // render output from egui
-        let clipped_primitives = self.ctx.tessellate(full_output.shapes, full_output.pixels_per_point);
+        let ppp = full_output.pixels_per_point;
+        let clipped_primitives = self.ctx.tessellate(full_output.shapes, ppp);
 
         // textures to create before rendering
         self.create_textures(full_output.textures_delta.set);
 
-        let mut primitive_meshes = Vec::new();
-        for ClippedPrimitive {clip_rect, primitive} in clipped_primitives {
-            // TODO: scissor takes in "pixels", make sure its the same as logical pixels from egui
-            let scissor = vk::Rect2D {
-                offset: vk::Offset2D {x: clip_rect.min.x as i32, y: clip_rect.min.y as i32},
-                extent: vk::Extent2D {
-                    width: (clip_rect.max.x-clip_rect.min.x) as u32,
-                    height: (clip_rect.max.y-clip_rect.min.y) as u32
-                },
-            };
-            if let Primitive::Mesh(Mesh {indices, vertices, texture_id: txtr_id}) = primitive {
-                // println!("MESH {indices:?} {vertices:?}");
-                primitive_meshes.push((vertices, indices, scissor, txtr_id));
-            }
-        }
+        // positions are converted to NDC here
+        let screen_size = self.raw_input.screen_rect.map_or(egui::Vec2::splat(1.0), |rect| rect.size());
 
         // textures to free/destroy will happen at the end of program
         // (assuming we dont help egui to add new texture in between execution)
 
-        primitive_meshes
+        clipped_primitives.into_iter()
+            .filter_map(|ClippedPrimitive {clip_rect, primitive}| match primitive {
+                Primitive::Mesh(Mesh {indices, vertices, texture_id: txtr_id}) if !indices.is_empty() => {
+                    let verts = vertices.into_iter()
+                        .map(|mut vert| {
+                            vert.pos = (vert.pos.to_vec2()/screen_size*2.0-egui::Vec2::splat(1.0)).to_pos2();
+                            vert
+                        })
+                        .collect();
+
+                    // scissors are in pixels while egui works in points
+                    let min = (clip_rect.min*ppp).max(Pos2::ZERO);
+                    let max = (clip_rect.max*ppp).max(min);
+                    let scissor = vk::Rect2D {
+                        offset: vk::Offset2D {x: min.x as i32, y: min.y as i32},
+                        extent: vk::Extent2D {
+                            width: (max.x-min.x) as u32,
+                            height: (max.y-min.y) as u32
+                        },
+                    };
+                    Some((verts, indices, scissor, txtr_id))
+                }
+                _ => None,
+            })
+            .collect()
     }
 
     unsafe fn destroy(&self) {
         for (txtr_id, txtr) in &self.textures {
             println!("FREE TEXTURE {txtr_id:?}");
-
-            self.device.destroy_sampler(txtr.sampler, None);
-            if let Some(img_view) = txtr.img_view {
-                self.device.destroy_image_view(img_view, None);
-            }
-
-            self.device.destroy_buffer(txtr.host_buf, None);
-            self.device.free_memory(txtr.host_buf_mem, None);
-
-            self.device.destroy_image(txtr.local_img, None);
-            self.device.free_memory(txtr.local_img_mem, None);
+            self.destroy_texture(txtr);
         }
     }
 
+    unsafe fn destroy_texture(&self, txtr: &UITextureDescriptor) {
+        self.device.destroy_sampler(txtr.sampler, None);
+        self.device.destroy_image_view(txtr.img_view, None);
+        self.alloc.destroy_image(txtr.local_img, txtr.local_img_alloc);
+    }
+
     fn convert_filter(filter: TextureFilter) -> vk::Filter {
         match filter {
             TextureFilter::Linear => vk::Filter::LINEAR,
@@ -277,54 +428,64 @@ impl EguiHandler {
         }
     }
 
+    fn image_bytes(image: ImageData) -> (Vec<u8>, vk::Extent3D) {
+        match image {
+            ImageData::Color(color) => {
+                let extent = vk::Extent3D {
+                    width: color.width() as u32,
+                    height: color.height() as u32,
+                    depth: 1,
+                };
+                (color.as_raw().to_vec(), extent)
+            }
+            ImageData::Font(font) => {
+                // vk::Format::R32_SFLOAT  // red pixel representing coverage (alpha)
+                let bytes = font.srgba_pixels(None)
+                    .flat_map(|col| col.to_srgba_unmultiplied())
+                    .collect();
+                let extent = vk::Extent3D {
+                    width: font.width() as u32,
+                    height: font.height() as u32,
+                    depth: 1,
+                };
+                (bytes, extent)
+            }
+        }
+    }
+
+    // textures are uploaded right away, the one-shot submission waits for the queue to be idle, so
+    // nothing in flight still samples a texture that is written to or replaced here
     unsafe fn create_textures(&mut self, new_txtrs: Vec<(TextureId, ImageDelta)>) {
         for (txtr_id, ImageDelta {image, options, pos} ) in new_txtrs {
-            // TODO: create vk buffer image
+            let (bytes, extent) = Self::image_bytes(image);
+
+            if let Some([x, y]) = pos {
+                // partial update of a texture that already exists
+                let Some(txtr) = self.textures.get(&txtr_id) else { continue };
+                let offset = vk::Offset3D {x: x as i32, y: y as i32, z: 0};
+                self.upload(txtr.local_img, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, offset, extent, &bytes);
+                continue;
+            }
             println!("SET {txtr_id:?}");
 
-            let (host_buf, host_buf_mem, local_img, local_img_mem, extent) = match image {
-                ImageData::Color(color) => {
-                    println!("COLOR");
-
-                    let bytes = color.as_raw();
-                    let img_extent = vk::Extent3D {
-                        width: color.width() as u32,
-                        height: color.height() as u32,
-                        depth: 1,
-                    };
-
-                    let (buf, buf_mem, _, _) = create_host_buffer(self.vi.clone(), self.device.clone(), bytes, vk::BufferUsageFlags::TRANSFER_SRC, true);
-
-                    let (img, img_mem) = create_local_image(self.vi.clone(), self.device.clone(), img_extent, 1, vk::Format::R8G8B8A8_SRGB, vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED, None);
+            let (local_img, local_img_alloc) = create_local_image(self.alloc.clone(), self.device.clone(), extent, 1, vk::Format::R8G8B8A8_SRGB, vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED, None);
+            self.upload(local_img, vk::ImageLayout::UNDEFINED, vk::Offset3D {x: 0, y: 0, z: 0}, extent, &bytes);
 
-                    (buf, buf_mem, img, img_mem, img_extent)
-                }
-                ImageData::Font(font) => {
-                    println!("FONT");
-
-                    // vk::Format::R32_SFLOAT  // red pixel representing coverage (alpha)
-                    let bytes = font.srgba_pixels(None)
-                        .map(|col| {
-                            col.to_srgba_unmultiplied()
-                        })
-                        .fold(Vec::new(), |mut b, next| {
-                            b.extend_from_slice(&next);
-                            b
-                        });
-
-                    let img_extent = vk::Extent3D {
-                        width: font.width() as u32,
-                        height: font.height() as u32,
-                        depth: 1,
-                    };
-
-                    let (buf, buf_mem, _, _) = create_host_buffer(self.vi.clone(), self.device.clone(), &bytes, vk::BufferUsageFlags::TRANSFER_SRC, true);
-
-                    let (img, img_mem) = create_local_image(self.vi.clone(), self.device.clone(), img_extent, 1, vk::Format::R8G8B8A8_SRGB, vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED, None);
-
-                    (buf, buf_mem, img, img_mem, img_extent)
-                }
+            let img_view_info = vk::ImageViewCreateInfo {
+                image: local_img,
+                view_type: vk::ImageViewType::TYPE_2D,
+                format: vk::Format::R8G8B8A8_SRGB,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                ..Default::default()
             };
+            let img_view = self.device.create_image_view(&img_view_info, None)
+                .expect("Failed to create texture image view");
 
             let sampler_info = vk::SamplerCreateInfo {
                 mag_filter: Self::convert_filter(options.magnification),
@@ -346,100 +507,85 @@ impl EguiHandler {
             let sampler = self.device.create_sampler(&sampler_info, None)
                 .expect("Failed to create UI sampler");
 
-            self.textures.insert(txtr_id, UITextureDescriptor {
-                sampler, img_view: None, host_buf, host_buf_mem, local_img, local_img_mem, extent
+            let key = self.next_texture_key;
+            self.next_texture_key += 1;
+            let replaced = self.textures.insert(txtr_id, UITextureDescriptor {
+                key, sampler, img_view, local_img, local_img_alloc
             });
+            if let Some(old_txtr) = replaced {
+                self.destroy_texture(&old_txtr);
+            }
         }
     }
 
-    unsafe fn transfer_img_barrier(&self, cmd_buf: vk::CommandBuffer, ui_txtr: &UITextureDescriptor) {
-        // transition image layout to prepare for transfer
-
-        let transfer_barriers = [vk::ImageMemoryBarrier {
-            old_layout: vk::ImageLayout::UNDEFINED,
-            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            image: ui_txtr.local_img,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            src_access_mask: vk::AccessFlags::empty(),
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            ..Default::default()
-        }];
-        self.device.cmd_pipeline_barrier(
-            cmd_buf, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(), &[], &[], &transfer_barriers
-        );
-
-        // copy buffer to image
-
-        let regions = [vk::BufferImageCopy {
-            buffer_offset: 0,
-            buffer_row_length: 0,
-            buffer_image_height: 0,
-            image_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                mip_level: 0,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            image_offset: vk::Offset3D {x:0, y:0, z:0},
-            image_extent: ui_txtr.extent,
-        }];
-        self.device.cmd_copy_buffer_to_image(
-            cmd_buf, ui_txtr.host_buf, ui_txtr.local_img, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions
-        );
-
-        // transition image layout from transfer to be read by shaders
-
-        let shader_barriers = [vk::ImageMemoryBarrier {
-            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            image: ui_txtr.local_img,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::SHADER_READ,
-            ..Default::default()
-        }];
-        self.device.cmd_pipeline_barrier(
-            cmd_buf, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER,
-            vk::DependencyFlags::empty(), &[], &[], &shader_barriers
-        );
-    }
+    // copies the pixels into a region of the image and leaves it ready to be read by shaders
+    unsafe fn upload(&self, img: vk::Image, old_layout: vk::ImageLayout, offset: vk::Offset3D, extent: vk::Extent3D, bytes: &[u8]) {
+        let (host_buf, host_buf_alloc, _, _) = create_host_buffer(self.alloc.clone(), self.device.clone(), bytes, vk::BufferUsageFlags::TRANSFER_SRC);
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
 
-    unsafe fn create_img_views(&mut self) {
-        for (_, txtr) in &mut self.textures {
-            let img_view_info = vk::ImageViewCreateInfo {
-                image: txtr.local_img,
-                view_type: vk::ImageViewType::TYPE_2D,
-                format: vk::Format::R8G8B8A8_SRGB,
-                subresource_range: vk::ImageSubresourceRange {
+        self.ctx_vk.record(|cmd_buf| {
+            // transition image layout to prepare for transfer
+
+            let transfer_barriers = [vk::ImageMemoryBarrier {
+                old_layout,
+                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: img,
+                subresource_range,
+                src_access_mask: vk::AccessFlags::SHADER_READ,
+                dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                ..Default::default()
+            }];
+            self.device.cmd_pipeline_barrier(
+                cmd_buf, vk::PipelineStageFlags::FRAGMENT_SHADER, vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(), &[], &[], &transfer_barriers
+            );
+
+            // copy buffer to image
+
+            let regions = [vk::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
+                    mip_level: 0,
                     base_array_layer: 0,
                     layer_count: 1,
                 },
+                image_offset: offset,
+                image_extent: extent,
+            }];
+            self.device.cmd_copy_buffer_to_image(
+                cmd_buf, host_buf, img, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions
+            );
+
+            // transition image layout from transfer to be read by shaders
+
+            let shader_barriers = [vk::ImageMemoryBarrier {
+                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: img,
+                subresource_range,
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
                 ..Default::default()
-            };
-            txtr.img_view.replace(
-                self.device.create_image_view(&img_view_info, None)
-                    .expect("Failed to create texture image view")
+            }];
+            self.device.cmd_pipeline_barrier(
+                cmd_buf, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(), &[], &[], &shader_barriers
             );
-        }
+        });
+
+        self.alloc.destroy_buffer(host_buf, host_buf_alloc);
     }
 }
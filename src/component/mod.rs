@@ -6,6 +6,7 @@ pub mod tick;
 pub mod flags;
 
 use ash::vk;
+use crate::memory::Allocation;
 use crate::util::CmdBufContext;
 use crate::world::{WorldEvent};
 
@@ -18,7 +19,6 @@ pub enum RenderDataPurpose {
     TerrainOpaque,
     TerrainTransparent,
     TerrainTranslucent,
-    PresentationInpAttachment,
     DebugUI,
     DebugUIInpAttachment,
     Time,
@@ -30,9 +30,41 @@ pub enum RenderDataPurpose {
 pub enum RenderData {
     InitialDescriptorBuffer(Vec<vk::DescriptorBufferInfo>, RenderDataPurpose),
     InitialDescriptorImage(Vec<vk::DescriptorImageInfo>, RenderDataPurpose),
-    RecreateVertexBuffer(vk::Buffer, vk::DeviceMemory, RenderDataPurpose),
-    RecreateIndexBuffer(vk::Buffer, vk::DeviceMemory, u32, RenderDataPurpose),
+    RecreateVertexBuffer(vk::Buffer, Allocation, RenderDataPurpose),
+    RecreateIndexBuffer(vk::Buffer, Allocation, u32, RenderDataPurpose),
     SetScissorDynamicState(vk::Rect2D, RenderDataPurpose),
+    SetDrawRanges(Vec<DrawRange>, RenderDataPurpose),
+    SetClippedDraws(Vec<ClippedDraw>, RenderDataPurpose),
+}
+
+impl RenderData {
+    pub fn purpose(&self) -> RenderDataPurpose {
+        match self {
+            RenderData::InitialDescriptorBuffer(_, purpose) | RenderData::InitialDescriptorImage(_, purpose) |
+            RenderData::RecreateVertexBuffer(_, _, purpose) | RenderData::RecreateIndexBuffer(_, _, _, purpose) |
+            RenderData::SetScissorDynamicState(_, purpose) | RenderData::SetDrawRanges(_, purpose) |
+            RenderData::SetClippedDraws(_, purpose) => *purpose,
+        }
+    }
+}
+
+// a slice of an index buffer drawn on its own, so that its vertices can stay relative to the origin
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DrawRange {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub origin: [i32; 3],  // in blocks
+}
+
+// a slice of an index buffer drawn with its own texture, clipped by the scissor
+#[derive(Copy, Clone, Debug)]
+pub struct ClippedDraw {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    pub scissor: vk::Rect2D,
+    pub texture: u64,  // changes whenever the texture is replaced, so its descriptor has to be rewritten
+    pub texture_info: vk::DescriptorImageInfo,
 }
 
 // using a single master trait for components, since splitting the trait into related methods
@@ -47,5 +79,7 @@ pub trait Component {
     fn update(&mut self);
     // Descriptable
     unsafe fn load_descriptors(&mut self, _: CmdBufContext) -> Vec<RenderData> {Vec::new()}
+    // called once the frame slot is no longer in use by the GPU, uniform buffers of that frame can be written to
+    unsafe fn write_uniforms(&mut self, _frame: usize) {}
     unsafe fn destroy(&mut self) {}
 }
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::rc::Rc;
+use crate::component::RenderDataPurpose;
+use crate::component::terrain::FaceDir;
+
+
+pub(crate) type ChunkMeshData<V, I> = Vec<(Vec<V>, Vec<I>, Option<FaceDir>, RenderDataPurpose)>;
+pub(crate) type SharedChunkCache<V, I> = Rc<RefCell<ChunkCache<V, I>>>;
+
+// a chunk position along with its size in blocks, so that rings of the same fidelity share entries
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ChunkKey {
+    pub(crate) fidelity: u32,
+    pub(crate) x: isize, pub(crate) y: isize, pub(crate) z: isize,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,  // bytes
+}
+
+// meshes of recently unloaded chunks, the least recently unloaded ones are evicted first once the
+// memory budget is exceeded. a chunk leaves the cache again when it is loaded back.
+// only meshes are kept: chunks hold no voxel data of their own, the meshers sample the terrain
+// generator block by block, so a cached mesh already skips all of the generation work
+pub(crate) struct ChunkCache<V, I> {
+    entries: HashMap<ChunkKey, (u64, usize, ChunkMeshData<V, I>)>,  // unload stamp, bytes, mesh
+    order: BTreeMap<u64, ChunkKey>,  // unload stamp to key, oldest first
+    stamp: u64,
+    stats: CacheStats,
+}
+
+impl<V, I> ChunkCache<V, I> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            stamp: 0,
+            stats: CacheStats {capacity, ..Default::default()},
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn mesh_bytes(mesh: &ChunkMeshData<V, I>) -> usize {
+        mesh.iter()
+            .map(|(verts, inds, _, _)| verts.len()*mem::size_of::<V>() + inds.len()*mem::size_of::<I>())
+            .sum()
+    }
+
+    pub(crate) fn insert(&mut self, key: ChunkKey, mesh: ChunkMeshData<V, I>) {
+        let bytes = Self::mesh_bytes(&mesh);
+        if bytes > self.stats.capacity {
+            return;
+        }
+        if let Some((stamp, old_bytes, _)) = self.entries.remove(&key) {
+            self.order.remove(&stamp);
+            self.stats.bytes -= old_bytes;
+        }
+
+        self.stamp += 1;
+        self.entries.insert(key, (self.stamp, bytes, mesh));
+        self.order.insert(self.stamp, key);
+        self.stats.bytes += bytes;
+
+        while self.stats.bytes > self.stats.capacity {
+            let (_, oldest) = self.order.pop_first().unwrap();
+            let (_, evicted_bytes, _) = self.entries.remove(&oldest).unwrap();
+            self.stats.bytes -= evicted_bytes;
+            self.stats.evictions += 1;
+        }
+        self.stats.entries = self.entries.len();
+    }
+
+    pub(crate) fn take(&mut self, key: &ChunkKey) -> Option<ChunkMeshData<V, I>> {
+        match self.entries.remove(key) {
+            Some((stamp, bytes, mesh)) => {
+                self.order.remove(&stamp);
+                self.stats.bytes -= bytes;
+                self.stats.entries = self.entries.len();
+                self.stats.hits += 1;
+                Some(mesh)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    // the cached meshes are only valid for the texture mapper and chunk offsets they were made with
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.stats.bytes = 0;
+        self.stats.entries = 0;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(x: isize) -> ChunkKey {
+        ChunkKey {fidelity: 16, x, y: 0, z: 0}
+    }
+
+    fn mesh(verts: usize) -> ChunkMeshData<u32, u32> {
+        vec![(vec![0; verts], vec![], None, RenderDataPurpose::TerrainOpaque)]
+    }
+
+    #[test]
+    fn evicts_least_recently_unloaded() {
+        let mut cache = ChunkCache::new(12);
+        cache.insert(key(0), mesh(1));
+        cache.insert(key(1), mesh(1));
+        cache.insert(key(2), mesh(1));
+        assert_eq!(cache.stats().bytes, 12);
+
+        cache.insert(key(3), mesh(2));
+        assert_eq!(cache.stats().evictions, 2);
+        assert_eq!(cache.stats().entries, 2);
+
+        assert!(cache.take(&key(0)).is_none());
+        assert!(cache.take(&key(2)).is_some());
+        assert!(cache.take(&key(2)).is_none());
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.stats().bytes, 8);
+
+        // larger than the whole budget, never cached
+        cache.insert(key(4), mesh(4));
+        assert!(cache.take(&key(4)).is_none());
+        assert_eq!(cache.stats().entries, 1);
+    }
+}
@@ -1,34 +1,70 @@
 use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::rc::Rc;
 use noise::{NoiseFn};
 use uom::si::f32::Length;
 use crate::component::camera::Length3D;
-use crate::component::RenderDataPurpose;
+use crate::component::{DrawRange, RenderDataPurpose};
 use crate::component::terrain::{BlockData, FaceDir};
-use crate::component::terrain::chunk_mesh::{Chunk, ChunkGeneratable, Position};
+use crate::component::terrain::chunk_mesh::{BlockLengthUnit, Chunk, ChunkGeneratable, Position};
 use crate::component::terrain::mesh_util::{ChunkMeshUtil};
 use crate::component::terrain::terrain_gen::TerrainGenerator;
 use crate::component::texture::TextureIDMapper;
-use crate::measurement::{blox, chux};
-use crate::shader::chunk::ChunkVertex;
+use crate::measurement::{blox, chux, chux_hf, chux_lf, chux_mf};
+use crate::shader::chunk::{ChunkVertex, PackedChunkVertex};
 
-pub(super) struct ChunkGeneratorEF<'b> {
+// the fidelity levels only differ in their units, A is the unit of the ring radius and B the size
+// of a single chunk within it
+pub type ChunkGeneratorEF<'b> = ChunkGenerator<'b, chux, chux>;
+pub type ChunkGeneratorHF<'b> = ChunkGenerator<'b, chux_hf, chux>;
+pub type ChunkGeneratorMF<'b> = ChunkGenerator<'b, chux_mf, chux_hf>;
+pub type ChunkGeneratorLF<'b> = ChunkGenerator<'b, chux_lf, chux_mf>;
+
+// the meshes of a chunk before their vertices are packed
+pub type UnpackedChunkMesh = Vec<(Vec<ChunkVertex>, Vec<u32>, Option<FaceDir>, RenderDataPurpose)>;
+
+pub struct ChunkGenerator<'b, A: BlockLengthUnit, B: BlockLengthUnit> {
     chunk_size: u32,
     block_ind: Vec<BlockData<'b>>,
     txtr_id_mapper: TextureIDMapper,
     terrain_gen: Rc<TerrainGenerator>,
+    _units: PhantomData<(A, B)>,
 }
 
-impl<'b> ChunkGeneratorEF<'b> {
-    pub(super) fn new(block_ind: Vec<BlockData<'b>>, txtr_id_mapper: TextureIDMapper, terrain_gen: Rc<TerrainGenerator>) -> Self {
+impl<'b, A: BlockLengthUnit, B: BlockLengthUnit> ChunkGenerator<'b, A, B> {
+    pub fn new(block_ind: Vec<BlockData<'b>>, txtr_id_mapper: TextureIDMapper, terrain_gen: Rc<TerrainGenerator>) -> Self {
+        let chunk_size = Length::new::<B>(1.0).get::<blox>() as u32;
+        assert!(chunk_size <= ChunkVertex::MAX_CHUNK_SIZE, "Chunks of {chunk_size} blocks are too large for packed vertices");
         Self {
-            chunk_size: Length::new::<<Self as ChunkGeneratable>::B>(1.0).get::<blox>() as u32, block_ind, txtr_id_mapper,
+            chunk_size, block_ind, txtr_id_mapper,
             terrain_gen,
+            _units: PhantomData,
+        }
+    }
+
+    pub fn generate_unpacked_mesh(&self, origin: [i64; 3]) -> UnpackedChunkMesh {
+        // blocks are sampled at exact integer coordinates, while the vertices stay relative to the
+        // chunk so they can be packed. the fluid mesher may step one block below the chunk, which
+        // wraps around in u32
+        let ofs = (origin[0], origin[1], origin[2]);
+        let chunk_pos = |x: u32, y: u32, z: u32| (x as i32 as f32, y as i32 as f32, -(z as i32 as f32));
+
+        let opaque_cube_mesh = self.voluminous_opaque_cubes_mesh(ofs, chunk_pos);
+        let transparent_floral_mesh = self.sparse_transparent_floral_mesh(ofs, chunk_pos);
+        let translucent_fluid_mesh = self.temporary_fluid_mesher(ofs, chunk_pos);
+
+        let mut all_mesh = Vec::new();
+
+        for (v, i, f) in opaque_cube_mesh {
+            all_mesh.push((v, i, Some(f), RenderDataPurpose::TerrainOpaque))
         }
+        all_mesh.push((transparent_floral_mesh.0, transparent_floral_mesh.1, None, RenderDataPurpose::TerrainTransparent));
+        all_mesh.push((translucent_fluid_mesh.0, translucent_fluid_mesh.1, None, RenderDataPurpose::TerrainTranslucent));
+        all_mesh
     }
 }
 
-impl<'b> ChunkMeshUtil<'b> for ChunkGeneratorEF<'b> {
+impl<'b, A: BlockLengthUnit, B: BlockLengthUnit> ChunkMeshUtil<'b> for ChunkGenerator<'b, A, B> {
     fn chunk_size(&self) -> u32 {self.chunk_size}
 
     fn texture_id_mapper(&self) -> TextureIDMapper {self.txtr_id_mapper.clone()}
@@ -42,58 +78,46 @@ impl<'b> ChunkMeshUtil<'b> for ChunkGeneratorEF<'b> {
     }
 }
 
-impl ChunkGeneratable for ChunkGeneratorEF<'_> {
-    type A = chux;
-    type B = chux;
-    type V = ChunkVertex;
+impl<A: BlockLengthUnit, B: BlockLengthUnit> ChunkGeneratable for ChunkGenerator<'_, A, B> {
+    type A = A;
+    type B = B;
+    type V = PackedChunkVertex;
     type I = u32;
 
-    fn generate_mesh(&self, pos: Length3D)
+    fn generate_mesh(&self, origin: [i64; 3])
         -> Vec<(Vec<Self::V>, Vec<Self::I>, Option<FaceDir>, RenderDataPurpose)>
     {
-        let ofs = (pos.x.get::<blox>().ceil() as i32, pos.y.get::<blox>().ceil() as i32, pos.z.get::<blox>().ceil() as i32);
-        let chunk_pos = |x: u32, y: u32, z: u32| (
-            pos.x.get::<blox>()+x as f32,
-            pos.y.get::<blox>()+y as f32,
-            -pos.z.get::<blox>()-z as f32
-        );
-
-        let opaque_cube_mesh = self.voluminous_opaque_cubes_mesh(ofs, chunk_pos);
-        let transparent_floral_mesh = self.sparse_transparent_floral_mesh(ofs, chunk_pos);
-        let translucent_fluid_mesh = self.temporary_fluid_mesher(ofs, chunk_pos);
-
-        let mut all_mesh = Vec::new();
-
-        for (v, i, f) in opaque_cube_mesh {
-            all_mesh.push((v, i, Some(f), RenderDataPurpose::TerrainOpaque))
-        }
-        all_mesh.push((transparent_floral_mesh.0, transparent_floral_mesh.1, None, RenderDataPurpose::TerrainTransparent));
-        all_mesh.push((translucent_fluid_mesh.0, translucent_fluid_mesh.1, None, RenderDataPurpose::TerrainTranslucent));
-
-        all_mesh
+        self.generate_unpacked_mesh(origin).into_iter()
+            .map(|(v, i, f, p)| (v.iter().map(|vert| vert.pack(f)).collect(), i, f, p))
+            .collect()
     }
 
     fn aggregate_mesh(&self,
                       _central_pos: Length3D,
                       chunks: &HashMap<Position<Self::B>, Chunk<Self::V, Self::I, Self::B>>
-    ) -> Vec<(Vec<Self::V>, Vec<Self::I>, RenderDataPurpose)>
+    ) -> Vec<(Vec<Self::V>, Vec<Self::I>, Vec<DrawRange>, RenderDataPurpose)>
     {
         println!("GEN AGGREGATED MESH");
 
         let mut opaque_verts = vec![];
         let mut opaque_inds = vec![];
         let mut opaque_ind_count = 0;
+        let mut opaque_draws = vec![];
         let mut transparent_verts = vec![];
         let mut transparent_inds = vec![];
         let mut transparent_ind_count = 0;
+        let mut transparent_draws = vec![];
         let mut translucent_verts = vec![];
         let mut translucent_inds = vec![];
         let mut translucent_ind_count = 0;
+        let mut translucent_draws = vec![];
 
         for chunk in chunks.values().filter(|c| c.visible()) {
+            let origin = chunk.hash_pos.origin_block().map(|o| o as i32);
             for (vert, raw_ind, _, purpose) in chunk.mesh.iter() {
                 match purpose {
                     RenderDataPurpose::TerrainOpaque => {
+                        push_draw(&mut opaque_draws, opaque_inds.len(), raw_ind.len(), origin);
                         let mut ind = raw_ind.clone().iter().map(|i| i+opaque_ind_count).collect();
                         opaque_ind_count += vert.len() as u32;
 
@@ -101,6 +125,7 @@ impl ChunkGeneratable for ChunkGeneratorEF<'_> {
                         opaque_inds.append(&mut ind);
                     }
                     RenderDataPurpose::TerrainTransparent => {
+                        push_draw(&mut transparent_draws, transparent_inds.len(), raw_ind.len(), origin);
                         let mut ind = raw_ind.clone().iter().map(|i| i+transparent_ind_count).collect();
                         transparent_ind_count += vert.len() as u32;
 
@@ -108,6 +133,7 @@ impl ChunkGeneratable for ChunkGeneratorEF<'_> {
                         transparent_inds.append(&mut ind);
                     }
                     RenderDataPurpose::TerrainTranslucent => {
+                        push_draw(&mut translucent_draws, translucent_inds.len(), raw_ind.len(), origin);
                         let mut ind = raw_ind.clone().iter().map(|i| i+translucent_ind_count).collect();
                         translucent_ind_count += vert.len() as u32;
 
@@ -120,10 +146,23 @@ impl ChunkGeneratable for ChunkGeneratorEF<'_> {
         }
 
         vec![
-            (opaque_verts, opaque_inds, RenderDataPurpose::TerrainOpaque),
-            (transparent_verts, transparent_inds, RenderDataPurpose::TerrainTransparent),
-            (translucent_verts, translucent_inds, RenderDataPurpose::TerrainTranslucent),
+            (opaque_verts, opaque_inds, opaque_draws, RenderDataPurpose::TerrainOpaque),
+            (transparent_verts, transparent_inds, transparent_draws, RenderDataPurpose::TerrainTransparent),
+            (translucent_verts, translucent_inds, translucent_draws, RenderDataPurpose::TerrainTranslucent),
         ]
     }
 }
 
+// the face meshes of a chunk follow each other in the index buffer, so they share a single draw
+fn push_draw(draws: &mut Vec<DrawRange>, first_index: usize, index_count: usize, origin: [i32; 3]) {
+    if index_count == 0 {
+        return;
+    }
+    match draws.last_mut() {
+        Some(last) if last.origin == origin && (last.first_index+last.index_count) as usize == first_index => {
+            last.index_count += index_count as u32;
+        }
+        _ => draws.push(DrawRange {first_index: first_index as u32, index_count: index_count as u32, origin}),
+    }
+}
+
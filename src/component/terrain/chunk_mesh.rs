@@ -1,15 +1,18 @@
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+use std::mem;
 use uom::num_traits::Float;
 use uom::si::f32::Length;
 use uom::si::Unit;
 use crate::component::camera::{Length3D};
-use crate::component::RenderDataPurpose;
+use crate::component::{DrawRange, RenderDataPurpose};
 use crate::component::terrain::FaceDir;
+use crate::component::terrain::chunk_cache::{ChunkKey, SharedChunkCache};
+use crate::measurement::blox;
 
 
-pub(crate) trait BlockLengthUnit: uom::si::length::Unit + uom::Conversion<f32, T = f32> {}
+pub trait BlockLengthUnit: uom::si::length::Unit + uom::Conversion<f32, T = f32> {}
 impl<T> BlockLengthUnit for T where T: uom::si::length::Unit + uom::Conversion<f32, T = f32> {}
 
 
@@ -18,9 +21,10 @@ pub trait ChunkGeneratable {
     type B: BlockLengthUnit;  // empty inner radius
     type V;
     type I;
-    fn generate_mesh(&self, pos: Length3D) -> Vec<(Vec<Self::V>, Vec<Self::I>, Option<FaceDir>, RenderDataPurpose)>;
+    // origin is the block coordinate of the chunk corner
+    fn generate_mesh(&self, origin: [i64; 3]) -> Vec<(Vec<Self::V>, Vec<Self::I>, Option<FaceDir>, RenderDataPurpose)>;
     fn aggregate_mesh(&self, central_pos: Length3D, chunks: &HashMap<Position<Self::B>, Chunk<Self::V, Self::I, Self::B>>)
-        -> Vec<(Vec<Self::V>, Vec<Self::I>, RenderDataPurpose)>;
+        -> Vec<(Vec<Self::V>, Vec<Self::I>, Vec<DrawRange>, RenderDataPurpose)>;
 }
 
 
@@ -72,6 +76,23 @@ impl<M: BlockLengthUnit> Position<M> {
     fn right(self) -> Self { Self { x: self.x+1, y: self.y, z: self.z, _measure: PhantomData } }
     fn front(self) -> Self { Self { x: self.x, y: self.y, z: self.z+1, _measure: PhantomData } }
     fn back(self) -> Self { Self { x: self.x, y: self.y, z: self.z-1, _measure: PhantomData } }
+
+    // integer block coordinate of the chunk corner, exact regardless of the distance to the world origin
+    pub(crate) fn origin_block(&self) -> [i64; 3] {
+        let size = Length::new::<M>(1.0).get::<blox>() as i64;
+        [self.x as i64*size, self.y as i64*size, self.z as i64*size]
+    }
+
+    // min and max corner of the chunk
+    fn bounds(&self) -> (Length3D, Length3D) {
+        let min = Length3D::new(
+            Length::new::<M>(self.x as f32), Length::new::<M>(self.y as f32), Length::new::<M>(self.z as f32),
+        );
+        let max = Length3D::new(
+            Length::new::<M>(self.x as f32+1.0), Length::new::<M>(self.y as f32+1.0), Length::new::<M>(self.z as f32+1.0),
+        );
+        (min, max)
+    }
 }
 
 
@@ -81,7 +102,7 @@ pub enum UpdateChunk {
 }
 
 // border_radius, update_radius
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ChunkRadius(pub u32, pub u32);
 
 
@@ -99,6 +120,10 @@ pub struct ChunkMesh<G: ChunkGeneratable> {
     generator: G,
     chunks: HashMap<Position<G::B>, Chunk<G::V, G::I, G::B>>,
     chunk_adjacency: Vec<ChunkAdjacency<G::B>>,
+    load_queue: Vec<(Position<G::B>, Length3D)>,  // missing chunks in range, see load_next()
+    queue_sorted: bool,
+    forward: Option<(f32, f32)>,  // horizontal facing of the user, chunks in front of them load first
+    cache: Option<SharedChunkCache<G::V, G::I>>,  // unloaded chunks go there instead of being dropped
 }
 
 impl<G: ChunkGeneratable> ChunkMesh<G> {
@@ -119,6 +144,10 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
             generator,
             chunks: HashMap::new(),
             chunk_adjacency: Vec::new(),
+            load_queue: Vec::new(),
+            queue_sorted: true,
+            forward: None,
+            cache: None,
         }
     }
 
@@ -126,6 +155,10 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
         self.generator = generator;
     }
 
+    pub(crate) fn set_cache(&mut self, cache: SharedChunkCache<G::V, G::I>) {
+        self.cache.replace(cache);
+    }
+
     pub fn update(&mut self, mode: UpdateChunk) -> bool {
         let mut outer_chunk_update = false;
         let mut inner_chunk_update = false;
@@ -154,6 +187,9 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
             // println!("CENTRAL POS {:?}", self.central_pos);
             if outer_chunk_update {
                 self.reset_chunk_visibility();
+                // rebuilt from scratch, so chunks that went out of range before being loaded are dropped
+                self.load_queue.clear();
+                self.queue_sorted = false;
             }
 
             for cx in -self.subchunk_outer_radius..self.subchunk_outer_radius {
@@ -168,17 +204,17 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
                         if outer_chunk_update {
 
                             if let Some(chunk) = self.chunks.get_mut(&Position::from(chunk_pos)) {
-                                if !chunk.visible {
-                                    chunk.visible = true;
-                                    chunk_changed = true;
-                                }
+                                let was_visible = chunk.visible();
+                                chunk.in_range = true;
+                                chunk_changed |= was_visible != chunk.visible();
                             } else {
                                 // chunk at new_chunk_pos does not exist (needs to be created) for all chunks regardless whether its inner
+                                //  it is only queued here, load_next() generates it once the frame budget allows
 
                                 if chunk_pos.x.get::<G::A>() % 1.0 == 0.0 &&
                                     chunk_pos.y.get::<G::A>() % 2.0 == 0.0 &&
                                     chunk_pos.z.get::<G::A>() % 2.0 == 0.0 {
-                                    println!("New chunk loaded [{} {} {} <{}>]",
+                                    println!("New chunk queued [{} {} {} <{}>]",
                                              chunk_pos.x.get::<G::A>(),
                                              chunk_pos.y.get::<G::A>(),
                                              chunk_pos.z.get::<G::A>(),
@@ -186,37 +222,17 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
                                     );
                                 }
 
-                                self.load_chunk(chunk_pos);
-                                chunk_changed = true;
+                                self.load_queue.push((Position::from(chunk_pos), chunk_pos));
                             }
                         }
-                        // in the niche case when forced to start, inner chunk sets EXISTING inner chunks to invisible
-                        //  hence, it needs to be after it is generated only in this niche case
+                        // chunks inside the inner radius are 'removed', the ones that left it are added again
                         if inner_chunk_update {
-                            if let Some(chunk) = self.chunks.get_mut(&Position::from(chunk_pos)) {
-                                if
-                                    Self::check_inside_radius::<G::B>(&self.inner_central_pos.x, &chunk.pos.x, self.chunk_inner_radius) &&
-                                    Self::check_inside_radius::<G::B>(&self.inner_central_pos.y, &chunk.pos.y, self.chunk_inner_radius) &&
-                                    Self::check_inside_radius::<G::B>(&self.inner_central_pos.z, &chunk.pos.z, self.chunk_inner_radius) {
-                                    // chunk inside inner radius (needs to be 'removed')
-                                    if chunk.visible {
-                                        chunk.visible = false;
-                                        chunk_changed = true;
-                                    }
-                                    // println!("Set chunk to INVISIBLE [{} {} {} <{}>]",
-                                    //          &chunk.pos.x.get::<G::A>(),
-                                    //          &chunk.pos.y.get::<G::A>(),
-                                    //          &chunk.pos.z.get::<G::A>(),
-                                    //          G::A::abbreviation(),
-                                    // );
-                                } else if !chunk.visible {
-                                    // not visible but not inside the inner radius? (needs to be added again)
-                                    // assumes all the chunks are generated since inner radius is inside the border radius
-                                    //  where all the chunk generation happens
-                                    // if not, it will just result in empty chunks somehow inside rest of the chunks
-                                    chunk.visible = true;
-                                    chunk_changed = true;
-                                }
+                            let hash_pos = Position::from(chunk_pos);
+                            let hollow = self.chunks.get(&hash_pos).map(|chunk| self.inside_inner_radius(chunk.pos));
+                            if let (Some(chunk), Some(hollow)) = (self.chunks.get_mut(&hash_pos), hollow) {
+                                let was_visible = chunk.visible();
+                                chunk.hollow = hollow;
+                                chunk_changed |= was_visible != chunk.visible();
                             }
                         }
                     }
@@ -224,6 +240,16 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
             }
 
             if outer_chunk_update {
+                // whatever is left outside the border is handed over to the cache
+                let out_of_range = self.chunks.values()
+                    .filter(|chunk| !chunk.in_range)
+                    .map(|chunk| chunk.hash_pos)
+                    .collect::<Vec<_>>();
+                chunk_changed |= !out_of_range.is_empty();
+                for hash_pos in out_of_range {
+                    self.unload_chunk(hash_pos);
+                }
+
                 println!("CHUNK NEED UPDATE: BORDER {:?}", G::A::abbreviation());
             }
             if inner_chunk_update {
@@ -238,7 +264,7 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
 
     fn reset_chunk_visibility(&mut self) {
         for v in self.chunks.values_mut() {
-            v.visible = false;
+            v.in_range = false;
         }
     }
 
@@ -272,19 +298,145 @@ impl<G: ChunkGeneratable> ChunkMesh<G> {
             c.adjacency.front.replace(hash_pos);
         }
 
-        let mesh = self.generator.generate_mesh(pos);
+        let cached = self.cache.as_ref().and_then(|cache| cache.borrow_mut().take(&Self::cache_key(hash_pos)));
+        let mesh = cached.unwrap_or_else(|| self.generator.generate_mesh(hash_pos.origin_block()));
 
-        self.chunks.insert(
-            hash_pos, Chunk::new(pos, hash_pos, adj, mesh)
-        );
+        let mut chunk = Chunk::new(pos, hash_pos, adj, mesh);
+        chunk.hollow = self.inside_inner_radius(pos);
+        self.chunks.insert(hash_pos, chunk);
+    }
+
+    // distance to the central position, stretched up to twice for chunks behind the user
+    fn load_priority(&self, pos: Length3D) -> f32 {
+        let half = Length::new::<G::B>(0.5);
+        let dx = (pos.x+half-self.central_pos.x).get::<blox>();
+        let dy = (pos.y+half-self.central_pos.y).get::<blox>();
+        let dz = (pos.z+half-self.central_pos.z).get::<blox>();
+        let dist = (dx*dx+dy*dy+dz*dz).sqrt();
+
+        let alignment = match self.forward {
+            Some((fx, fz)) if dist > 0.0 => (dx*fx+dz*fz)/dist,
+            _ => 0.0,
+        };
+        dist*(1.5-0.5*alignment)
     }
 
-    fn unload_chunk(&mut self) {
+    fn sort_queue(&mut self) {
+        if !self.queue_sorted {
+            // most urgent last, so loading pops from the back
+            let mut queue = mem::take(&mut self.load_queue).into_iter()
+                .map(|queued| (self.load_priority(queued.1), queued))
+                .collect::<Vec<_>>();
+            queue.sort_by(|(a, _), (b, _)| b.total_cmp(a));
 
+            self.load_queue = queue.into_iter().map(|(_, queued)| queued).collect();
+            self.queue_sorted = true;
+        }
+    }
+
+    pub(crate) fn set_forward(&mut self, forward: Option<(f32, f32)>) {
+        if self.forward != forward {
+            self.forward = forward;
+            self.queue_sorted = false;
+        }
+    }
+
+    pub fn queued(&self) -> usize {
+        self.load_queue.len()
+    }
+
+    // priority of the next chunk to be loaded, lower is more urgent
+    pub fn next_priority(&mut self) -> Option<f32> {
+        self.sort_queue();
+        self.load_queue.last().map(|(_, pos)| self.load_priority(*pos))
+    }
+
+    // generates the most urgent queued chunk, false if nothing is left to load
+    pub fn load_next(&mut self) -> bool {
+        self.sort_queue();
+        match self.load_queue.pop() {
+            Some((_, pos)) => {
+                self.load_chunk(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn load_all(&mut self) -> bool {
+        let mut loaded = false;
+        while self.load_next() {
+            loaded = true;
+        }
+        loaded
+    }
+
+    // whether a chunk overlapping the given bounds is still waiting to be loaded
+    pub(crate) fn pending_in(&self, min: Length3D, max: Length3D) -> bool {
+        self.load_queue.iter().any(|(hash_pos, _)| {
+            let (chunk_min, chunk_max) = hash_pos.bounds();
+            chunk_min.x < max.x && min.x < chunk_max.x &&
+                chunk_min.y < max.y && min.y < chunk_max.y &&
+                chunk_min.z < max.z && min.z < chunk_max.z
+        })
+    }
+
+    // chunks inside the inner radius stay visible as placeholders until the finer ring filling that
+    // radius has loaded the same area
+    pub(crate) fn update_placeholders(&mut self, pending: &dyn Fn(Length3D, Length3D) -> bool) -> bool {
+        let mut changed = false;
+        for chunk in self.chunks.values_mut().filter(|c| c.in_range && c.hollow) {
+            let (min, max) = chunk.hash_pos.bounds();
+            let placeholder = pending(min, max);
+            changed |= chunk.placeholder != placeholder;
+            chunk.placeholder = placeholder;
+        }
+        changed
+    }
+
+    fn inside_inner_radius(&self, pos: Length3D) -> bool {
+        Self::check_inside_radius::<G::B>(&self.inner_central_pos.x, &pos.x, self.chunk_inner_radius) &&
+            Self::check_inside_radius::<G::B>(&self.inner_central_pos.y, &pos.y, self.chunk_inner_radius) &&
+            Self::check_inside_radius::<G::B>(&self.inner_central_pos.z, &pos.z, self.chunk_inner_radius)
+    }
+
+    fn unload_chunk(&mut self, hash_pos: Position<G::B>) {
+        if let Some(chunk) = self.chunks.remove(&hash_pos) {
+            let adj = chunk.adjacency;
+            if let Some(c) = adj.top.and_then(|p| self.chunks.get_mut(&p)) { c.adjacency.bottom = None; }
+            if let Some(c) = adj.bottom.and_then(|p| self.chunks.get_mut(&p)) { c.adjacency.top = None; }
+            if let Some(c) = adj.left.and_then(|p| self.chunks.get_mut(&p)) { c.adjacency.right = None; }
+            if let Some(c) = adj.right.and_then(|p| self.chunks.get_mut(&p)) { c.adjacency.left = None; }
+            if let Some(c) = adj.front.and_then(|p| self.chunks.get_mut(&p)) { c.adjacency.back = None; }
+            if let Some(c) = adj.back.and_then(|p| self.chunks.get_mut(&p)) { c.adjacency.front = None; }
+
+            if let Some(cache) = &self.cache {
+                cache.borrow_mut().insert(Self::cache_key(hash_pos), chunk.mesh);
+            }
+        }
+    }
+
+    fn cache_key(hash_pos: Position<G::B>) -> ChunkKey {
+        ChunkKey {
+            fidelity: Length::new::<G::B>(1.0).get::<blox>() as u32,
+            x: hash_pos.x, y: hash_pos.y, z: hash_pos.z,
+        }
+    }
+
+    // grows or shrinks the rings in place: chunks still in range are kept, only the new shell gets
+    // queued and the chunks beyond the new border are unloaded
+    pub fn resize(&mut self, outer: ChunkRadius, inner: Option<ChunkRadius>) -> bool {
+        self.chunk_outer_radius = outer.0 as i32;
+        self.chunk_outer_update_radius = outer.1 as f32;
+        self.subchunk_outer_radius = Length::new::<G::A>(outer.0 as f32).get::<G::B>() as i32;
+        self.chunk_inner_radius = inner.map(| ChunkRadius(border, _) | border as f32);
+        self.chunk_inner_update_radius = inner.map(| ChunkRadius(_, update) | update as f32);
+
+        self.update(UpdateChunk::Forced)
     }
 
     // generate the entire aggregated vertices/indices
-    pub(crate) fn generate_vertices(&mut self) -> Vec<(Vec<G::V>, Vec<G::I>, RenderDataPurpose)> {
+    pub(crate) fn generate_vertices(&mut self) -> Vec<(Vec<G::V>, Vec<G::I>, Vec<DrawRange>, RenderDataPurpose)> {
         // TODO: pass in the chunks relative direction to central pos (for face culling)
         self.generator.aggregate_mesh(self.central_pos, &self.chunks)
     }
@@ -319,7 +471,9 @@ pub(crate) struct Chunk<V, I, M: BlockLengthUnit> {
     pub(crate) hash_pos: Position<M>,
     pub(crate) adjacency: ChunkAdjacency<M>,
     pub(crate) mesh: Vec<(Vec<V>, Vec<I>, Option<FaceDir>, RenderDataPurpose)>,
-    visible: bool,
+    in_range: bool,
+    hollow: bool,  // inside the inner radius
+    placeholder: bool,  // still covering for the finer ring while hollow
 }
 
 impl<V, I, M: BlockLengthUnit> Chunk<V, I, M> {
@@ -330,9 +484,123 @@ impl<V, I, M: BlockLengthUnit> Chunk<V, I, M> {
         mesh: Vec<(Vec<V>, Vec<I>, Option<FaceDir>, RenderDataPurpose)>,
     ) -> Self {
         Self {
-            pos, hash_pos, adjacency: init_adjs, mesh, visible: true,
+            pos, hash_pos, adjacency: init_adjs, mesh, in_range: true, hollow: false, placeholder: false,
+        }
+    }
+
+    pub(crate) fn visible(&self) -> bool {self.in_range && (!self.hollow || self.placeholder)}
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use crate::component::terrain::chunk_cache::ChunkCache;
+    use crate::measurement::chux;
+    use super::*;
+
+    // counts the generated chunks instead of meshing anything
+    struct CountingGenerator {
+        generated: Cell<usize>,
+    }
+
+    impl ChunkGeneratable for CountingGenerator {
+        type A = chux;
+        type B = chux;
+        type V = ();
+        type I = ();
+
+        fn generate_mesh(&self, _origin: [i64; 3]) -> Vec<(Vec<()>, Vec<()>, Option<FaceDir>, RenderDataPurpose)> {
+            self.generated.set(self.generated.get()+1);
+            vec![]
+        }
+
+        fn aggregate_mesh(&self, _central_pos: Length3D, _chunks: &HashMap<Position<chux>, Chunk<(), (), chux>>)
+            -> Vec<(Vec<()>, Vec<()>, Vec<DrawRange>, RenderDataPurpose)> {
+            vec![]
         }
     }
 
-    pub(crate) fn visible(&self) -> bool {self.visible}
+    fn visible_count(mesh: &ChunkMesh<CountingGenerator>) -> usize {
+        mesh.chunks.values().filter(|c| c.visible()).count()
+    }
+
+    #[test]
+    fn resize_keeps_chunks_in_range() {
+        let pos = Length3D::new(Length::new::<chux>(0.5), Length::new::<chux>(0.5), Length::new::<chux>(0.5));
+        let mut mesh = ChunkMesh::new(pos, ChunkRadius(1, 1), None, CountingGenerator {generated: Cell::new(0)});
+        let cache = Rc::new(RefCell::new(ChunkCache::new(1024)));
+        mesh.set_cache(cache.clone());
+        mesh.update(UpdateChunk::Forced);
+        assert!(mesh.load_all());
+        assert_eq!(mesh.chunks.len(), 8);
+        assert_eq!(mesh.generator.generated.get(), 8);
+
+        // only the new shell is generated
+        mesh.resize(ChunkRadius(2, 1), Some(ChunkRadius(1, 1)));
+        assert_eq!(mesh.queued(), 56);
+        assert!(mesh.load_all());
+        assert_eq!(mesh.chunks.len(), 64);
+        assert_eq!(mesh.generator.generated.get(), 64);
+        assert_eq!(visible_count(&mesh), 56);
+
+        // shrinking unloads everything outside and regenerates nothing
+        assert!(mesh.resize(ChunkRadius(1, 1), None));
+        assert!(!mesh.load_all());
+        assert_eq!(mesh.chunks.len(), 8);
+        assert_eq!(visible_count(&mesh), 8);
+        assert_eq!(mesh.generator.generated.get(), 64);
+        for chunk in mesh.chunks.values() {
+            let adj = chunk.adjacency;
+            let neighbours = [adj.top, adj.bottom, adj.left, adj.right, adj.front, adj.back];
+            assert!(neighbours.iter().flatten().all(|p| mesh.chunks.contains_key(p)));
+            assert_eq!(neighbours.iter().flatten().count(), 3);
+        }
+
+        // and growing back again is served from the cache
+        assert_eq!(cache.borrow().stats().entries, 56);
+        mesh.resize(ChunkRadius(2, 1), None);
+        assert!(mesh.load_all());
+        assert_eq!(mesh.generator.generated.get(), 64);
+        assert_eq!(cache.borrow().stats().hits, 56);
+        assert_eq!(cache.borrow().stats().misses, 64);
+    }
+
+    #[test]
+    fn load_queue_order_and_placeholders() {
+        let pos = Length3D::new(Length::new::<chux>(0.5), Length::new::<chux>(0.5), Length::new::<chux>(0.5));
+        let mut mesh = ChunkMesh::new(pos, ChunkRadius(2, 1), Some(ChunkRadius(1, 1)), CountingGenerator {generated: Cell::new(0)});
+        mesh.set_forward(Some((1.0, 0.0)));
+        mesh.update(UpdateChunk::Forced);
+        assert!(mesh.chunks.is_empty());
+
+        // closest first, ties broken in favour of the facing direction
+        let mut last = 0.0;
+        let mut loaded = Vec::new();
+        while let Some(priority) = mesh.next_priority() {
+            assert!(last <= priority);
+            last = priority;
+            let queued = mesh.load_queue.last().unwrap().0;
+            assert!(mesh.load_next());
+            loaded.push(queued);
+        }
+        assert_eq!(loaded.len(), 64);
+        assert!(loaded[..8].iter().all(|p| (-1..=0).contains(&p.x) && (-1..=0).contains(&p.y) && (-1..=0).contains(&p.z)));
+        assert!(loaded[..4].iter().all(|p| p.x == 0));
+        assert_eq!(loaded[8].x, 1);
+        assert_eq!(visible_count(&mesh), 56);
+
+        // the hollow chunks cover for whatever is still pending there
+        let origin = Position::<chux>::default();
+        assert!(mesh.update_placeholders(&|min, max| {
+            let (chunk_min, chunk_max) = origin.bounds();
+            chunk_min.x < max.x && min.x < chunk_max.x && chunk_min.y < max.y && min.y < chunk_max.y &&
+                chunk_min.z < max.z && min.z < chunk_max.z
+        }));
+        assert_eq!(visible_count(&mesh), 57);
+        assert!(mesh.chunks[&origin].visible());
+        assert!(mesh.update_placeholders(&|_, _| false));
+        assert_eq!(visible_count(&mesh), 56);
+    }
 }
@@ -0,0 +1,224 @@
+use std::rc::Rc;
+use crate::component::camera::Length3D;
+use crate::component::{DrawRange, RenderDataPurpose};
+use crate::component::terrain::BlockData;
+use crate::component::terrain::chunk_cache::SharedChunkCache;
+use crate::component::terrain::chunk_gen::ChunkGenerator;
+use crate::component::terrain::chunk_mesh::{BlockLengthUnit, ChunkGeneratable, ChunkMesh, ChunkRadius, UpdateChunk};
+use crate::component::terrain::terrain_gen::TerrainGenerator;
+use crate::component::texture::TextureIDMapper;
+use crate::measurement::{chux, chux_hf, chux_lf, chux_mf, chux_minf};
+use crate::shader::chunk::PackedChunkVertex;
+
+
+// the aggregated meshes of a ring, one entry per render purpose
+pub(crate) type RingRenderData = Vec<(Vec<PackedChunkVertex>, Vec<u32>, Vec<DrawRange>, RenderDataPurpose)>;
+
+// the chunk units of measurement.rs, from the finest to the coarsest
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChunkUnit {
+    Chux,
+    ChuxHF,
+    ChuxMF,
+    ChuxLF,
+    ChuxMinF,
+}
+
+// the chunk units a ring can be meshed in, the coarser ones are too large for packed vertices
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChunkSize {
+    Chux,
+    ChuxHF,
+    ChuxMF,
+}
+
+impl ChunkSize {
+    pub(crate) fn unit(self) -> ChunkUnit {
+        match self {
+            ChunkSize::Chux => ChunkUnit::Chux,
+            ChunkSize::ChuxHF => ChunkUnit::ChuxHF,
+            ChunkSize::ChuxMF => ChunkUnit::ChuxMF,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GeneratorKind {
+    Voxel,  // block meshes straight from the terrain generator
+}
+
+// a single level of detail around the user. the outer radius is measured in radius_unit while the
+// inner (hollow) radius, which is left to the finer rings, is measured in chunk_unit
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LodRing {
+    pub radius_unit: ChunkUnit,
+    pub chunk_unit: ChunkSize,
+    pub outer: ChunkRadius,
+    pub inner: Option<ChunkRadius>,
+    pub generator: GeneratorKind,
+}
+
+impl LodRing {
+    // radii are given for this render distance and scale linearly with it
+    pub const REFERENCE_RENDER_DISTANCE: u32 = 4;
+
+    // extreme, high and medium fidelity
+    pub fn default_rings() -> Vec<LodRing> {
+        vec![
+            LodRing {
+                radius_unit: ChunkUnit::Chux, chunk_unit: ChunkSize::Chux,
+                outer: ChunkRadius(4, 2), inner: None,
+                generator: GeneratorKind::Voxel,
+            },
+            LodRing {
+                radius_unit: ChunkUnit::ChuxHF, chunk_unit: ChunkSize::Chux,
+                outer: ChunkRadius(4, 2), inner: Some(ChunkRadius(4, 2)),
+                generator: GeneratorKind::Voxel,
+            },
+            LodRing {
+                radius_unit: ChunkUnit::ChuxMF, chunk_unit: ChunkSize::ChuxHF,
+                outer: ChunkRadius(2, 1), inner: Some(ChunkRadius(4, 2)),
+                generator: GeneratorKind::Voxel,
+            },
+        ]
+    }
+
+    pub(crate) fn scaled(&self, render_distance: u32) -> Self {
+        let scale = |ChunkRadius(border, update): ChunkRadius| ChunkRadius(
+            (border*render_distance/Self::REFERENCE_RENDER_DISTANCE).max(1),
+            (update*render_distance/Self::REFERENCE_RENDER_DISTANCE).max(1),
+        );
+
+        Self {
+            outer: scale(self.outer),
+            inner: self.inner.map(scale),
+            ..*self
+        }
+    }
+
+    pub(crate) fn build<'b>(
+        &self, pos: Length3D, block_ind: Vec<BlockData<'b>>, txtr_mapper: TextureIDMapper, terrain_gen: Rc<TerrainGenerator>,
+        cache: SharedChunkCache<PackedChunkVertex, u32>,
+    ) -> Box<dyn LodMesh + 'b> {
+        assert!(self.chunk_unit.unit() <= self.radius_unit, "The chunks of a LOD ring cannot be larger than its radius unit: {self:?}");
+
+        match self.generator {
+            GeneratorKind::Voxel => match self.radius_unit {
+                ChunkUnit::Chux => self.build_voxel::<chux>(pos, block_ind, txtr_mapper, terrain_gen, cache),
+                ChunkUnit::ChuxHF => self.build_voxel::<chux_hf>(pos, block_ind, txtr_mapper, terrain_gen, cache),
+                ChunkUnit::ChuxMF => self.build_voxel::<chux_mf>(pos, block_ind, txtr_mapper, terrain_gen, cache),
+                ChunkUnit::ChuxLF => self.build_voxel::<chux_lf>(pos, block_ind, txtr_mapper, terrain_gen, cache),
+                ChunkUnit::ChuxMinF => self.build_voxel::<chux_minf>(pos, block_ind, txtr_mapper, terrain_gen, cache),
+            }
+        }
+    }
+
+    fn build_voxel<'b, A: BlockLengthUnit + 'b>(
+        &self, pos: Length3D, block_ind: Vec<BlockData<'b>>, txtr_mapper: TextureIDMapper, terrain_gen: Rc<TerrainGenerator>,
+        cache: SharedChunkCache<PackedChunkVertex, u32>,
+    ) -> Box<dyn LodMesh + 'b> {
+        match self.chunk_unit {
+            ChunkSize::Chux => self.voxel_mesh(ChunkGenerator::<A, chux>::new(block_ind, txtr_mapper, terrain_gen), pos, cache),
+            ChunkSize::ChuxHF => self.voxel_mesh(ChunkGenerator::<A, chux_hf>::new(block_ind, txtr_mapper, terrain_gen), pos, cache),
+            ChunkSize::ChuxMF => self.voxel_mesh(ChunkGenerator::<A, chux_mf>::new(block_ind, txtr_mapper, terrain_gen), pos, cache),
+        }
+    }
+
+    fn voxel_mesh<'b, G: ChunkGeneratable<V=PackedChunkVertex, I=u32> + 'b>(
+        &self, generator: G, pos: Length3D, cache: SharedChunkCache<PackedChunkVertex, u32>,
+    ) -> Box<dyn LodMesh + 'b> {
+        let mut mesh = ChunkMesh::new(pos, self.outer, self.inner, generator);
+        mesh.set_cache(cache);
+        Box::new(mesh)
+    }
+}
+
+
+// type erased ChunkMesh, so that rings of different units can live in the same list
+pub(crate) trait LodMesh {
+    fn update(&mut self, mode: UpdateChunk) -> bool;
+    fn resize(&mut self, outer: ChunkRadius, inner: Option<ChunkRadius>) -> bool;
+    fn set_forward(&mut self, forward: Option<(f32, f32)>);
+    fn next_priority(&mut self) -> Option<f32>;
+    fn load_next(&mut self) -> bool;
+    fn pending_in(&self, min: Length3D, max: Length3D) -> bool;
+    fn update_placeholders(&mut self, pending: &dyn Fn(Length3D, Length3D) -> bool) -> bool;
+    fn generate_vertices(&mut self) -> RingRenderData;
+}
+
+impl<G: ChunkGeneratable<V=PackedChunkVertex, I=u32>> LodMesh for ChunkMesh<G> {
+    fn update(&mut self, mode: UpdateChunk) -> bool {
+        ChunkMesh::update(self, mode)
+    }
+
+    fn resize(&mut self, outer: ChunkRadius, inner: Option<ChunkRadius>) -> bool {
+        ChunkMesh::resize(self, outer, inner)
+    }
+
+    fn set_forward(&mut self, forward: Option<(f32, f32)>) {
+        ChunkMesh::set_forward(self, forward)
+    }
+
+    fn next_priority(&mut self) -> Option<f32> {
+        ChunkMesh::next_priority(self)
+    }
+
+    fn load_next(&mut self) -> bool {
+        ChunkMesh::load_next(self)
+    }
+
+    fn pending_in(&self, min: Length3D, max: Length3D) -> bool {
+        ChunkMesh::pending_in(self, min, max)
+    }
+
+    fn update_placeholders(&mut self, pending: &dyn Fn(Length3D, Length3D) -> bool) -> bool {
+        ChunkMesh::update_placeholders(self, pending)
+    }
+
+    fn generate_vertices(&mut self) -> RingRenderData {
+        ChunkMesh::generate_vertices(self)
+    }
+}
+
+pub(crate) struct LodRingMesh<'b> {
+    pub(crate) mesh: Box<dyn LodMesh + 'b>,
+    pub(crate) needs_update: bool,
+    pub(crate) render_data: RingRenderData,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uom::si::f32::Length;
+    use crate::measurement::blox;
+    use crate::shader::chunk::ChunkVertex;
+
+    #[test]
+    fn scaled_rings() {
+        let rings = LodRing::default_rings();
+        let reference = rings.iter()
+            .map(|ring| ring.scaled(LodRing::REFERENCE_RENDER_DISTANCE))
+            .collect::<Vec<_>>();
+        assert_eq!(reference, rings);
+
+        let doubled = rings[2].scaled(8);
+        assert_eq!(doubled.outer, ChunkRadius(4, 2));
+        assert_eq!(doubled.inner, Some(ChunkRadius(8, 4)));
+
+        // never collapses to an empty ring
+        assert_eq!(rings[2].scaled(1).outer, ChunkRadius(1, 1));
+    }
+
+    #[test]
+    fn chunk_sizes_fit_packed_vertices() {
+        for size in [ChunkSize::Chux, ChunkSize::ChuxHF, ChunkSize::ChuxMF] {
+            let blocks = match size {
+                ChunkSize::Chux => Length::new::<chux>(1.0),
+                ChunkSize::ChuxHF => Length::new::<chux_hf>(1.0),
+                ChunkSize::ChuxMF => Length::new::<chux_mf>(1.0),
+            }.get::<blox>() as u32;
+            assert!(blocks <= ChunkVertex::MAX_CHUNK_SIZE, "{size:?} is too large for packed vertices");
+        }
+    }
+}
@@ -19,7 +19,7 @@ pub(super) trait ChunkMeshUtil<'b> {
         (y*size*size+x*size+z) as usize
     }
 
-    fn voluminous_opaque_cubes_mesh<C>(&self, ofs: (i32, i32, i32), chunk_pos: C) -> [(Vec<ChunkVertex>, Vec<u32>, FaceDir); 6]
+    fn voluminous_opaque_cubes_mesh<C>(&self, ofs: (i64, i64, i64), chunk_pos: C) -> [(Vec<ChunkVertex>, Vec<u32>, FaceDir); 6]
         where C: Fn(u32, u32, u32) -> (f32, f32, f32)
     {
         let mut top_verts = vec![];
@@ -51,19 +51,19 @@ pub(super) trait ChunkMeshUtil<'b> {
         // - note: the height bounds are increased by one (i.e. hb+1u32) since the mesh fill list algo
         //      needs to check one additional block for the closing face (just like the expanded checking of the chunk size)
 
-        let mut xz_max_height_bounds = vec![0i32; (expanded_size*expanded_size) as usize];
+        let mut xz_max_height_bounds = vec![0i64; (expanded_size*expanded_size) as usize];
         let mut min_height_bound = expanded_size;
         let mut max_height_bound = 0u32;
 
         for x in 0..expanded_size {
             for z in 0..expanded_size {
-                let hb = self.terrain_gen().opaque_block_height_bound_test((ofs.0+x as i32) as f64, (ofs.2+z as i32) as f64).ceil() as i32;
+                let hb = self.terrain_gen().opaque_block_height_bound_test((ofs.0+x as i64) as f64, (ofs.2+z as i64) as f64).ceil() as i64;
                 xz_max_height_bounds[(x*expanded_size+z) as usize] = hb;
-                if hb-ofs.1+1 > max_height_bound as i32 {
-                    max_height_bound = (hb-ofs.1+1).clamp(0i32, expanded_size as i32) as u32;
+                if hb-ofs.1+1 > max_height_bound as i64 {
+                    max_height_bound = (hb-ofs.1+1).clamp(0i64, expanded_size as i64) as u32;
                 }
-                if hb-ofs.1 < min_height_bound as i32 {
-                    min_height_bound = (hb-ofs.1).clamp(0i32, expanded_size as i32) as u32;
+                if hb-ofs.1 < min_height_bound as i64 {
+                    min_height_bound = (hb-ofs.1).clamp(0i64, expanded_size as i64) as u32;
                 }
             }
         }
@@ -96,13 +96,13 @@ pub(super) trait ChunkMeshUtil<'b> {
                 }
 
                 for y in min_height_bound..max_height_bound {
-                    let open = ofs.1+y as i32 >= hb;
+                    let open = ofs.1+y as i64 >= hb;
                     let mut xy_cell = &mut xy_grid[(x*expanded_size+y) as usize];
                     let mut yz_cell = &mut yz_grid[(y*expanded_size+z) as usize];
                     // let mut xz_cell = &mut xz_grid[(x*expanded_size+z) as usize];
 
                     let lazy_block_gen = |dx: i32, dy: i32, dz: i32| {
-                        self.terrain_gen().get_block((ofs.0+dx+x as i32) as f64, (ofs.1+dy+y as i32) as f64, (ofs.2+dz+z as i32) as f64)
+                        self.terrain_gen().get_block((ofs.0+(dx+x as i32) as i64) as f64, (ofs.1+(dy+y as i32) as i64) as f64, (ofs.2+(dz+z as i32) as i64) as f64)
                     };
 
                     let mut fast_block_face_gen = |
@@ -212,7 +212,7 @@ pub(super) trait ChunkMeshUtil<'b> {
         ]
     }
 
-    fn sparse_transparent_floral_mesh<C>(&self, ofs: (i32, i32, i32), chunk_pos: C) -> (Vec<ChunkVertex>, Vec<u32>)
+    fn sparse_transparent_floral_mesh<C>(&self, ofs: (i64, i64, i64), chunk_pos: C) -> (Vec<ChunkVertex>, Vec<u32>)
         where C: Fn(u32, u32, u32) -> (f32, f32, f32)
     {
         let mut transparent_verts = vec![];
@@ -221,11 +221,11 @@ pub(super) trait ChunkMeshUtil<'b> {
 
         for x in 0..self.chunk_size() {
             for z in 0..self.chunk_size() {
-                if let Some(y) = self.terrain_gen().floral_existence_bound_test((ofs.0+x as i32) as f64, (ofs.2+z as i32) as f64) {
+                if let Some(y) = self.terrain_gen().floral_existence_bound_test((ofs.0+x as i64) as f64, (ofs.2+z as i64) as f64) {
                     let y = y.ceil();
                     if ofs.1 as f64 <= y && y < ofs.1 as f64+self.chunk_size() as f64 {
                         if let Some(block)
-                            = self.terrain_gen().get_block((ofs.0+x as i32) as f64, (y as i32) as f64, (ofs.2+z as i32) as f64)
+                            = self.terrain_gen().get_block((ofs.0+x as i64) as f64, (y as i64) as f64, (ofs.2+z as i64) as f64)
                         {
                             // assumes floral mesh
 
@@ -234,7 +234,7 @@ pub(super) trait ChunkMeshUtil<'b> {
                             let txtr = block.texture_id;
 
                             let (mut xcross_verts, mut xcross_inds) = self.gen_xcross(
-                                chunk_pos(x, (y as i32-ofs.1) as u32, z), transparent_faces*4, txtr,
+                                chunk_pos(x, (y as i64-ofs.1) as u32, z), transparent_faces*4, txtr,
                             );
                             transparent_verts.append(&mut xcross_verts);
                             transparent_inds.append(&mut xcross_inds);
@@ -250,7 +250,7 @@ pub(super) trait ChunkMeshUtil<'b> {
 
 
     // TODO: TEMPORARY
-    fn temporary_fluid_mesher<C>(&self, ofs: (i32, i32, i32), chunk_pos: C) -> (Vec<ChunkVertex>, Vec<u32>)
+    fn temporary_fluid_mesher<C>(&self, ofs: (i64, i64, i64), chunk_pos: C) -> (Vec<ChunkVertex>, Vec<u32>)
         where C: Fn(u32, u32, u32) -> (f32, f32, f32)
     {
         let mut translucent_verts = vec![];
@@ -267,14 +267,14 @@ pub(super) trait ChunkMeshUtil<'b> {
 
         for x in 0..expanded_size {
             for z in 0..expanded_size {
-                if let Some(hb) = self.terrain_gen().fluid_height_existence_bound_test((ofs.0+x as i32) as f64, (ofs.2+z as i32) as f64) {
-                    let hb = hb.ceil() as i32;
+                if let Some(hb) = self.terrain_gen().fluid_height_existence_bound_test((ofs.0+x as i64) as f64, (ofs.2+z as i64) as f64) {
+                    let hb = hb.ceil() as i64;
                     xz_max_height_bounds[(x*expanded_size+z) as usize] = Some(hb);
-                    if hb-ofs.1+1 > max_height_bound as i32 {
-                        max_height_bound = (hb-ofs.1+1).clamp(0i32, expanded_size as i32) as u32;
+                    if hb-ofs.1+1 > max_height_bound as i64 {
+                        max_height_bound = (hb-ofs.1+1).clamp(0i64, expanded_size as i64) as u32;
                     }
-                    if hb-ofs.1 < min_height_bound as i32 {
-                        min_height_bound = (hb-ofs.1).clamp(0i32, expanded_size as i32) as u32;
+                    if hb-ofs.1 < min_height_bound as i64 {
+                        min_height_bound = (hb-ofs.1).clamp(0i64, expanded_size as i64) as u32;
                     }
                 }
             }
@@ -291,11 +291,11 @@ pub(super) trait ChunkMeshUtil<'b> {
                     }
 
                     for y in min_height_bound..max_height_bound {
-                        let open = ofs.1+y as i32 >= height;
+                        let open = ofs.1+y as i64 >= height;
                         let mut xz_cell = &mut xz_grid[(x*expanded_size+z) as usize];
 
                         let lazy_block_gen = |dx: i32, dy: i32, dz: i32| {
-                            self.terrain_gen().get_block((ofs.0+dx+x as i32) as f64, (ofs.1+dy+y as i32) as f64, (ofs.2+dz+z as i32) as f64)
+                            self.terrain_gen().get_block((ofs.0+(dx+x as i32) as i64) as f64, (ofs.1+(dy+y as i32) as i64) as f64, (ofs.2+(dz+z as i32) as i64) as f64)
                         };
 
                         let mut fast_fluid_face_gen = |
@@ -353,9 +353,8 @@ pub(super) trait ChunkMeshUtil<'b> {
     fn gen_face(&self, loc: (f32, f32, f32), ind_ofs: u32, face: FaceDir, txtr_mapping: TextureMapper, fluid: bool) -> (Vec<ChunkVertex>, Vec<u32>) {
         let txtr_mapper = |name: &str| *self.texture_id_mapper().get(name).unwrap_or(&0) as f32;
 
-        // TODO: encode indent height into the shader itself
         let hgt = if fluid {
-            0.9
+            1.0-ChunkVertex::FLUID_INSET
         } else {
             1.0
         };
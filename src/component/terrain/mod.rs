@@ -1,24 +1,25 @@
 pub mod chunk_gen;
-pub mod chunk_gen_hf;
-pub mod chunk_gen_mf;
 pub mod mesh_util;
 pub mod terrain_gen;
 pub mod chunk_mesh;
+pub mod chunk_cache;
+pub mod lod;
 
+use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 use ash::{Device, vk};
 use noise::NoiseFn;
-use crate::component::terrain::chunk_mesh::{ChunkGeneratable, ChunkMesh, ChunkRadius, UpdateChunk};
-use crate::component::{Component, RenderData, RenderDataPurpose};
+use crate::component::terrain::chunk_cache::{CacheStats, ChunkCache, SharedChunkCache};
+use crate::component::terrain::chunk_mesh::UpdateChunk;
+use crate::component::{Component, DrawRange, RenderData};
 use crate::component::camera::Length3D;
-use crate::component::terrain::chunk_gen::ChunkGeneratorEF;
-use crate::component::terrain::chunk_gen_hf::ChunkGeneratorHF;
-use crate::component::terrain::chunk_gen_mf::ChunkGeneratorMF;
+use crate::component::texture::TextureIDMapper;
+use crate::component::terrain::lod::{LodRing, LodRingMesh, RingRenderData};
 use crate::component::terrain::terrain_gen::TerrainGenerator;
-use crate::handler::VulkanInstance;
-use crate::shader::chunk::ChunkVertex;
-use crate::util::{CmdBufContext, create_host_buffer, create_local_buffer};
-use crate::world::WorldEvent;
+use crate::shader::chunk::PackedChunkVertex;
+use crate::transfer::UploadQueue;
+use crate::world::{CardinalDir, WorldEvent};
 
 
 #[derive(Copy, Clone, Debug)]
@@ -121,41 +122,109 @@ pub struct Block(u16);
 
 
 pub(crate) struct Terrain<'b> {
-    vi: Rc<VulkanInstance>,
     device: Rc<Device>,
-    ctx: CmdBufContext,
+    uploads: Rc<UploadQueue>,
 
     block_ind: Vec<BlockData<'b>>,
 
     terrain_gen: Rc<TerrainGenerator>,
 
-    chunk_mesh_ef: Option<ChunkMesh<ChunkGeneratorEF<'b>>>,
-    chunk_mesh_hf: Option<ChunkMesh<ChunkGeneratorHF<'b>>>,
-    chunk_mesh_mf: Option<ChunkMesh<ChunkGeneratorMF<'b>>>,
-    chunk_update_ef: bool,
-    chunk_update_hf: bool,
-    chunk_update_mf: bool,
-    chunk_rd_ef: Vec<(Vec<ChunkVertex>, Vec<u32>, RenderDataPurpose)>,
-    chunk_rd_hf: Vec<(Vec<ChunkVertex>, Vec<u32>, RenderDataPurpose)>,
-    chunk_rd_mf: Vec<(Vec<ChunkVertex>, Vec<u32>, RenderDataPurpose)>,
+    lod_rings: Vec<LodRing>,
+    chunk_cache: SharedChunkCache<PackedChunkVertex, u32>,  // shared by all rings
+    cache_stats: CacheStats,  // last reported
+    ring_meshes: Vec<LodRingMesh<'b>>,  // one per LOD ring, built once the texture mapper is known
     to_render: Vec<RenderData>,
+    pending_uploads: Vec<(u64, Vec<RenderData>)>,  // upload ticket, buffers to hand over once it completes
 
     spectator_mode: bool,
+    render_distance: u32,
+    txtr_mapper: Option<TextureIDMapper>,
+    user_pos: Length3D,
+    user_dir: CardinalDir,
 }
 
 impl<'b> Terrain<'b> {
-    pub(crate) fn new(vi: Rc<VulkanInstance>, device: Rc<Device>, ctx: CmdBufContext, block_ind: Vec<BlockData<'b>>) -> Self {
+    pub(crate) fn new(
+        device: Rc<Device>, uploads: Rc<UploadQueue>, seed: u32, render_distance: u32, lod_rings: Vec<LodRing>,
+        chunk_cache_capacity: usize, block_ind: Vec<BlockData<'b>>,
+    ) -> Self {
         Self {
-            vi, device, ctx: ctx.clone(),
+            device, uploads,
             block_ind,
-            terrain_gen: Rc::new(TerrainGenerator::new()),
-            chunk_mesh_ef: None, chunk_mesh_mf: None, chunk_mesh_hf: None,
-            chunk_update_ef: true, chunk_update_hf: true, chunk_update_mf: true,
-            chunk_rd_ef: vec![], chunk_rd_hf: vec![], chunk_rd_mf: vec![],
+            terrain_gen: Rc::new(TerrainGenerator::new(seed)),
+            lod_rings,
+            chunk_cache: Rc::new(RefCell::new(ChunkCache::new(chunk_cache_capacity))),
+            cache_stats: CacheStats::default(),
+            ring_meshes: vec![],
             to_render: vec![],
+            pending_uploads: vec![],
             spectator_mode: false,
+            render_distance,
+            txtr_mapper: None,
+            user_pos: Length3D::origin(),
+            user_dir: CardinalDir::UNDEFINED,
         }
     }
+
+    // chunks generated per frame at most, whichever of the two limits is hit first
+    const CHUNK_LOAD_BUDGET: usize = 32;
+    const CHUNK_LOAD_TIME: Duration = Duration::from_millis(4);
+
+    // horizontal unit vector in the chunk space the user is moving along when going forward
+    fn forward_axis(dir: CardinalDir) -> Option<(f32, f32)> {
+        match dir {
+            CardinalDir::NORTH => Some((0.0, -1.0)),
+            CardinalDir::EAST => Some((1.0, 0.0)),
+            CardinalDir::SOUTH => Some((0.0, 1.0)),
+            CardinalDir::WEST => Some((-1.0, 0.0)),
+            CardinalDir::UNDEFINED => None,
+        }
+    }
+
+    // drains the load queues of all rings, the most urgent chunk across them first, so that crossing
+    // a chunk border never stalls a frame
+    fn load_queued_chunks(&mut self) {
+        let start = Instant::now();
+        for _ in 0..Self::CHUNK_LOAD_BUDGET {
+            if start.elapsed() > Self::CHUNK_LOAD_TIME {
+                break;
+            }
+
+            let next = self.ring_meshes.iter_mut().enumerate()
+                .filter_map(|(ind, ring)| ring.mesh.next_priority().map(|priority| (ind, priority)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+            let Some((ind, _)) = next else { break };
+
+            let ring = &mut self.ring_meshes[ind];
+            ring.needs_update |= ring.mesh.load_next();
+        }
+
+        // the hollow of a ring keeps being drawn wherever the finer ring inside is still loading
+        for ind in 1..self.ring_meshes.len() {
+            let (finer, coarser) = self.ring_meshes.split_at_mut(ind);
+            let (finer, coarser) = (&finer[ind-1], &mut coarser[0]);
+            coarser.needs_update |= coarser.mesh.update_placeholders(&|min, max| finer.mesh.pending_in(min, max));
+        }
+    }
+
+    // rebuilds every LOD ring around the user and queues all of their chunks, needed for a new texture mapper
+    fn rebuild_chunk_meshes(&mut self) {
+        let txtr_mapper = self.txtr_mapper.clone().unwrap();
+        self.chunk_cache.borrow_mut().clear();
+
+        self.ring_meshes = self.lod_rings.iter()
+            .map(|ring| {
+                let mut mesh = ring.scaled(self.render_distance).build(
+                    self.user_pos, self.block_ind.clone(), txtr_mapper.clone(), self.terrain_gen.clone(),
+                    self.chunk_cache.clone(),
+                );
+                mesh.set_forward(Self::forward_axis(self.user_dir));
+                mesh.update(UpdateChunk::Forced);
+
+                LodRingMesh {mesh, needs_update: true, render_data: vec![]}
+            })
+            .collect();
+    }
 }
 
 impl Component for Terrain<'static> {
@@ -167,53 +236,40 @@ impl Component for Terrain<'static> {
     fn respond_event(&mut self, event: WorldEvent) -> Vec<WorldEvent> {
         match event {
             WorldEvent::UserPosition(pos) if !self.spectator_mode => {
-                if let Some(ref mut chunk_mesh) = self.chunk_mesh_ef {
-                    let need_update = chunk_mesh.update(UpdateChunk::NewPos(pos));
-                    self.chunk_update_ef = self.chunk_update_ef || need_update;
-                }
-                if let Some(ref mut chunk_mesh) = self.chunk_mesh_hf {
-                    let need_update = chunk_mesh.update(UpdateChunk::NewPos(pos));
-                    self.chunk_update_hf = self.chunk_update_hf || need_update;
+                self.user_pos = pos;
+                for ring in &mut self.ring_meshes {
+                    let need_update = ring.mesh.update(UpdateChunk::NewPos(pos));
+                    ring.needs_update = ring.needs_update || need_update;
                 }
-                if let Some(ref mut chunk_mesh) = self.chunk_mesh_mf {
-                    let need_update = chunk_mesh.update(UpdateChunk::NewPos(pos));
-                    self.chunk_update_mf = self.chunk_update_mf || need_update;
+            }
+            WorldEvent::UserFaceDir(dir) if !self.spectator_mode => {
+                self.user_dir = dir;
+                for ring in &mut self.ring_meshes {
+                    ring.mesh.set_forward(Self::forward_axis(dir));
                 }
             }
             WorldEvent::NewTextureMapper(txtr_mapper) => {
-                let mut chunk_mesh_ef = ChunkMesh::new(
-                    Length3D::origin(),
-                    ChunkRadius(4, 2), None,
-                    ChunkGeneratorEF::new(
-                        self.block_ind.clone(), txtr_mapper.clone(), self.terrain_gen.clone()
-                    ),
-                );
-                chunk_mesh_ef.update(UpdateChunk::Forced);
-                self.chunk_mesh_ef.replace(chunk_mesh_ef);
-
-                let mut chunk_mesh_hf = ChunkMesh::new(
-                    Length3D::origin(),
-                    ChunkRadius(4, 2), Some(ChunkRadius(4, 2)),
-                    ChunkGeneratorHF::new(
-                        self.block_ind.clone(), txtr_mapper.clone(), self.terrain_gen.clone()
-                    ),
-                );
-                chunk_mesh_hf.update(UpdateChunk::Forced);
-                self.chunk_mesh_hf.replace(chunk_mesh_hf);
-
-                let mut chunk_mesh_mf = ChunkMesh::new(
-                    Length3D::origin(),
-                    ChunkRadius(2, 1), Some(ChunkRadius(4, 2)),
-                    ChunkGeneratorMF::new(
-                        self.block_ind.clone(), txtr_mapper.clone(), self.terrain_gen.clone()
-                    ),
-                );
-                chunk_mesh_mf.update(UpdateChunk::Forced);
-                self.chunk_mesh_mf.replace(chunk_mesh_mf);
+                self.txtr_mapper.replace(txtr_mapper);
+                self.rebuild_chunk_meshes();
+            }
+            WorldEvent::SettingsChanged(settings) if settings.render_distance != self.render_distance => {
+                self.render_distance = settings.render_distance;
+                // the rings keep whatever is still in range and only mesh the difference
+                for (ring, ring_mesh) in self.lod_rings.iter().zip(self.ring_meshes.iter_mut()) {
+                    let ring = ring.scaled(self.render_distance);
+                    ring_mesh.needs_update |= ring_mesh.mesh.resize(ring.outer, ring.inner);
+                }
             }
             WorldEvent::SpectatorMode(enabled) => {
                 self.spectator_mode = enabled;
             }
+            WorldEvent::Tick => {
+                let stats = self.chunk_cache.borrow().stats();
+                if stats != self.cache_stats {
+                    self.cache_stats = stats;
+                    return vec![WorldEvent::ChunkCacheStats(stats)];
+                }
+            }
             _ => {}
         }
 
@@ -224,99 +280,90 @@ impl Component for Terrain<'static> {
         self.to_render.clear();
 
         let mut any_chunk_update = false;
-        let mut render_data: Vec<(Vec<ChunkVertex>, Vec<u32>, RenderDataPurpose)> = Vec::new();
+        let mut render_data: RingRenderData = Vec::new();
 
-        let mut data_aggregator = |rd: Vec<(Vec<ChunkVertex>, Vec<u32>, RenderDataPurpose)>| {
-            for (mut verts, inds, purpose) in rd {
+        let mut data_aggregator = |rd: RingRenderData| {
+            for (mut verts, inds, draws, purpose) in rd {
                 let mut appended = false;
-                for (v, i, p) in render_data.iter_mut() {
+                for (v, i, d, p) in render_data.iter_mut() {
                     if *p == purpose {
                         let ind_count = v.len() as u32;
+                        let first_index = i.len() as u32;
                         let mut offsetted_ind = inds.iter().map(|i| i+ind_count).collect();
 
                         v.append(&mut verts);
                         i.append(&mut offsetted_ind);
+                        d.extend(draws.iter().map(|draw| DrawRange {first_index: draw.first_index+first_index, ..*draw}));
                         appended = true;
                         break;
                     }
                 }
                 if !appended {
-                    render_data.push((verts, inds, purpose));
+                    render_data.push((verts, inds, draws, purpose));
                 }
             }
         };
 
-        if self.chunk_update_ef {
-            if let Some(ref mut chunk_mesh) = &mut self.chunk_mesh_ef {
-                self.chunk_rd_ef = chunk_mesh.generate_vertices();
-                self.chunk_update_ef = false;
-                any_chunk_update = true;
-            }
-        }
-        if self.chunk_update_hf {
-            if let Some(ref mut chunk_mesh) = &mut self.chunk_mesh_hf {
-                self.chunk_rd_hf = chunk_mesh.generate_vertices();
-                self.chunk_update_hf = false;
-                any_chunk_update = true;
-            }
-        }
-        if self.chunk_update_mf {
-            if let Some(ref mut chunk_mesh) = &mut self.chunk_mesh_mf {
-                self.chunk_rd_mf = chunk_mesh.generate_vertices();
-                self.chunk_update_mf = false;
-                any_chunk_update = true;
-            }
+        self.load_queued_chunks();
+
+        for ring in self.ring_meshes.iter_mut().filter(|ring| ring.needs_update) {
+            ring.render_data = ring.mesh.generate_vertices();
+            ring.needs_update = false;
+            any_chunk_update = true;
         }
 
         if any_chunk_update {
-            data_aggregator(self.chunk_rd_ef.clone());
-            data_aggregator(self.chunk_rd_hf.clone());
-            data_aggregator(self.chunk_rd_mf.clone());
+            for ring in &self.ring_meshes {
+                data_aggregator(ring.render_data.clone());
+            }
 
-            self.to_render = render_data.iter()
-                .filter(|(verts, inds, purpose)| {
+            let mut copies = Vec::new();
+            let uploaded = render_data.iter()
+                .filter(|(verts, inds, _, purpose)| {
                     println!("RENDER DATA: {:?} {:?} {:?}", verts.len(), inds.len(), purpose);
 
                     verts.len() != 0 && inds.len() != 0
                 })
-                .flat_map(|(verts, inds, purpose)| {
-                    let (host_vbo, host_vmo, _, host_vbo_size) = unsafe {
-                        create_host_buffer(self.vi.clone(), self.device.clone(), &verts, vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::VERTEX_BUFFER, true)
-                    };
-                    let (host_ibo, host_imo, _, host_ibo_size) = unsafe {
-                        create_host_buffer(self.vi.clone(), self.device.clone(), &inds, vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::INDEX_BUFFER, true)
+                .flat_map(|(verts, inds, draws, purpose)| {
+                    let (staging_vbo, staging_vbo_ofs) = unsafe { self.uploads.stage(&verts) };
+                    let (staging_ibo, staging_ibo_ofs) = unsafe { self.uploads.stage(&inds) };
+                    let (local_vbo, local_vbo_alloc, local_vbo_size) = unsafe {
+                        self.uploads.create_local_buffer((verts.len()*std::mem::size_of::<PackedChunkVertex>()) as vk::DeviceSize, vk::BufferUsageFlags::VERTEX_BUFFER)
                     };
-                    let (local_vbo, local_vmo, _) = unsafe {
-                        create_local_buffer(self.vi.clone(), self.device.clone(), host_vbo_size, vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER)
-                    };
-                    let (local_ibo, local_imo, _) = unsafe {
-                        create_local_buffer(self.vi.clone(), self.device.clone(), host_ibo_size, vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER)
+                    let (local_ibo, local_ibo_alloc, local_ibo_size) = unsafe {
+                        self.uploads.create_local_buffer((inds.len()*std::mem::size_of::<u32>()) as vk::DeviceSize, vk::BufferUsageFlags::INDEX_BUFFER)
                     };
 
-                    unsafe { self.ctx.record(|cmd_buf| {
-                        let vert_buf_region = [vk::BufferCopy {src_offset: 0, dst_offset: 0, size: host_vbo_size}];
-                        self.device.cmd_copy_buffer(cmd_buf, host_vbo, local_vbo, &vert_buf_region);
-                        let indx_buf_region = [vk::BufferCopy {src_offset: 0, dst_offset: 0, size: host_ibo_size}];
-                        self.device.cmd_copy_buffer(cmd_buf, host_ibo, local_ibo, &indx_buf_region);
-                    }); }
-
-                    unsafe {
-                        self.device.destroy_buffer(host_vbo, None);
-                        self.device.free_memory(host_vmo, None);
-                        self.device.destroy_buffer(host_ibo, None);
-                        self.device.free_memory(host_imo, None);
-                    }
+                    copies.push((staging_vbo, local_vbo, vk::BufferCopy {src_offset: staging_vbo_ofs, dst_offset: 0, size: local_vbo_size}));
+                    copies.push((staging_ibo, local_ibo, vk::BufferCopy {src_offset: staging_ibo_ofs, dst_offset: 0, size: local_ibo_size}));
 
                     [
                         RenderData::RecreateVertexBuffer(
-                            local_vbo, local_vmo, *purpose
+                            local_vbo, local_vbo_alloc, *purpose
                         ),
                         RenderData::RecreateIndexBuffer(
-                            local_ibo, local_imo, inds.len() as u32, *purpose
-                        )
+                            local_ibo, local_ibo_alloc, inds.len() as u32, *purpose
+                        ),
+                        RenderData::SetDrawRanges(draws.clone(), *purpose),
                     ]
                 })
-                .collect();
+                .collect::<Vec<RenderData>>();
+
+            // the new meshes replace the old ones only once the copies have landed
+            let ticket = unsafe { self.uploads.submit(|cmd_buf| {
+                for (src, dst, region) in &copies {
+                    self.device.cmd_copy_buffer(cmd_buf, *src, *dst, &[*region]);
+                }
+            }) };
+            self.pending_uploads.push((ticket, uploaded));
+        }
+
+        let uploads = self.uploads.clone();
+        let (completed, pending) = self.pending_uploads.drain(..)
+            .partition::<Vec<_>, _>(|(ticket, _)| uploads.is_complete(*ticket));
+        self.pending_uploads = pending;
+        for (_, mut uploaded) in completed {
+            self.to_render.append(&mut uploaded);
         }
     }
 }
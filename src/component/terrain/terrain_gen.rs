@@ -18,12 +18,13 @@ impl TerrainGenerator {
     const SEA_LEVEL: f64 = 10.0;
     const SAND_LEVEL: f64 = 13.0;
 
-    pub fn new() -> Self {
+    // the offsets keep the originally hand picked noise seeds (50, 23, 47) for the default seed of 50
+    pub fn new(seed: u32) -> Self {
         Self {
-            height_noise: Perlin::new(50),
-            humidity_noise: Perlin::new(23),
-            temperature_noise: Perlin::new(47),
-            floral_noise: Perlin::new(23),
+            height_noise: Perlin::new(seed),
+            humidity_noise: Perlin::new(seed.wrapping_sub(27)),
+            temperature_noise: Perlin::new(seed.wrapping_sub(3)),
+            floral_noise: Perlin::new(seed.wrapping_sub(27)),
         }
     }
 
@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
 use std::rc::Rc;
 use ash::{Device, vk};
 use png;
 use crate::component::{Component, RenderData, RenderDataPurpose};
 use crate::handler::VulkanInstance;
-use crate::util::{CmdBufContext, create_host_buffer, create_local_image};
+use crate::memory::{Allocation, MemoryAllocator};
+use crate::transfer::UploadQueue;
+use crate::util::CmdBufContext;
 use crate::world::WorldEvent;
 
 
@@ -14,14 +16,12 @@ pub(crate) type TextureIDMapper = Rc<HashMap<String, u32>>;
 
 pub(crate) struct TextureHandler {
     vi: Rc<VulkanInstance>,
+    alloc: Rc<MemoryAllocator>,
     device: Rc<Device>,
 
-    staging_buf: vk::Buffer,
-    staging_buf_mem: vk::DeviceMemory,
-    staging_buf_offsets: Vec<usize>,
     img: vk::Image,
     img_fmt: vk::Format,
-    img_mem: vk::DeviceMemory,
+    img_alloc: Allocation,
     img_extent: vk::Extent3D,
     // two modes of accessing image: ImgView for simple viewing of image,
     //      Sampler for frag shader to sample textures (distinct from image)
@@ -34,7 +34,8 @@ pub(crate) struct TextureHandler {
 
 impl TextureHandler {
     const TEXTURE_MIPMAP_LEVELS: u32 = 4;
-    pub(crate) fn new(vi: Rc<VulkanInstance>, device: Rc<Device>, txtr_path: Vec<&Path>) -> Self {
+    pub(crate) fn new(vi: Rc<VulkanInstance>, alloc: Rc<MemoryAllocator>, device: Rc<Device>, uploads: Rc<UploadQueue>,
+                      txtr_path: Vec<PathBuf>) -> Self {
         let mut raw_buf = Vec::new();
         let mut offsets = Vec::with_capacity(txtr_path.len());
         let mut mapper = HashMap::new();
@@ -48,7 +49,7 @@ impl TextureHandler {
             txtr_mapper.insert(String::from(path.file_stem().unwrap().to_str().unwrap()), ind as u32);
 
             let decoder = png::Decoder::new(
-                fs::File::open(path).unwrap()
+                fs::File::open(path).unwrap_or_else(|e| panic!("Failed to open texture {path:?}: {e}"))
             );
             let mut reader = decoder.read_info().unwrap();
             let mut txtr_raw_buf = vec![0; reader.output_buffer_size()];
@@ -90,14 +91,18 @@ impl TextureHandler {
         // };
 
         unsafe {
-            let (buf, buf_mem, _, _) = create_host_buffer(vi.clone(), device.clone(), &raw_buf, vk::BufferUsageFlags::TRANSFER_SRC, true);
-
-            let (img, img_mem) = create_local_image(
-                vi.clone(), device.clone(), img_extent.unwrap(), Self::TEXTURE_MIPMAP_LEVELS, img_fmt,
+            let (img, img_alloc) = uploads.create_local_image(
+                img_extent.unwrap(), Self::TEXTURE_MIPMAP_LEVELS, img_fmt,
                 vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
                 Some(txtr_path.len() as u32),
             );
 
+            // the base level is copied in the background, mipmaps are generated on the graphics queue later
+            let (staging_buf, staging_ofs) = uploads.stage(&raw_buf);
+            uploads.submit(Self::record_copy(
+                &device, img, img_extent.unwrap(), txtr_path.len() as u32, staging_buf, staging_ofs, &offsets
+            ));
+
             let sampler_info = vk::SamplerCreateInfo {
                 mag_filter: vk::Filter::NEAREST,
                 min_filter: vk::Filter::NEAREST,
@@ -119,17 +124,14 @@ impl TextureHandler {
             let sampler = device.create_sampler(&sampler_info, None)
                 .expect("Failed to create sampler");
 
-            let txtr_len = offsets.clone().len() as u32;
+            let txtr_len = offsets.len() as u32;
             println!("TEXTURE ARRAY IMAGE LAYERS: {}", txtr_len);
 
             Self {
-                vi, device,
-                staging_buf: buf,
-                staging_buf_mem: buf_mem,
-                staging_buf_offsets: offsets,
+                vi, alloc, device,
                 img,
                 img_fmt,
-                img_mem,
+                img_alloc,
                 img_extent: img_extent.unwrap(),
                 img_view: None,
                 img_sampler: sampler,
@@ -139,8 +141,11 @@ impl TextureHandler {
         }
     }
 
-    fn record(&self) -> impl FnMut(vk::CommandBuffer)+'_ {
-        |cmd_buf| unsafe {
+    fn record_copy<'a>(
+        device: &'a Device, img: vk::Image, img_extent: vk::Extent3D, txtr_len: u32,
+        staging_buf: vk::Buffer, staging_ofs: vk::DeviceSize, offsets: &'a [usize],
+    ) -> impl FnMut(vk::CommandBuffer)+'a {
+        move |cmd_buf| unsafe {
             // transition image layout to prepare for transfer
 
             let transfer_barrier = vk::ImageMemoryBarrier {
@@ -148,29 +153,29 @@ impl TextureHandler {
                 new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
                 dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                image: self.img,
+                image: img,
                 subresource_range: vk::ImageSubresourceRange {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     base_mip_level: 0,
                     level_count: Self::TEXTURE_MIPMAP_LEVELS,
                     base_array_layer: 0,
-                    layer_count: self.txtr_len,
+                    layer_count: txtr_len,
                 },
                 src_access_mask: vk::AccessFlags::empty(),
                 dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
                 ..Default::default()
             };
-            self.device.cmd_pipeline_barrier(
+            device.cmd_pipeline_barrier(
                 cmd_buf, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER,
                 vk::DependencyFlags::empty(), &[], &[], &[transfer_barrier]
             );
 
             // copy buffer to image
 
-            let mut region_layers = Vec::with_capacity(self.staging_buf_offsets.len());
-            for (ind, ofs) in self.staging_buf_offsets.iter().enumerate() {
+            let mut region_layers = Vec::with_capacity(offsets.len());
+            for (ind, ofs) in offsets.iter().enumerate() {
                 let region = vk::BufferImageCopy {
-                    buffer_offset: *ofs as vk::DeviceSize,
+                    buffer_offset: staging_ofs + *ofs as vk::DeviceSize,
                     buffer_row_length: 0,
                     buffer_image_height: 0,
                     image_subresource: vk::ImageSubresourceLayers {
@@ -180,14 +185,18 @@ impl TextureHandler {
                         layer_count: 1,
                     },
                     image_offset: vk::Offset3D {x:0, y:0, z:0},
-                    image_extent: self.img_extent,
+                    image_extent: img_extent,
                 };
                 region_layers.push(region);
             }
-            self.device.cmd_copy_buffer_to_image(
-                cmd_buf, self.staging_buf, self.img, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &region_layers
+            device.cmd_copy_buffer_to_image(
+                cmd_buf, staging_buf, img, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &region_layers
             );
+        }
+    }
 
+    fn record_mipmaps(&self) -> impl FnMut(vk::CommandBuffer)+'_ {
+        |cmd_buf| unsafe {
             // generating mipmaps & transitioning each mipmap level to be read by shaders
             let prop = self.vi.get_physical_device_format_properties(self.img_fmt);
             if !prop.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) {
@@ -210,7 +219,7 @@ impl TextureHandler {
             let mut mip_width = self.img_extent.width.clone();
             let mut mip_height = self.img_extent.height.clone();
 
-            // for layer in 0..self.staging_buf_offsets.len() {
+            // for layer in 0..self.txtr_len {
             //
             // }
             for i in 1..Self::TEXTURE_MIPMAP_LEVELS {
@@ -300,7 +309,7 @@ impl Component for TextureHandler {
     }
 
     unsafe fn load_descriptors(&mut self, ctx: CmdBufContext) -> Vec<RenderData> {
-        ctx.record(self.record());
+        ctx.record(self.record_mipmaps());
 
         let img_view_info = vk::ImageViewCreateInfo {
             image: self.img,
@@ -311,7 +320,7 @@ impl Component for TextureHandler {
                 base_mip_level: 0,
                 level_count: Self::TEXTURE_MIPMAP_LEVELS,
                 base_array_layer: 0,
-                layer_count: self.staging_buf_offsets.len() as u32,
+                layer_count: self.txtr_len,
             },
             ..Default::default()
         };
@@ -336,10 +345,6 @@ impl Component for TextureHandler {
             self.device.destroy_image_view(img_view, None);
         }
 
-        self.device.destroy_buffer(self.staging_buf, None);
-        self.device.free_memory(self.staging_buf_mem, None);
-
-        self.device.destroy_image(self.img, None);
-        self.device.free_memory(self.img_mem, None);
+        self.alloc.destroy_image(self.img, self.img_alloc);
     }
 }
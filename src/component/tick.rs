@@ -1,42 +1,46 @@
-use std::{ffi, mem};
+use std::ffi;
 use std::rc::Rc;
 use ash::{Device, vk};
+use matrixagon_util::UniformBlock;
 use uom::num_traits::FloatConst;
 use crate::component::{Component, RenderData, RenderDataPurpose};
-use crate::handler::VulkanInstance;
-use crate::util::{CmdBufContext, create_host_buffer, update_buffer};
+use crate::memory::{Allocation, MemoryAllocator};
+use crate::util::{CmdBufContext, create_uniform_buffer, update_uniform_buffer};
 use crate::world::WorldEvent;
 
+#[derive(Copy, Clone, UniformBlock)]
+pub(crate) struct TimeUBO {
+    time: f32,
+}
+
 pub(crate) struct TickSync {
-    device: Rc<Device>,
+    alloc: Rc<MemoryAllocator>,
 
     tick: bool,
     time: f32,
     increment: f32,
 
-    ubo_buf: vk::Buffer,
-    ubo_mem: vk::DeviceMemory,
-    ubo_ptr: *mut ffi::c_void,
-    ubo_size: vk::DeviceSize,
+    ubo_bufs: Vec<(vk::Buffer, Allocation, *mut ffi::c_void)>,  // one per frame in flight
 }
 
 impl TickSync {
-    pub(crate) fn new(vi: Rc<VulkanInstance>, device: Rc<Device>, speed: f32) -> Self {
+    pub(crate) fn new(alloc: Rc<MemoryAllocator>, device: Rc<Device>, frames_in_flight: usize, speed: f32) -> Self {
         let time = 0.0;
 
-        let (ubo_buf, ubo_mem, ubo_ptr, ubo_size) = unsafe {
-            create_host_buffer(vi.clone(), device.clone(), &[time], vk::BufferUsageFlags::UNIFORM_BUFFER, false)
-        };
+        let mut ubo_bufs = Vec::new();
+        for _ in 0..frames_in_flight {
+            let (ubo_buf, ubo_alloc, ubo_ptr, _) = unsafe {
+                create_uniform_buffer(alloc.clone(), device.clone(), &TimeUBO {time})
+            };
+            ubo_bufs.push((ubo_buf, ubo_alloc, ubo_ptr));
+        }
 
         Self {
-            device,
+            alloc,
             tick: false,
             time,
             increment: speed,
-            ubo_buf,
-            ubo_mem,
-            ubo_ptr,
-            ubo_size
+            ubo_bufs,
         }
     }
 
@@ -46,10 +50,6 @@ impl TickSync {
         } else {
             self.time += self.increment*delta;
         }
-
-        unsafe {
-            update_buffer(self.ubo_ptr, &[self.time], self.ubo_size);
-        }
     }
 }
 
@@ -60,6 +60,10 @@ impl Component for TickSync {
 
     fn respond_event(&mut self, event: WorldEvent) -> Vec<WorldEvent> {
         match event {
+            WorldEvent::SettingsChanged(settings) => {
+                self.increment = settings.tick_speed;
+                vec![]
+            }
             WorldEvent::DeltaTime(dur) => {
                 self.tick = false;
 
@@ -80,18 +84,21 @@ impl Component for TickSync {
     unsafe fn load_descriptors(&mut self, _: CmdBufContext) -> Vec<RenderData> {
         vec![
             RenderData::InitialDescriptorBuffer(
-                vec![vk::DescriptorBufferInfo {
-                    buffer: self.ubo_buf,
-                    offset: 0 as vk::DeviceSize,
-                    range: mem::size_of::<f32>() as vk::DeviceSize,
-                }],
+                self.ubo_bufs.iter()
+                    .map(|(ubo_buf, _, _)| TimeUBO::descriptor_buffer_info(*ubo_buf))
+                    .collect(),
                 RenderDataPurpose::Time
             )
         ]
     }
 
+    unsafe fn write_uniforms(&mut self, frame: usize) {
+        update_uniform_buffer(self.ubo_bufs[frame].2, &TimeUBO {time: self.time});
+    }
+
     unsafe fn destroy(&mut self) {
-        self.device.destroy_buffer(self.ubo_buf, None);
-        self.device.free_memory(self.ubo_mem, None);
+        for (ubo_buf, ubo_alloc, _) in &self.ubo_bufs {
+            self.alloc.destroy_buffer(*ubo_buf, *ubo_alloc);
+        }
     }
 }
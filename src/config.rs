@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+use crate::component::terrain::lod::LodRing;
+use crate::debug::DebugVisibility;
+use crate::display::DisplayConfig;
+use crate::swapchain::PresentSetting;
+
+
+// everything the app needs to know before the window is opened
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    pub validate: bool,
+    pub debug_visibility: DebugVisibility,
+    pub display: DisplayConfig,
+    pub mouse_lock: bool,
+    pub frames_in_flight: usize,
+    pub present_setting: PresentSetting,
+    pub seed: u32,
+    pub render_distance: Option<u32>,  // overrides the render distance from the settings file
+    pub lod_rings: Vec<LodRing>,  // from the finest to the coarsest
+    pub chunk_cache_mib: usize,  // memory budget for the meshes of recently unloaded chunks
+    pub settings_path: PathBuf,
+    pub asset_root: PathBuf,  // block textures are looked up under <asset_root>/block_textures
+    pub pipeline_cache_path: PathBuf,  // compiled pipelines, reused as long as the device and driver stay the same
+    pub shader_hot_reload: bool,  // recompile the shaders in src/shader when they change, needs glslc
+    pub screenshot_dir: PathBuf,  // where the screenshot key saves the frame as a png
+}
+
+impl AppConfig {
+    pub const DEFAULT_SEED: u32 = 50;
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            validate: true,
+            debug_visibility: DebugVisibility {
+                vk_setup_output: true,
+                mtxg_output: true,
+                vk_swapchain_output: false,
+                mtxg_render_output: false,
+            },
+            display: DisplayConfig::default(),
+            mouse_lock: true,
+            frames_in_flight: 2,
+            present_setting: PresentSetting::LowLatency,
+            seed: Self::DEFAULT_SEED,
+            render_distance: None,
+            lod_rings: LodRing::default_rings(),
+            chunk_cache_mib: 256,
+            settings_path: PathBuf::from("settings.cfg"),
+            asset_root: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/resource")),
+            pipeline_cache_path: PathBuf::from("pipeline_cache.bin"),
+            shader_hot_reload: false,
+            screenshot_dir: PathBuf::from("screenshots"),
+        }
+    }
+}
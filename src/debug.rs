@@ -1,4 +1,4 @@
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct DebugVisibility {
     pub vk_setup_output: bool,
     pub mtxg_output: bool,
@@ -0,0 +1,110 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use ash::{Device, vk};
+use ash::extensions::khr::Swapchain;
+use matrixagon_util::BufferRelease;
+use crate::memory::{Allocation, MemoryAllocator};
+
+
+pub(crate) enum RetiredResource {
+    Buffer(vk::Buffer, Allocation),
+    Image(vk::Image, Allocation),
+    ImageView(vk::ImageView),
+    Framebuffer(vk::Framebuffer),
+    Pipeline(vk::Pipeline),
+    RenderPass(vk::RenderPass),
+    DescriptorPool(vk::DescriptorPool),
+    Swapchain(Swapchain, vk::SwapchainKHR),
+}
+
+// resources that may still be referenced by submitted work are parked here instead of waiting
+// for the device to go idle, each one is tagged with the frame that was being built when it got retired
+pub(crate) struct DeletionQueue {
+    device: Rc<Device>,
+    alloc: Rc<MemoryAllocator>,
+
+    frame: Cell<u64>,  // number of frames submitted so far
+    retired: RefCell<VecDeque<(u64, RetiredResource)>>,
+}
+
+impl DeletionQueue {
+    pub(crate) fn new(device: Rc<Device>, alloc: Rc<MemoryAllocator>) -> Self {
+        Self {
+            device, alloc,
+            frame: Cell::new(0),
+            retired: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    pub(crate) fn frame(&self) -> u64 {
+        self.frame.get()
+    }
+
+    pub(crate) fn retire(&self, resource: RetiredResource) {
+        self.retired.borrow_mut().push_back((self.frame.get(), resource));
+    }
+
+    // called right after a frame has been submitted (along with its fence)
+    pub(crate) fn advance_frame(&self) {
+        self.frame.set(self.frame.get() + 1);
+    }
+
+    // completed_frames: amount of frames whose fence is known to have signalled, anything retired
+    // while one of those frames was being built (including the uploads submitted before it) is free to go
+    pub(crate) unsafe fn collect(&self, completed_frames: u64) {
+        let mut retired = self.retired.borrow_mut();
+        while let Some((frame, _)) = retired.front() {
+            if *frame >= completed_frames {
+                break;
+            }
+            let (_, resource) = retired.pop_front().unwrap();
+            self.destroy_resource(resource);
+        }
+    }
+
+    // only valid once the device is idle
+    pub(crate) unsafe fn flush(&self) {
+        let retired = std::mem::take(&mut *self.retired.borrow_mut());
+        for (_, resource) in retired {
+            self.destroy_resource(resource);
+        }
+    }
+
+    unsafe fn destroy_resource(&self, resource: RetiredResource) {
+        match resource {
+            RetiredResource::Buffer(buf, allocation) => {
+                self.alloc.destroy_buffer(buf, allocation);
+            }
+            RetiredResource::Image(img, allocation) => {
+                self.alloc.destroy_image(img, allocation);
+            }
+            RetiredResource::ImageView(imgv) => {
+                self.device.destroy_image_view(imgv, None);
+            }
+            RetiredResource::Framebuffer(framebuffer) => {
+                self.device.destroy_framebuffer(framebuffer, None);
+            }
+            RetiredResource::Pipeline(pipeline) => {
+                self.device.destroy_pipeline(pipeline, None);
+            }
+            RetiredResource::RenderPass(renderpass) => {
+                self.device.destroy_render_pass(renderpass, None);
+            }
+            RetiredResource::DescriptorPool(pool) => {
+                self.device.destroy_descriptor_pool(pool, None);
+            }
+            RetiredResource::Swapchain(loader, swapchain) => {
+                loader.destroy_swapchain(swapchain, None);
+            }
+        }
+    }
+}
+
+impl BufferRelease for DeletionQueue {
+    type Memory = Allocation;
+
+    unsafe fn release_buffer(&self, buf: vk::Buffer, mem: Allocation) {
+        self.retire(RetiredResource::Buffer(buf, mem));
+    }
+}
@@ -0,0 +1,182 @@
+use winit::dpi::PhysicalSize;
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{Fullscreen, Window};
+use crate::debug::DebugVisibility;
+
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+    Exclusive,  // changes the monitor's video mode
+}
+
+impl WindowMode {
+    pub fn next(&self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayConfig {
+    pub mode: WindowMode,
+    pub monitor: Option<usize>,  // index into the available monitors, primary (or first) monitor if none
+    pub windowed_size: (u32, u32),
+    // exclusive fullscreen video mode, the monitor's largest one if none
+    pub resolution: Option<(u32, u32)>,
+    pub refresh_rate_millihertz: Option<u32>,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            mode: WindowMode::Windowed,
+            monitor: None,
+            windowed_size: (1000, 1000),
+            resolution: None,
+            refresh_rate_millihertz: None,
+        }
+    }
+}
+
+impl DisplayConfig {
+    pub(crate) fn select_monitor(&self, window: &Window) -> Option<MonitorHandle> {
+        match self.monitor {
+            Some(ind) => window.available_monitors().nth(ind)
+                .or_else(|| {
+                    println!("Monitor [{ind}] is not available, falling back to the primary monitor");
+                    window.primary_monitor()
+                }),
+            None => window.primary_monitor(),
+        }
+            .or_else(|| window.available_monitors().next())
+    }
+
+    pub(crate) fn select_video_mode(&self, monitor: &MonitorHandle) -> Option<VideoMode> {
+        let video_modes = monitor.video_modes().collect::<Vec<VideoMode>>();
+        let candidates = video_modes.iter()
+            .map(|vm| (vm.size().width, vm.size().height, vm.refresh_rate_millihertz(), vm.bit_depth()))
+            .collect::<Vec<_>>();
+
+        pick_video_mode(&candidates, self.resolution, self.refresh_rate_millihertz)
+            .map(|ind| video_modes[ind].clone())
+    }
+
+    pub(crate) fn fullscreen(&self, window: &Window) -> Option<Fullscreen> {
+        match self.mode {
+            WindowMode::Windowed => None,
+            WindowMode::Borderless => Some(Fullscreen::Borderless(self.select_monitor(window))),
+            WindowMode::Exclusive => {
+                let video_mode = self.select_monitor(window)
+                    .and_then(|monitor| self.select_video_mode(&monitor));
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => {
+                        println!("No video mode available for exclusive fullscreen, using borderless instead");
+                        Some(Fullscreen::Borderless(self.select_monitor(window)))
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn next_monitor(&self, window: &Window) -> usize {
+        let count = window.available_monitors().count().max(1);
+        self.monitor.map_or(0, |ind| (ind+1) % count)
+    }
+
+    // cycles through the distinct resolutions of the selected monitor, largest first
+    pub(crate) fn next_resolution(&self, window: &Window) -> Option<(u32, u32)> {
+        let mut resolutions = self.select_monitor(window)?.video_modes()
+            .map(|vm| (vm.size().width, vm.size().height))
+            .collect::<Vec<_>>();
+        resolutions.sort_by_key(|(w, h)| (std::cmp::Reverse(*w as u64 * *h as u64), *w, *h));
+        resolutions.dedup();
+
+        let next = match self.resolution.and_then(|res| resolutions.iter().position(|r| *r == res)) {
+            Some(ind) => (ind+1) % resolutions.len(),
+            None => 0,
+        };
+        resolutions.get(next).copied()
+    }
+
+    // the window is created first and switched afterwards, so startup and runtime take the same path
+    pub(crate) fn apply(&self, dbgv: DebugVisibility, window: &Window) {
+        let fullscreen = self.fullscreen(window);
+        if dbgv.mtxg_output {
+            println!("DISPLAY MODE {:?} {:?}", self.mode, fullscreen);
+        }
+
+        if fullscreen.is_none() {
+            window.set_fullscreen(None);
+            window.set_inner_size(PhysicalSize::new(self.windowed_size.0, self.windowed_size.1));
+        } else {
+            window.set_fullscreen(fullscreen);
+        }
+    }
+}
+
+pub(crate) fn print_monitors(window: &Window) {
+    for (ind, monitor) in window.available_monitors().enumerate() {
+        println!("MONITOR [{ind}] {:?} {:?}", monitor.name(), monitor.size());
+        for video_mode in monitor.video_modes() {
+            println!("\t{:?} @ {}mHz ({} bit)", video_mode.size(), video_mode.refresh_rate_millihertz(), video_mode.bit_depth());
+        }
+    }
+}
+
+// (width, height, refresh rate in mHz, bit depth) per video mode. an exact resolution match is
+// required if one was requested, the closest refresh rate (or else the highest) and then the
+// deepest color wins among the matches
+fn pick_video_mode(
+    video_modes: &[(u32, u32, u32, u16)], resolution: Option<(u32, u32)>, refresh_rate: Option<u32>,
+) -> Option<usize> {
+    let largest = video_modes.iter()
+        .map(|(w, h, _, _)| *w as u64 * *h as u64)
+        .max()?;
+
+    video_modes.iter().enumerate()
+        .filter(|(_, (w, h, _, _))| match resolution {
+            Some(res) => (*w, *h) == res,
+            None => *w as u64 * *h as u64 == largest,
+        })
+        .max_by_key(|(_, (_, _, rate, depth))| {
+            let rate_rank = match refresh_rate {
+                Some(requested) => u32::MAX - rate.abs_diff(requested),
+                None => *rate,
+            };
+            (rate_rank, *depth)
+        })
+        .map(|(ind, _)| ind)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [(u32, u32, u32, u16); 5] = [
+        (1920, 1080, 60000, 32),
+        (1920, 1080, 144000, 32),
+        (2560, 1440, 60000, 24),
+        (2560, 1440, 60000, 32),
+        (1280, 720, 60000, 32),
+    ];
+
+    #[test]
+    fn largest_mode_by_default() {
+        assert_eq!(pick_video_mode(&MODES, None, None), Some(3));
+        assert_eq!(pick_video_mode(&[], None, None), None);
+    }
+
+    #[test]
+    fn requested_resolution_and_refresh_rate() {
+        assert_eq!(pick_video_mode(&MODES, Some((1920, 1080)), None), Some(1));
+        assert_eq!(pick_video_mode(&MODES, Some((1920, 1080)), Some(59940)), Some(0));
+        assert_eq!(pick_video_mode(&MODES, Some((800, 600)), None), None);
+    }
+}
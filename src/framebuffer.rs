@@ -1,30 +1,35 @@
+use std::collections::HashMap;
 use std::rc::Rc;
 use ash::{Device, vk};
-use crate::component::{RenderData, RenderDataPurpose};
 use crate::debug::DebugVisibility;
-use crate::handler::VulkanInstance;
+use crate::deletion::{DeletionQueue, RetiredResource};
+use crate::memory::{Allocation, MemoryAllocator};
+use crate::render_graph::RenderGraph;
 use crate::util::create_local_image;
 
 
 // presentation attachment is not included
-// all AttachmentRef refers to all elective attachments (including depth/stencil)
-#[derive(Copy, Clone, Debug)]
+// all AttachmentRef refers to all elective attachments (including depth/stencil), along with their image usage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub(crate) enum FBAttachmentRef {
-    Color,
-    ColorInput,  // color attachment that can be also used as input (attachments)
-    Depth, // uses its own depth format
+    Color(vk::ImageUsageFlags),  // also an input attachment if the usage says so
+    Depth(vk::ImageUsageFlags), // uses its own depth format
 }
 
+// the images every pass reads as input attachments, by pass name and then by framebuffer
+pub(crate) type PassInputs = HashMap<&'static str, Vec<Vec<vk::DescriptorImageInfo>>>;
+
 
 pub(crate) struct FramebufferManager {
     device: Rc<Device>,
+    alloc: Rc<MemoryAllocator>,
 
     prsnt_imgvs: Vec<vk::ImageView>,  // presenting images are controlled by swapchain
     attachment_imgs: Vec<vk::Image>,  // ordered as per the attachment references initial param after the presentation image (if there is one)
     attachment_imgvs: Vec<vk::ImageView>,
-    attachment_imgms: Vec<vk::DeviceMemory>,
-    inp_attachment_imgvs: Vec<vk::ImageView>,
+    attachment_allocs: Vec<Allocation>,
     pub(crate) framebuffers: Vec<vk::Framebuffer>,  // duplicated to the same amount as presentation images
+    pub(crate) extent: vk::Extent2D,
 }
 
 impl FramebufferManager {
@@ -35,15 +40,13 @@ impl FramebufferManager {
     // }
 
     pub(crate) unsafe fn new_swapchain_bounded(
-        dbv: DebugVisibility, vi: Rc<VulkanInstance>, device: Rc<Device>, renderpass: vk::RenderPass,
+        dbv: DebugVisibility, alloc: Rc<MemoryAllocator>, device: Rc<Device>, renderpass: vk::RenderPass,
         attachments: Vec<FBAttachmentRef>, prsnt_imgs: Vec<vk::Image>,
         color_fmt: vk::Format, depth_fmt: vk::Format, extent: vk::Extent2D,
-        prsnt_inp: bool,
     ) -> Self {
         let mut attachment_imgs = Vec::new();
         let mut attachment_imgvs = Vec::new();
-        let mut attachment_imgms = Vec::new();
-        let mut inp_attachment_imgvs = Vec::new();
+        let mut attachment_allocs = Vec::new();
 
         if dbv.vk_swapchain_output {
             println!("NEW FB ATTACHMENTS {attachments:?}");
@@ -52,112 +55,41 @@ impl FramebufferManager {
             if dbv.vk_swapchain_output {
                 println!("FB ATTACHMENT {attachment:?}");
             }
-            match attachment {
-                FBAttachmentRef::Depth => {
-                    // TODO: maybe we can but the image format (color, depth, etc.) separate
-                    // TODO: when creating image buffer
-                    let (depth_img, depth_img_mem) = create_local_image(
-                        vi.clone(), device.clone(),
-                        vk::Extent3D {width: extent.width, height: extent.height, depth: 1},
-                        1, depth_fmt, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, None
-                    );
-
-                    let depth_imgv_create_info = vk::ImageViewCreateInfo {
-                        image: depth_img,
-                        view_type: vk::ImageViewType::TYPE_2D,
-                        format: depth_fmt,
-                        components: vk::ComponentMapping {
-                            r: vk::ComponentSwizzle::IDENTITY,
-                            g: vk::ComponentSwizzle::IDENTITY,
-                            b: vk::ComponentSwizzle::IDENTITY,
-                            a: vk::ComponentSwizzle::IDENTITY,
-                        },
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::DEPTH,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        },
-                        ..Default::default()
-                    };
-                    let depth_view = device.create_image_view(&depth_imgv_create_info, None)
-                        .expect("Failed to create image view");
-
-                    attachment_imgs.push(depth_img);
-                    attachment_imgvs.push(depth_view);
-                    attachment_imgms.push(depth_img_mem);
-                }
-                FBAttachmentRef::Color => {
-                    let (color_img, color_img_mem) = create_local_image(
-                        vi.clone(), device.clone(),
-                        vk::Extent3D {width: extent.width, height: extent.height, depth: 1},
-                        1, color_fmt, vk::ImageUsageFlags::COLOR_ATTACHMENT, None
-                    );
-
-                    let color_imgv_create_info = vk::ImageViewCreateInfo {
-                        image: color_img,
-                        view_type: vk::ImageViewType::TYPE_2D,
-                        format: color_fmt,
-                        components: vk::ComponentMapping {
-                            r: vk::ComponentSwizzle::IDENTITY,
-                            g: vk::ComponentSwizzle::IDENTITY,
-                            b: vk::ComponentSwizzle::IDENTITY,
-                            a: vk::ComponentSwizzle::IDENTITY,
-                        },
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        },
-                        ..Default::default()
-                    };
-                    let color_view = device.create_image_view(&color_imgv_create_info, None)
-                        .expect("Failed to create image view");
-
-                    attachment_imgs.push(color_img);
-                    attachment_imgvs.push(color_view);
-
-                    attachment_imgms.push(color_img_mem);
-                }
-                FBAttachmentRef::ColorInput => {
-                    let (color_img, color_img_mem) = create_local_image(
-                        vi.clone(), device.clone(),
-                        vk::Extent3D {width: extent.width, height: extent.height, depth: 1},
-                        1, color_fmt, vk::ImageUsageFlags::INPUT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                        None
-                    );
-
-                    let color_imgv_create_info = vk::ImageViewCreateInfo {
-                        image: color_img,
-                        view_type: vk::ImageViewType::TYPE_2D,
-                        format: color_fmt,
-                        components: vk::ComponentMapping {
-                            r: vk::ComponentSwizzle::IDENTITY,
-                            g: vk::ComponentSwizzle::IDENTITY,
-                            b: vk::ComponentSwizzle::IDENTITY,
-                            a: vk::ComponentSwizzle::IDENTITY,
-                        },
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        },
-                        ..Default::default()
-                    };
-                    let color_view = device.create_image_view(&color_imgv_create_info, None)
-                        .expect("Failed to create image view");
-
-                    attachment_imgs.push(color_img);
-                    attachment_imgvs.push(color_view);
-                    attachment_imgms.push(color_img_mem);
-                    inp_attachment_imgvs.push(color_view);
-                }
-            }
+            let (fmt, usage, aspect_mask) = match attachment {
+                FBAttachmentRef::Color(usage) => (color_fmt, usage, vk::ImageAspectFlags::COLOR),
+                FBAttachmentRef::Depth(usage) => (depth_fmt, usage, depth_aspect_mask(depth_fmt)),
+            };
+            let (img, img_alloc) = create_local_image(
+                alloc.clone(), device.clone(),
+                vk::Extent3D {width: extent.width, height: extent.height, depth: 1},
+                1, fmt, usage, None
+            );
+
+            let imgv_create_info = vk::ImageViewCreateInfo {
+                image: img,
+                view_type: vk::ImageViewType::TYPE_2D,
+                format: fmt,
+                components: vk::ComponentMapping {
+                    r: vk::ComponentSwizzle::IDENTITY,
+                    g: vk::ComponentSwizzle::IDENTITY,
+                    b: vk::ComponentSwizzle::IDENTITY,
+                    a: vk::ComponentSwizzle::IDENTITY,
+                },
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                ..Default::default()
+            };
+            let imgv = device.create_image_view(&imgv_create_info, None)
+                .expect("Failed to create image view");
+
+            attachment_imgs.push(img);
+            attachment_imgvs.push(imgv);
+            attachment_allocs.push(img_alloc);
         }
 
         let mut prsnt_imgvs = Vec::new();
@@ -201,42 +133,52 @@ impl FramebufferManager {
                 .expect("Failed to create framebuffer");
 
             prsnt_imgvs.push(view);
-            if prsnt_inp {
-                inp_attachment_imgvs.push(view);
-            }
             framebuffers.push(fb);
         }
 
         Self {
-            device,prsnt_imgvs, attachment_imgs, attachment_imgvs, attachment_imgms,
-            inp_attachment_imgvs, framebuffers
+            device, alloc, prsnt_imgvs, attachment_imgs, attachment_imgvs, attachment_allocs, framebuffers, extent
         }
     }
 
-    pub(crate) unsafe fn get_input_attachment_descriptors(&self) -> Vec<RenderData> {
-        self.inp_attachment_imgvs.iter()
-            .map(|imgv| {
-                RenderData::InitialDescriptorImage(
-                    vec![vk::DescriptorImageInfo {
-                        sampler: vk::Sampler::null(),
-                        image_view: *imgv,
-                        image_layout: vk::ImageLayout::READ_ONLY_OPTIMAL,  // TODO: change for presentation attachment as input?
-                    }],
-                    RenderDataPurpose::PresentationInpAttachment,  // TODO: change to variable
-                )
+    // the views are only valid as long as these framebuffers, so the inputs are rewritten after every recreation
+    pub(crate) fn input_attachments(&self, graph: &RenderGraph) -> PassInputs {
+        graph.pass_names()
+            .map(|pass| {
+                let inputs = graph.input_attachments(pass);
+                let per_framebuffer = self.prsnt_imgvs.iter()
+                    .map(|prsnt_imgv| inputs.iter()
+                        .map(|(attachment, layout)| vk::DescriptorImageInfo {
+                            sampler: vk::Sampler::null(),
+                            image_view: if *attachment == 0 {*prsnt_imgv} else {self.attachment_imgvs[attachment-1]},
+                            image_layout: *layout,
+                        })
+                        .collect())
+                    .collect();
+                (pass, per_framebuffer)
             })
             .collect()
     }
 
+    // hands everything over to the deletion queue instead, for when the previous frames may still use them
+    pub(crate) fn retire(self, deletion: &DeletionQueue) {
+        for framebuffer in self.framebuffers {
+            deletion.retire(RetiredResource::Framebuffer(framebuffer));
+        }
+        for imgv in self.prsnt_imgvs.into_iter().chain(self.attachment_imgvs) {
+            deletion.retire(RetiredResource::ImageView(imgv));
+        }
+        for (img, allocation) in self.attachment_imgs.into_iter().zip(self.attachment_allocs) {
+            deletion.retire(RetiredResource::Image(img, allocation));
+        }
+    }
+
     pub(crate) unsafe fn destroy(&self) {
         for attachment_imgv in &self.attachment_imgvs {
             self.device.destroy_image_view(*attachment_imgv, None);
         }
-        for attachment_img in &self.attachment_imgs {
-            self.device.destroy_image(*attachment_img, None);
-        }
-        for attachment_imgm in &self.attachment_imgms {
-            self.device.free_memory(*attachment_imgm, None);
+        for (attachment_img, attachment_alloc) in self.attachment_imgs.iter().zip(&self.attachment_allocs) {
+            self.alloc.destroy_image(*attachment_img, *attachment_alloc);
         }
 
 
@@ -249,3 +191,13 @@ impl FramebufferManager {
         }
     }
 }
+
+// depth formats that carry a stencil component need both aspects in attachment views
+fn depth_aspect_mask(depth_fmt: vk::Format) -> vk::ImageAspectFlags {
+    match depth_fmt {
+        vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
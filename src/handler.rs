@@ -1,33 +1,46 @@
 use std::borrow::Cow;
 use std::ffi::{c_char, CStr};
+use std::mem;
 use std::os::raw::c_void;
+use std::path::PathBuf;
 use std::rc::Rc;
 use ash::extensions::ext::DebugUtils;
 use ash::extensions::khr::Surface;
-use ash::{Device, Instance, vk};
+use ash::{Device, Entry, Instance, vk};
 use ash::vk::DeviceQueueCreateFlags;
 use ash_window::create_surface;
 use winit::event_loop::EventLoop;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use winit::window::Window;
+use crate::component::{RenderData, RenderDataPurpose};
 use crate::debug::DebugVisibility;
+use crate::deletion::{DeletionQueue, RetiredResource};
+use crate::framebuffer::FramebufferManager;
+use crate::memory::MemoryAllocator;
+use crate::offscreen::{OffscreenTarget, Screenshot};
+use crate::render_graph::RenderGraph;
 use crate::shader::Shader;
-use crate::swapchain::{query_swapchain_support, SwapchainManager};
+use crate::shader::pipeline_cache::PipelineCache;
+use crate::swapchain::{query_swapchain_support, PresentSetting, SwapchainManager};
+use crate::transfer::UploadQueue;
 use crate::util::CmdBufContext;
 
 
 const DEVICE_EXTS: &[*const c_char] = &[
     unsafe {CStr::from_bytes_with_nul_unchecked(b"VK_KHR_swapchain\0").as_ptr()},
 ];
+// selects a GPU by its index or (part of its) name
+const GPU_OVERRIDE_ENV: &str = "MATRIXAGON_GPU";
 const VALIDATION_LYRS: &[*const c_char] = &[
     unsafe {CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_KHRONOS_validation\0").as_ptr()},
     // unsafe {CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_LUNARG_api_dump\0").as_ptr()},
 ];
 
+// one set per frame in flight
 struct SyncMTXG {
-    image_available_smph: [vk::Semaphore; 1],
-    render_finished_smph: [vk::Semaphore; 1],
-    in_flight_fence: [vk::Fence; 1],
+    image_available_smph: Vec<vk::Semaphore>,
+    render_finished_smph: Vec<vk::Semaphore>,
+    in_flight_fence: Vec<vk::Fence>,
 }
 
 pub struct VulkanHandler {
@@ -38,36 +51,72 @@ pub struct VulkanHandler {
 
     pub(crate) vi: Rc<VulkanInstance>,
     pub(crate) device: Rc<Device>,
+    pub(crate) alloc: Rc<MemoryAllocator>,
+    pub(crate) deletion: Rc<DeletionQueue>,
+    pub(crate) uploads: Rc<UploadQueue>,
+    pub(crate) pipeline_cache: PipelineCache,
     gfxs_queue: vk::Queue,
     prsnt_queue: vk::Queue,
     pub(crate) swapchain: Option<SwapchainManager>,
     cmd_pool: vk::CommandPool,
     transient_cmd_pool: vk::CommandPool,
 
-    render_cmd_buf: [vk::CommandBuffer; 1],
+    frames_in_flight: usize,
+    current_frame: usize,
+    render_cmd_bufs: Vec<vk::CommandBuffer>,
     sync: SyncMTXG,
 
-    shader: Option<Box<dyn Shader>>,
+    render_graph: Option<(RenderGraph, vk::RenderPass)>,
+    shaders: Vec<Box<dyn Shader>>,  // drawn in this order within each pass
 }
 
 impl VulkanHandler {
-    pub(crate) fn init(event_loop: &EventLoop<()>, window: &Window, validate: bool, debug_output: DebugVisibility) -> Self
+    pub(crate) fn init(event_loop: &EventLoop<()>, window: &Window, validate: bool, debug_output: DebugVisibility,
+                           frames_in_flight: usize, pipeline_cache_path: PathBuf) -> Self
+    {
+        let surf_exts = ash_window::enumerate_required_extensions(event_loop.raw_display_handle())
+            .expect("Enumerate required extensions for raw display handle failed")
+            .to_vec();
+        let create_surf = |entry: &Entry, inst: &Instance| unsafe {
+            create_surface(
+                entry,
+                inst,
+                window.raw_display_handle(),
+                window.raw_window_handle(),
+                None
+            )
+                .expect("Surface creation failed")
+        };
+        Self::create(surf_exts, create_surf, validate, debug_output, frames_in_flight, pipeline_cache_path)
+    }
+
+    // without a window nothing can be presented, frames are only rendered into offscreen targets. only the render
+    // tests run without one so far
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn init_headless(validate: bool, debug_output: DebugVisibility, frames_in_flight: usize,
+                                pipeline_cache_path: PathBuf) -> Self {
+        Self::create(Vec::new(), |_, _| vk::SurfaceKHR::null(), validate, debug_output, frames_in_flight, pipeline_cache_path)
+    }
+
+    fn create(mut surf_exts: Vec<*const c_char>, create_surf: impl FnOnce(&Entry, &Instance) -> vk::SurfaceKHR,
+              validate: bool, debug_output: DebugVisibility, frames_in_flight: usize, pipeline_cache_path: PathBuf) -> Self
     {
         let debug_loader;
         let debug;
         let vi;
         let device;
+        let alloc;
+        let deletion;
+        let uploads;
+        let pipeline_cache;
         let gfxs_queue;
         let prsnt_queue;
         let cmd_pool;
         let transient_cmd_pool;
-        let render_cmd_buf;
-        let sync;
+        let render_cmd_bufs;
+        let mut sync;
         unsafe {
             let entry = ash::Entry::linked(); // ash::Entry::load().expect("VK Entry failed to load");
-            let mut surf_exts = ash_window::enumerate_required_extensions(event_loop.raw_display_handle())
-                .expect("Enumerate required extensions for raw display handle failed")
-                .to_vec();
             if validate {
                 surf_exts.push(CStr::from_bytes_with_nul_unchecked(b"VK_EXT_debug_utils\0").as_ptr());
             }
@@ -127,14 +176,7 @@ impl VulkanHandler {
                 )
             } else {None};
 
-            let surf = create_surface(
-                &entry,
-                &inst,
-                window.raw_display_handle(),
-                window.raw_window_handle(),
-                None
-            )
-                .expect("Surface creation failed");
+            let surf = create_surf(&entry, &inst);
 
             let surf_loader = Surface::new(&entry, &inst);
             if debug_output.vk_setup_output {
@@ -146,11 +188,16 @@ impl VulkanHandler {
             // CREATING GRAPHICS AND PRESENTATION QUEUES
 
             let queue_fam_ind = find_queue_families(debug_output, &vi).unwrap();
+            let transfer_fam_ind = find_transfer_queue_family(debug_output, &vi).unwrap_or(queue_fam_ind);
             let priorities = [1.0];
             let gfxs_queue_create_info = vk::DeviceQueueCreateInfo::builder()
                 .queue_family_index(queue_fam_ind)
                 .queue_priorities(&priorities)
                 .build();
+            let transfer_queue_create_info = vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(transfer_fam_ind)
+                .queue_priorities(&priorities)
+                .build();
 
             // since our presentation queue will be the same as the graphics queue
             // let prsnt_queue_create_info = vk::DeviceQueueCreateInfo {
@@ -167,11 +214,20 @@ impl VulkanHandler {
                 ..Default::default()
             };
 
-            let queues = [gfxs_queue_create_info/*, prsnt_queue_create_info*/ ];
+            // uploads are tracked with a timeline semaphore
+            let mut vk12_feats = vk::PhysicalDeviceVulkan12Features::builder()
+                .timeline_semaphore(true);
+
+            let queues = if transfer_fam_ind == queue_fam_ind {
+                vec![gfxs_queue_create_info/*, prsnt_queue_create_info*/ ]
+            } else {
+                vec![gfxs_queue_create_info, transfer_queue_create_info]
+            };
             let device_create_info = vk::DeviceCreateInfo::builder()
                 .queue_create_infos(&queues)
                 .enabled_features(&phys_devc_feats)
                 .enabled_extension_names(&DEVICE_EXTS)
+                .push_next(&mut vk12_feats)
                 .build();
 
             device = Rc::new(vi.inst.create_device(vi.phys_devc, &device_create_info, None)
@@ -180,8 +236,18 @@ impl VulkanHandler {
             if debug_output.vk_setup_output {
                 println!("(Logical) Device Object: {:?}", device.handle());
             }
+            alloc = Rc::new(MemoryAllocator::new(debug_output, vi.clone(), device.clone()));
+            deletion = Rc::new(DeletionQueue::new(device.clone(), alloc.clone()));
+
             gfxs_queue = device.clone().get_device_queue(queue_fam_ind,0);
             prsnt_queue = device.clone().get_device_queue(queue_fam_ind,0);
+            let transfer_queue = device.clone().get_device_queue(transfer_fam_ind,0);
+
+            uploads = Rc::new(UploadQueue::new(
+                debug_output, alloc.clone(), device.clone(), transfer_queue, transfer_fam_ind, queue_fam_ind
+            ));
+
+            pipeline_cache = PipelineCache::load(debug_output, device.clone(), &vi.get_physical_device_properties(), pipeline_cache_path);
 
             // COMMAND BUFFER
 
@@ -205,10 +271,10 @@ impl VulkanHandler {
             let cmd_alloc_info = vk::CommandBufferAllocateInfo {
                 command_pool: cmd_pool,
                 level: vk::CommandBufferLevel::PRIMARY,
-                command_buffer_count: 1,
+                command_buffer_count: frames_in_flight as u32,
                 ..Default::default()
             };
-            render_cmd_buf = device.allocate_command_buffers(&cmd_alloc_info)
+            render_cmd_bufs = device.allocate_command_buffers(&cmd_alloc_info)
                 .expect("Failed to allocate command buffers");
 
             // RENDER FRAME SYNCS
@@ -217,140 +283,346 @@ impl VulkanHandler {
             let fence_info = vk::FenceCreateInfo::builder()
                 .flags(vk::FenceCreateFlags::SIGNALED).build();
 
-            let image_available_smph = device.create_semaphore(&semaphore_info, None)
-                .expect("Failed to create image available semaphore");
-            let render_finished_smph = device.create_semaphore(&semaphore_info, None)
-                .expect("Failed to create render finished semaphore");
-            let in_flight_fence = device.create_fence(&fence_info, None)
-                .expect("Failed to create in flight fence");
-
             sync = SyncMTXG {
-                image_available_smph: [image_available_smph],
-                render_finished_smph: [render_finished_smph],
-                in_flight_fence: [in_flight_fence],
+                image_available_smph: Vec::new(),
+                render_finished_smph: Vec::new(),
+                in_flight_fence: Vec::new(),
+            };
+            for _ in 0..frames_in_flight {
+                sync.image_available_smph.push(device.create_semaphore(&semaphore_info, None)
+                    .expect("Failed to create image available semaphore"));
+                sync.render_finished_smph.push(device.create_semaphore(&semaphore_info, None)
+                    .expect("Failed to create render finished semaphore"));
+                sync.in_flight_fence.push(device.create_fence(&fence_info, None)
+                    .expect("Failed to create in flight fence"));
             }
         }
 
         VulkanHandler {
             debug_output, validate, debug_loader, debug,
-            vi: vi.clone(), device, gfxs_queue, prsnt_queue,
+            vi: vi.clone(), device, alloc, deletion, uploads, pipeline_cache, gfxs_queue, prsnt_queue,
             swapchain: None, cmd_pool, transient_cmd_pool,
-            render_cmd_buf: [render_cmd_buf[0]], sync, shader: None,
+            frames_in_flight, current_frame: 0, render_cmd_bufs, sync, render_graph: None, shaders: Vec::new(),
         }
     }
 
+    // the render pass the shaders create their pipelines in, destroyed along with the handler
+    pub(crate) fn load_render_graph(&mut self, graph: RenderGraph, renderpass: vk::RenderPass) {
+        self.render_graph.replace((graph, renderpass));
+    }
+
+    // render data owns its buffers and the shader it is handed to frees them, so no two shaders may share a purpose
     pub(crate) fn load_shader(&mut self, shader: impl Shader + 'static) {
-        // self.shader = Some(Box::new(shader) as Box<dyn Shader>);
-        self.shader.replace(Box::new(shader) as Box<dyn Shader>);
+        for loaded in &self.shaders {
+            if let Some(purpose) = shader.purposes().iter().find(|purpose| loaded.purposes().contains(purpose)) {
+                panic!("Render data purpose {purpose:?} is already drawn by another shader");
+            }
+        }
+        self.shaders.push(Box::new(shader) as Box<dyn Shader>);
+    }
+
+    fn addressed_shader(&self, purpose: RenderDataPurpose) -> Option<usize> {
+        self.shaders.iter().position(|shader| shader.purposes().contains(&purpose))
+    }
+
+    pub(crate) unsafe fn write_descriptors(&mut self, descriptors: Vec<RenderData>) {
+        let mut addressed = self.shaders.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+        for render_data in descriptors {
+            if let Some(shader) = self.addressed_shader(render_data.purpose()) {
+                addressed[shader].push(render_data);
+            }
+        }
+        for (shader, descriptors) in self.shaders.iter_mut().zip(addressed) {
+            shader.write_descriptors(descriptors);
+        }
+    }
+
+    // each render data goes to the one shader drawing its purpose
+    pub(crate) fn recreate_buffers(&mut self, render_data: Vec<RenderData>) {
+        for render_data in render_data {
+            if let Some(shader) = self.addressed_shader(render_data.purpose()) {
+                self.shaders[shader].recreate_buffer(render_data);
+            }
+        }
     }
 
-    pub(crate) fn obtain_shader_mut_ref(&mut self) -> &mut Box<dyn Shader> {
-        self.shader.as_mut().unwrap()
+    // every shader keeps its old pipelines if the reloaded shaders do not fit it
+    pub(crate) unsafe fn reload_shaders(&mut self, shaders: Vec<(String, Vec<u32>)>) -> Result<(), String> {
+        let errors = self.shaders.iter_mut()
+            .filter_map(|shader| shader.reload_shaders(shaders.clone()).err())
+            .collect::<Vec<_>>();
+        if errors.is_empty() {Ok(())} else {Err(errors.join("\n"))}
     }
 
-    pub(crate) fn load_swapchain(&mut self, swapchain_manager: SwapchainManager) {
+    // the render graph has to be loaded first, the shaders read the input attachments of its passes
+    pub(crate) unsafe fn load_swapchain(&mut self, swapchain_manager: SwapchainManager) {
+        Self::write_input_attachments(self.render_graph.as_ref(), &mut self.shaders, &swapchain_manager.fbm);
         self.swapchain.replace(swapchain_manager);
     }
 
+    // points the shaders at the attachments of the framebuffers drawn into next
+    unsafe fn write_input_attachments(
+        render_graph: Option<&(RenderGraph, vk::RenderPass)>, shaders: &mut [Box<dyn Shader>], fbm: &FramebufferManager,
+    ) {
+        let (graph, _) = render_graph
+            .expect("Attempted to write the input attachments when the render graph has not been loaded yet!");
+        let inputs = fbm.input_attachments(graph);
+        for shader in shaders {
+            shader.write_input_attachments(&inputs);
+        }
+    }
+
+    pub(crate) fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
     pub(crate) fn get_cmd_buf_context(&self) -> CmdBufContext {
-        CmdBufContext(self.device.clone(), self.transient_cmd_pool, self.gfxs_queue)
+        CmdBufContext(self.device.clone(), self.transient_cmd_pool, self.gfxs_queue, self.uploads.clone())
+    }
+
+    // waits until the next frame slot is free again, the returned index selects which per-frame
+    // resources (uniform buffers, descriptor sets) may be written to before drawing
+    pub(crate) unsafe fn begin_frame(&mut self) -> usize {
+        self.current_frame = (self.deletion.frame() % self.frames_in_flight as u64) as usize;
+
+        self.device.wait_for_fences(&self.sync.in_flight_fence[self.current_frame..self.current_frame+1], true, u64::MAX).unwrap();
+        // the slot's fence covers every frame up to the one that used this slot last
+        self.deletion.collect((self.deletion.frame()+1).saturating_sub(self.frames_in_flight as u64));
+        self.uploads.poll();
+
+        self.current_frame
     }
 
     pub(crate) unsafe fn draw_frame(&mut self) {
         let swapchain = self.swapchain.as_mut()
             .expect("Attempted to draw frame when swapchain has not initialized yet!");
-
-        self.device.wait_for_fences(&self.sync.in_flight_fence, true, u64::MAX).unwrap();
-
-        let acquisition = swapchain.loader.acquire_next_image(swapchain.swapchain[0], u64::MAX, self.sync.image_available_smph[0], vk::Fence::null());
-        let img_inds = match acquisition {
-            // swapchain suboptimal
-            Ok((_, true)) => {
-                // self.recreate_swapchain();
-                swapchain.recreate();
-                return;
-            }
+        let frame = self.current_frame;
+        let image_available_smph = [self.sync.image_available_smph[frame]];
+        let render_finished_smph = [self.sync.render_finished_smph[frame]];
+        let in_flight_fence = [self.sync.in_flight_fence[frame]];
+        let render_cmd_buf = [self.render_cmd_bufs[frame]];
+
+        let acquisition = swapchain.loader.acquire_next_image(swapchain.swapchain[0], u64::MAX, image_available_smph[0], vk::Fence::null());
+        let (img_inds, suboptimal) = match acquisition {
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                // self.recreate_swapchain();
-                swapchain.recreate();
+                self.recreate_swapchain();
                 return;
             }
             Err(e) => {
                 panic!("{}", e);
             }
-            Ok((ind, false)) => {
-                [ind]
+            // a suboptimal swapchain can still be presented to, and the semaphore has already been
+            // signalled, so the frame is finished first and the swapchain recreated afterwards
+            Ok((ind, suboptimal)) => {
+                ([ind], suboptimal)
             }
         };
 
-        self.device.reset_fences(&self.sync.in_flight_fence).unwrap();
+        self.device.reset_fences(&in_flight_fence).unwrap();
 
-        self.device.reset_command_buffer(self.render_cmd_buf[0], vk::CommandBufferResetFlags::empty()).unwrap();
+        self.device.reset_command_buffer(render_cmd_buf[0], vk::CommandBufferResetFlags::empty()).unwrap();
 
         // COMMAND RECORDING
 
         let cmd_begin_info = vk::CommandBufferBeginInfo {
             ..Default::default()
         };
-        self.device.begin_command_buffer(self.render_cmd_buf[0], &cmd_begin_info)
+        self.device.begin_command_buffer(render_cmd_buf[0], &cmd_begin_info)
             .expect("Failed to begin recording command buffers");
 
-        self.shader.as_ref().unwrap()
-            .draw_command(self.render_cmd_buf[0], swapchain.fbm.framebuffers[img_inds[0] as usize]);
+        Self::record_render_pass(
+            &self.device, self.render_graph.as_ref(), &self.shaders,
+            render_cmd_buf[0], &swapchain.fbm, img_inds[0] as usize, frame
+        );
 
-        self.device.end_command_buffer(self.render_cmd_buf[0])
+        self.device.end_command_buffer(render_cmd_buf[0])
             .expect("Failed to record command buffers");
 
-        let dst_stage_masks = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        // components only hand over uploads they have seen completing, waiting on the timeline
+        // makes those transfer writes visible to this frame
+        let wait_semaphores = [image_available_smph[0], self.uploads.timeline()];
+        let wait_values = [0, self.uploads.completed()];
+        let signal_values = [0];
+        let dst_stage_masks = [
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
         let submit_infos = [
             vk::SubmitInfo::builder()
-                .wait_semaphores(&self.sync.image_available_smph)
+                .wait_semaphores(&wait_semaphores)
                 .wait_dst_stage_mask(&dst_stage_masks)
-                .command_buffers(&self.render_cmd_buf)
-                .signal_semaphores(&self.sync.render_finished_smph)
+                .command_buffers(&render_cmd_buf)
+                .signal_semaphores(&render_finished_smph)
+                .push_next(&mut timeline_info)
                 .build()
         ];
 
-        self.device.queue_submit(self.gfxs_queue, &submit_infos, self.sync.in_flight_fence[0])
+        self.device.queue_submit(self.gfxs_queue, &submit_infos, in_flight_fence[0])
             .expect("Failed to submit draw command buffer to graphics queue");
+        self.deletion.advance_frame();
 
         let prsnt_info = vk::PresentInfoKHR::builder()
-            .wait_semaphores(&self.sync.render_finished_smph)
+            .wait_semaphores(&render_finished_smph)
             .swapchains(&swapchain.swapchain)
             .image_indices(&img_inds).build();
         let swapchain_result = swapchain.loader.queue_present(self.prsnt_queue, &prsnt_info);
         match swapchain_result {
             // swapchain suboptimal
             Ok(true) => {
-                // self.recreate_swapchain();
-                swapchain.recreate();
+                self.recreate_swapchain();
             }
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                // self.recreate_swapchain();
-                swapchain.recreate();
+                self.recreate_swapchain();
             }
             Err(e) => {
                 panic!("{}", e);
             }
-            _ => {}
+            Ok(false) => {
+                if suboptimal {
+                    self.recreate_swapchain();
+                }
+            }
+        }
+    }
+
+    // every shader draws in each pass of the render graph, in the order they were loaded
+    unsafe fn record_render_pass(
+        device: &Device, render_graph: Option<&(RenderGraph, vk::RenderPass)>, shaders: &[Box<dyn Shader>],
+        cmd_buf: vk::CommandBuffer, fbm: &FramebufferManager, image: usize, frame: usize,
+    ) {
+        let (graph, renderpass) = render_graph
+            .expect("Attempted to draw frame when the render graph has not been loaded yet!");
+        let clear_values = graph.clear_values();
+        let renderpass_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(*renderpass)
+            .framebuffer(fbm.framebuffers[image])
+            .render_area(vk::Rect2D { offset: vk::Offset2D {x:0, y:0}, extent: fbm.extent})
+            .clear_values(&clear_values)
+            .build();
+
+        device.cmd_begin_render_pass(cmd_buf, &renderpass_info, vk::SubpassContents::INLINE);
+        for (i, pass) in graph.pass_names().enumerate() {
+            if i > 0 {
+                device.cmd_next_subpass(cmd_buf, vk::SubpassContents::INLINE);
+            }
+            for shader in shaders {
+                shader.draw_pass(cmd_buf, pass, frame, image);
+            }
+        }
+        device.cmd_end_render_pass(cmd_buf);
+    }
+
+    // renders the last drawn frame again into an offscreen image of the swapchain's formats and extent, so it looks
+    // as it was presented
+    pub(crate) unsafe fn capture_frame(&mut self) -> Result<Screenshot, String> {
+        let swapchain = self.swapchain.as_ref()
+            .expect("Attempted to capture frame when swapchain has not initialized yet!");
+        let target = self.create_offscreen_target((swapchain.fmt.format, swapchain.depth_fmt), swapchain.extent);
+        let screenshot = self.render_offscreen(&target);
+        target.destroy();
+        screenshot
+    }
+
+    // the formats have to match the ones the render pass of the render graph was created with
+    pub(crate) unsafe fn create_offscreen_target(&self, format: (vk::Format, vk::Format), extent: vk::Extent2D) -> OffscreenTarget {
+        let (graph, renderpass) = self.render_graph.as_ref()
+            .expect("Attempted to create an offscreen target when the render graph has not been loaded yet!");
+        OffscreenTarget::new(
+            self.debug_output, self.alloc.clone(), self.device.clone(), *renderpass, graph, format, extent
+        )
+    }
+
+    // renders the current frame slot into the target instead of the swapchain and reads it back, waiting for the
+    // gpu to finish. the shaders read the target's attachments meanwhile
+    pub(crate) unsafe fn render_offscreen(&mut self, target: &OffscreenTarget) -> Result<Screenshot, String> {
+        Self::write_input_attachments(self.render_graph.as_ref(), &mut self.shaders, &target.fbm);
+        self.get_cmd_buf_context().record(|cmd_buf| {
+            Self::record_render_pass(
+                &self.device, self.render_graph.as_ref(), &self.shaders,
+                cmd_buf, &target.fbm, 0, self.current_frame
+            );
+            target.record_readback(cmd_buf);
+        });
+        if let Some(swapchain) = &self.swapchain {
+            Self::write_input_attachments(self.render_graph.as_ref(), &mut self.shaders, &swapchain.fbm);
+        }
+
+        target.screenshot()
+    }
+
+    pub(crate) unsafe fn recreate_swapchain(&mut self) {
+        let swapchain = self.swapchain.as_mut()
+            .expect("Attempted to recreate swapchain when swapchain has not initialized yet!");
+        let format_changed = swapchain.recreate();
+        self.swapchain_recreated(format_changed);
+    }
+
+    pub(crate) unsafe fn resize(&mut self, window_extent: vk::Extent2D) -> vk::Extent2D {
+        let swapchain = self.swapchain.as_mut()
+            .expect("Attempted to resize swapchain when swapchain has not initialized yet!");
+        let format_changed = swapchain.resize(window_extent);
+        self.swapchain_recreated(format_changed)
+    }
+
+    pub(crate) unsafe fn set_present_setting(&mut self, prsnt_setting: PresentSetting) {
+        let swapchain = self.swapchain.as_mut()
+            .expect("Attempted to change the present mode when swapchain has not initialized yet!");
+        let format_changed = swapchain.set_present_setting(prsnt_setting);
+        self.swapchain_recreated(format_changed);
+    }
+
+    // the shaders have to follow the swapchain extent, otherwise the viewport and scissors go stale. a new surface
+    // format also replaces the render pass, and with it the framebuffers and pipelines created in it
+    unsafe fn swapchain_recreated(&mut self, format_changed: bool) -> vk::Extent2D {
+        let swapchain = self.swapchain.as_mut().unwrap();
+        if format_changed {
+            let (graph, renderpass) = self.render_graph.as_mut()
+                .expect("Attempted to recreate the render pass when the render graph has not been loaded yet!");
+            let new_renderpass = graph.create_renderpass(self.device.clone(), swapchain.fmt.format, swapchain.depth_fmt);
+            self.deletion.retire(RetiredResource::RenderPass(mem::replace(renderpass, new_renderpass)));
+
+            swapchain.set_renderpass(new_renderpass);
+            for shader in &mut self.shaders {
+                shader.set_renderpass(new_renderpass);
+            }
         }
+
+        for shader in &mut self.shaders {
+            shader.update_extent(swapchain.extent);
+        }
+        Self::write_input_attachments(self.render_graph.as_ref(), &mut self.shaders, &swapchain.fbm);
+        swapchain.extent
     }
 
     pub(crate) unsafe fn destroy(&self) {
         if let Some(swapchain) = &self.swapchain {
             swapchain.destroy();
         }
-
-        self.device.destroy_semaphore(self.sync.image_available_smph[0], None);
-        self.device.destroy_semaphore(self.sync.render_finished_smph[0], None);
-        self.device.destroy_fence(self.sync.in_flight_fence[0], None);
+        for shader in &self.shaders {
+            shader.destroy();
+        }
+        if let Some((_, renderpass)) = &self.render_graph {
+            self.device.destroy_render_pass(*renderpass, None);
+        }
+        self.pipeline_cache.save();
+        self.pipeline_cache.destroy();
+        // device is idle by now, so anything still retired can go regardless of its frame
+        self.deletion.flush();
+        self.uploads.destroy();
+
+        for frame in 0..self.frames_in_flight {
+            self.device.destroy_semaphore(self.sync.image_available_smph[frame], None);
+            self.device.destroy_semaphore(self.sync.render_finished_smph[frame], None);
+            self.device.destroy_fence(self.sync.in_flight_fence[frame], None);
+        }
 
         self.device.destroy_command_pool(self.transient_cmd_pool, None);
         self.device.destroy_command_pool(self.cmd_pool, None);
 
-        if let Some(shader) = &self.shader {
-            shader.destroy();
-        }
+        self.alloc.destroy();
 
         // --- device level ---
         self.device.destroy_device(None);
@@ -358,11 +630,81 @@ impl VulkanHandler {
         if self.validate {
             self.debug_loader.as_ref().unwrap().destroy_debug_utils_messenger(self.debug.unwrap(), None);
         }
-        self.vi.surf_loader.destroy_surface(self.vi.surf, None);
+        if !self.vi.headless() {
+            self.vi.surf_loader.destroy_surface(self.vi.surf, None);
+        }
         self.vi.inst.destroy_instance(None);
     }
 }
 
+// a transfer-only family is usually backed by the DMA engines, which can copy alongside rendering
+unsafe fn find_transfer_queue_family(dbgv: DebugVisibility, vi: &VulkanInstance) -> Option<u32> {
+    let queue_fams = vi.get_physical_device_queue_family_properties();
+
+    for (ind, queue_fam) in queue_fams.iter().enumerate() {
+        if queue_fam.queue_flags.contains(vk::QueueFlags::TRANSFER) &&
+            !queue_fam.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE) {
+            if dbgv.vk_setup_output {
+                println!("Dedicated transfer queue family found: {ind}");
+            }
+            return Some(ind as u32);
+        }
+    }
+
+    None
+}
+
+// devices are compared by their type first, an integrated gpu or a software implementation sharing plenty of
+// system memory never outranks a discrete gpu. device local memory only decides between devices of the same type
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct DeviceScore {
+    type_rank: u32,
+    local_memory: vk::DeviceSize,
+}
+
+impl DeviceScore {
+    fn new(device_type: vk::PhysicalDeviceType, mem_props: &vk::PhysicalDeviceMemoryProperties) -> Self {
+        let type_rank = match device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 1,  // software implementations (lavapipe, swiftshader)
+            _ => 0,
+        };
+        let local_memory = mem_props.memory_heaps[..mem_props.memory_heap_count as usize].iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum::<vk::DeviceSize>();
+
+        Self {type_rank, local_memory}
+    }
+}
+
+// pick the highest scoring suitable device (the earliest one on ties), unless the override selects one
+// by its index in the enumeration or by a case insensitive part of its name
+fn pick_physical_device(candidates: &[(String, Result<DeviceScore, String>)], gpu_override: Option<&str>) -> Result<usize, String> {
+    if let Some(gpu_override) = gpu_override {
+        let requested = match gpu_override.trim().parse::<usize>() {
+            Ok(ind) if ind < candidates.len() => ind,
+            Ok(ind) => return Err(format!("{GPU_OVERRIDE_ENV}={gpu_override}: there is no GPU with index {ind}")),
+            Err(_) => candidates.iter()
+                .position(|(name, _)| name.to_lowercase().contains(&gpu_override.trim().to_lowercase()))
+                .ok_or(format!("{GPU_OVERRIDE_ENV}={gpu_override}: no GPU name matches"))?,
+        };
+
+        return match &candidates[requested].1 {
+            Ok(_) => Ok(requested),
+            Err(reason) => Err(format!("{GPU_OVERRIDE_ENV}={gpu_override}: {} is not suitable, {reason}", candidates[requested].0)),
+        };
+    }
+
+    candidates.iter().enumerate()
+        .filter_map(|(ind, (_, rating))| rating.as_ref().ok().map(|score| (ind, *score)))
+        .max_by(|(ind_a, score_a), (ind_b, score_b)| score_a.cmp(score_b).then(ind_b.cmp(ind_a)))
+        .map(|(ind, _)| ind)
+        .ok_or(String::from("No suitable physical device found"))
+}
+
 unsafe fn find_queue_families(dbgv: DebugVisibility, vi: &VulkanInstance) -> Option<u32> {
     let queue_fams = vi.get_physical_device_queue_family_properties();
 
@@ -372,7 +714,7 @@ unsafe fn find_queue_families(dbgv: DebugVisibility, vi: &VulkanInstance) -> Opt
             println!("Queue family properties: {:?}", queue_fam);
         }
 
-        let supported = vi.get_physical_device_surface_support(ind);
+        let supported = vi.headless() || vi.get_physical_device_surface_support(ind);
 
         if queue_fam.queue_flags.contains(vk::QueueFlags::GRAPHICS) && supported {
             return Some(ind);
@@ -420,7 +762,7 @@ unsafe extern "system" fn vulkan_validation_debug_callback(
 
 pub(crate) struct VulkanInstance {
     pub(crate) inst: Instance,
-    pub(crate) surf: vk::SurfaceKHR,
+    pub(crate) surf: vk::SurfaceKHR,  // null when headless
     pub(crate) surf_loader: Surface,
     pub(crate) phys_devc: vk::PhysicalDevice,
 }
@@ -445,27 +787,44 @@ impl VulkanInstance {
             println!("Available physical devices: {:?}", phys_devcs);
         }
 
-        // let mut phys_devc_o = None;
-        for phys_devc_i in phys_devcs {
-            self.phys_devc = phys_devc_i;
-            if self.is_device_suitable(dbgv, &self.inst, phys_devc_i) {
-                // phys_devc_o = Some(phys_devc_i);
-                if dbgv.vk_setup_output {
-                    println!("Suitable physical device found!");
+        let mut candidates = Vec::with_capacity(phys_devcs.len());
+        for (ind, phys_devc_i) in phys_devcs.iter().enumerate() {
+            // queue family and surface queries go through self.phys_devc
+            self.phys_devc = *phys_devc_i;
+            let props = self.inst.get_physical_device_properties(*phys_devc_i);
+            let name = CStr::from_ptr(props.device_name.as_ptr()).to_string_lossy().into_owned();
+
+            let rating = self.rate_device(dbgv, &self.inst, *phys_devc_i);
+            if dbgv.vk_setup_output {
+                match &rating {
+                    Ok(score) => println!("GPU [{ind}] {name} ({:?}): suitable, {score:?}", props.device_type),
+                    Err(reason) => println!("GPU [{ind}] {name} ({:?}): rejected, {reason}", props.device_type),
                 }
-                break;
             }
+            candidates.push((name, rating));
         }
-        // self.phys_devc = phys_devc_o.expect("No suitable physical device found");
+
+        let gpu_override = std::env::var(GPU_OVERRIDE_ENV).ok();
+        let selected = match pick_physical_device(&candidates, gpu_override.as_deref()) {
+            Ok(selected) => selected,
+            Err(e) => panic!("{e}"),
+        };
+        self.phys_devc = phys_devcs[selected];
 
         if dbgv.vk_setup_output {
+            println!(
+                "Selected GPU [{selected}] {}{}", candidates[selected].0,
+                if gpu_override.is_some() {format!(" (requested through {GPU_OVERRIDE_ENV})")} else {String::new()}
+            );
             println!("Selected physical device: {:?}", self.phys_devc);
         }
     }
 
-    unsafe fn is_device_suitable(&self, dbgv: DebugVisibility, inst: &Instance, device: vk::PhysicalDevice) -> bool {
+    // score of a device that meets every requirement, or why it does not
+    unsafe fn rate_device(&self, dbgv: DebugVisibility, inst: &Instance, device: vk::PhysicalDevice) -> Result<DeviceScore, String> {
         let props = inst.get_physical_device_properties(device);
         let feats = inst.get_physical_device_features(device);
+        let mem_props = inst.get_physical_device_memory_properties(device);
 
         let device_ext_props = inst.enumerate_device_extension_properties(device)
             .expect("Failed to enumerate device extension props");
@@ -492,17 +851,36 @@ impl VulkanInstance {
                 }
             }
             if !has {
-                return false;
+                return Err(format!("missing device extension {:?}", CStr::from_ptr(device_ext)));
             }
         }
 
-        let (_, formats, present_modes) = query_swapchain_support(dbgv, &self);
+        if props.api_version < vk::make_api_version(0, 1, 2, 0) {
+            // timeline semaphores
+            return Err(format!(
+                "Vulkan {}.{} is below the required 1.2",
+                vk::api_version_major(props.api_version), vk::api_version_minor(props.api_version)
+            ));
+        }
+        if find_queue_families(dbgv, &self).is_none() {
+            return Err(String::from("no graphics queue family that can present to the window surface"));
+        }
+        if !self.headless() {
+            let (_, formats, present_modes) = query_swapchain_support(dbgv, &self);
+            if formats.is_empty() || present_modes.is_empty() {
+                return Err(String::from("no surface formats or present modes"));
+            }
+        }
+        if feats.sampler_anisotropy == 0 {
+            return Err(String::from("sampler anisotropy is not supported"));
+        }
+
+        Ok(DeviceScore::new(props.device_type, &mem_props))
+    }
 
-        props.device_type == vk::PhysicalDeviceType::DISCRETE_GPU &&
-            find_queue_families(dbgv, &self).is_some() &&
-            !formats.is_empty() &&
-            !present_modes.is_empty() &&
-            feats.sampler_anisotropy != 0
+    // created without a surface, for rendering offscreen only
+    pub(crate) fn headless(&self) -> bool {
+        self.surf == vk::SurfaceKHR::null()
     }
 
     pub(crate) unsafe fn get_physical_device_surface_present_modes(&self) -> Vec<vk::PresentModeKHR> {
@@ -550,3 +928,64 @@ impl VulkanInstance {
         self.inst.get_physical_device_format_properties(self.phys_devc, format)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: vk::DeviceSize = 1024*1024*1024;
+
+    fn score(device_type: vk::PhysicalDeviceType, local_heaps: &[vk::DeviceSize]) -> DeviceScore {
+        let mut mem_props = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: local_heaps.len() as u32 + 1,
+            ..Default::default()
+        };
+        for (heap, size) in mem_props.memory_heaps.iter_mut().zip(local_heaps) {
+            *heap = vk::MemoryHeap {size: *size, flags: vk::MemoryHeapFlags::DEVICE_LOCAL};
+        }
+        // host memory does not count
+        mem_props.memory_heaps[local_heaps.len()] = vk::MemoryHeap {size: 128*GIB, flags: vk::MemoryHeapFlags::empty()};
+        DeviceScore::new(device_type, &mem_props)
+    }
+
+    fn candidates() -> Vec<(String, Result<DeviceScore, String>)> {
+        vec![
+            (String::from("llvmpipe (LLVM 15.0.7, 256 bits)"), Ok(score(vk::PhysicalDeviceType::CPU, &[16*GIB]))),
+            (String::from("Intel(R) UHD Graphics 630"), Ok(score(vk::PhysicalDeviceType::INTEGRATED_GPU, &[8*GIB]))),
+            (String::from("NVIDIA GeForce RTX 3070"), Ok(score(vk::PhysicalDeviceType::DISCRETE_GPU, &[8*GIB, 256*1024*1024]))),
+            (String::from("Old GPU"), Err(String::from("Vulkan 1.1 is below the required 1.2"))),
+        ]
+    }
+
+    #[test]
+    fn highest_score_wins() {
+        assert_eq!(pick_physical_device(&candidates(), None), Ok(2));
+        assert_eq!(pick_physical_device(&candidates()[..2], None), Ok(1));
+        assert!(pick_physical_device(&candidates()[3..], None).is_err());
+    }
+
+    #[test]
+    fn device_type_outranks_memory() {
+        let integrated = score(vk::PhysicalDeviceType::INTEGRATED_GPU, &[64*GIB]);
+        let discrete = score(vk::PhysicalDeviceType::DISCRETE_GPU, &[8*GIB]);
+        assert!(discrete > integrated);
+        assert!(score(vk::PhysicalDeviceType::CPU, &[64*GIB]) < score(vk::PhysicalDeviceType::VIRTUAL_GPU, &[GIB]));
+        assert!(score(vk::PhysicalDeviceType::DISCRETE_GPU, &[12*GIB]) > discrete);
+
+        let candidates = vec![
+            (String::from("AMD Radeon 780M"), Ok(integrated)),
+            (String::from("NVIDIA GeForce RTX 3050"), Ok(discrete)),
+        ];
+        assert_eq!(pick_physical_device(&candidates, None), Ok(1));
+    }
+
+    #[test]
+    fn override_by_index_or_name() {
+        assert_eq!(pick_physical_device(&candidates(), Some("0")), Ok(0));
+        assert_eq!(pick_physical_device(&candidates(), Some("intel")), Ok(1));
+        assert!(pick_physical_device(&candidates(), Some("7")).is_err());
+        assert!(pick_physical_device(&candidates(), Some("old gpu")).is_err());
+        assert!(pick_physical_device(&candidates(), Some("radeon")).is_err());
+    }
+}
@@ -20,8 +20,8 @@
 extern crate uom;
 extern crate alloc;
 
-use std::path::Path;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use ash::vk;
 use egui::{Id, Modifiers, Pos2, RawInput, Rect, ViewportId, ViewportIdMap, ViewportInfo};
 use egui::ahash::HashMapExt;
@@ -29,7 +29,8 @@ use uom::si::f32::Length;
 use winit::dpi::{PhysicalPosition, PhysicalSize};
 use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Fullscreen, Window, WindowBuilder};
+use winit::window::{Window, WindowBuilder};
+use crate::component::Component;
 use crate::component::camera::{CameraComponent, Length3D};
 use crate::component::debug_ui::{DebugUI};
 use crate::component::flags::WorldFlags;
@@ -40,9 +41,19 @@ use crate::component::terrain::{BlockData, MeshType, Terrain, TextureMapper, Tra
 use crate::component::texture::TextureHandler;
 use crate::component::tick::TickSync;
 use crate::measurement::{blox};
+use crate::render_graph::RenderGraph;
 use crate::shader::chunk::ChunkRasterizer;
-use crate::shader::Shader;
+use crate::shader::debug_ui::DebugUIRasterizer;
+use crate::shader::frame_graph;
+use crate::shader::hot_reload::ShaderWatcher;
 use crate::swapchain::{best_surface_color_and_depth_format, SwapchainManager};
+pub use crate::swapchain::PresentSetting;
+pub use crate::display::{DisplayConfig, WindowMode};
+pub use crate::config::AppConfig;
+pub use crate::settings::Settings;
+pub use crate::component::terrain::chunk_mesh::ChunkRadius;
+pub use crate::component::terrain::lod::{ChunkSize, ChunkUnit, GeneratorKind, LodRing};
+pub use crate::shader::chunk::{ChunkVertex, PackedChunkVertex};
 
 mod handler;
 pub mod debug;
@@ -53,6 +64,14 @@ mod util;
 mod measurement;
 mod swapchain;
 mod framebuffer;
+mod offscreen;
+mod render_graph;
+mod memory;
+mod deletion;
+mod transfer;
+mod display;
+mod config;
+mod settings;
 
 
 pub struct MatrixagonApp {
@@ -63,45 +82,59 @@ pub struct MatrixagonApp {
     window: Window,
     window_render: bool,
     mouse_lock: bool,
+    settings_open: bool,  // frees the cursor and routes the mouse to the settings menu
+    display: DisplayConfig,
     // Main app fields
     world: World,
     handler: VulkanHandler,
+    shader_watcher: Option<ShaderWatcher>,
+    screenshot_dir: PathBuf,
+    screenshot_requested: bool,  // taken once the next frame is drawn
     // Misc
     frame_time: Instant,
 }
 
 impl MatrixagonApp {
-    pub fn init(validate: bool, debug_visibility: DebugVisibility, fullscreen: bool, mouse_lock: bool) -> MatrixagonApp {
-        let prsnt_inp = true;
+    pub fn init(config: AppConfig) -> MatrixagonApp {
+        let AppConfig {
+            validate, debug_visibility, display, mouse_lock, frames_in_flight, present_setting,
+            seed, render_distance, lod_rings, chunk_cache_mib, settings_path, asset_root,
+            pipeline_cache_path, shader_hot_reload, screenshot_dir,
+        } = config;
+        assert!(frames_in_flight > 0, "At least one frame in flight is required");
+
+        let mut settings = Settings::load(&settings_path);
+        if let Some(render_distance) = render_distance {
+            settings.render_distance = render_distance;
+        }
+        let settings = settings.clamped();
 
-        let initial_extent = if fullscreen {
-            vk::Extent2D {
-                width: 2560,
-                height: 1600,
-            }
-        } else {
-            vk::Extent2D {
-                width: 1000,
-                height: 1000,
-            }
-        };
+        let texture_dir = asset_root.join("block_textures");
 
         let event_loop = EventLoop::new();
-        let window = if fullscreen {
-            WindowBuilder::new().with_fullscreen(Some(Fullscreen::Borderless(None)))
-        } else {
-            WindowBuilder::new().with_inner_size(PhysicalSize::<u32>::from((initial_extent.width, initial_extent.height)))
-        }
+        let window = WindowBuilder::new()
+            .with_inner_size(PhysicalSize::<u32>::from(display.windowed_size))
             .with_visible(true)
             .with_title("Matrixagon 2")
             .build(&event_loop)
             .expect("Window builder failed");
 
+        if debug_visibility.mtxg_output {
+            display::print_monitors(&window);
+        }
+        display.apply(debug_visibility, &window);
+
+        // may still be the windowed size if the platform switches asynchronously, the resize event catches up
+        let initial_extent = vk::Extent2D {
+            width: window.inner_size().width,
+            height: window.inner_size().height,
+        };
+
         window.set_cursor_position(PhysicalPosition::new(
             initial_extent.width as f32/2.0, initial_extent.height as f32/2.0
         )).unwrap();
 
-        let mut handler = VulkanHandler::init(&event_loop, &window, validate, debug_visibility);
+        let mut handler = VulkanHandler::init(&event_loop, &window, validate, debug_visibility, frames_in_flight, pipeline_cache_path);
 
         if debug_visibility.mtxg_output {
             println!("WINDOW SCALE FACTOR {:?}", window.scale_factor() as f32);
@@ -130,7 +163,7 @@ impl MatrixagonApp {
                         }),
                         minimized: None,
                         maximized: None,
-                        fullscreen: Some(fullscreen),
+                        fullscreen: Some(display.mode != WindowMode::Windowed),
                         focused: Some(true),
                     }
                 );
@@ -138,7 +171,10 @@ impl MatrixagonApp {
             },
             screen_rect: Some(Rect {
                 min: Pos2::from((0.0, 0.0)),
-                max: Pos2::from((160.0, 500.0))
+                max: Pos2::from((
+                    window.inner_size().width as f32/window.scale_factor() as f32,
+                    window.inner_size().height as f32/window.scale_factor() as f32,
+                ))
             }),
             modifiers: Modifiers::default(),
             events: Vec::new(),
@@ -147,100 +183,34 @@ impl MatrixagonApp {
         };
 
         let ratio = initial_extent.width as f32/initial_extent.height as f32;
-        let mut world = World::new(debug_visibility, vec![
-            Box::new(TickSync::new(
-                handler.vi.clone(), handler.device.clone(), 1.5,
-            )),
-            Box::new(WorldFlags::new()),
-            Box::new(TextureHandler::new(handler.vi.clone(), handler.device.clone(), vec![
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/null.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/stone.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/grass_top.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/grass_side.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/dirt.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/sand.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/grass_flora.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/grass_top.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/flower.png"),
-                Path::new("C:/Users/andrewshen/documents/matrixagon2/src/resource/block_textures/water.png"),
-            ])),
-            Box::new(CameraComponent::new(
-                handler.vi.clone(), handler.device.clone(), ratio, 70.0, 1.0, 0.05,
-                Length3D::new(
-                    Length::new::<blox>(0.0),
-                    Length::new::<blox>(40.0),
-                    Length::new::<blox>(0.0),
-                )
-            )),
-            Box::new(Terrain::new(handler.vi.clone(), handler.device.clone(), handler.get_cmd_buf_context(), vec![
-                BlockData {
-                    ident: "grass_block",
-                    texture_id: TextureMapper::Lateral("grass_top", "dirt", "grass_side"),
-                    mesh: MeshType::Cube,
-                    transparency: TransparencyType::Opaque,
-                },
-                BlockData {
-                    ident: "dirt",
-                    texture_id: TextureMapper::All("dirt"),
-                    mesh: MeshType::Cube,
-                    transparency: TransparencyType::Opaque,
-                },
-                BlockData {
-                    ident: "stone",
-                    texture_id: TextureMapper::All("stone"),
-                    mesh: MeshType::Cube,
-                    transparency: TransparencyType::Opaque,
-                },
-                BlockData {
-                    ident: "sand",
-                    texture_id: TextureMapper::All("sand"),
-                    mesh: MeshType::Cube,
-                    transparency: TransparencyType::Opaque,
-                },
-                BlockData {
-                    ident: "grass",
-                    texture_id: TextureMapper::All("grass_flora"),
-                    mesh: MeshType::XCross,
-                    transparency: TransparencyType::Transparent,
-                },
-                BlockData {
-                    ident: "flower",
-                    texture_id: TextureMapper::All("flower"),
-                    mesh: MeshType::XCross,
-                    transparency: TransparencyType::Transparent,
-                },
-                BlockData {
-                    ident: "water",
-                    texture_id: TextureMapper::All("water"),
-                    mesh: MeshType::Fluid,
-                    transparency: TransparencyType::Translucent,
-                },
-                BlockData {
-                    ident: "air",
-                    texture_id: TextureMapper::All("null"),
-                    mesh: MeshType::Empty,
-                    transparency: TransparencyType::Transparent,
-                },
-            ])),
-            Box::new(DebugUI::new(handler.vi.clone(), handler.device.clone(), init_raw_input)),
-        ]);
+        let mut components = world_components(&handler, settings, seed, lod_rings, chunk_cache_mib, &texture_dir, ratio);
+        components.push(
+            Box::new(DebugUI::new(handler.alloc.clone(), handler.device.clone(), handler.get_cmd_buf_context(), init_raw_input, settings, settings_path)),
+        );
+        let mut world = World::new(debug_visibility, components);
 
         let format = best_surface_color_and_depth_format(debug_visibility, handler.vi.clone());
-        let mut shader = unsafe {
-            ChunkRasterizer::new(handler.device.clone(), initial_extent, format.0, format.1)
-        };
-
-        let mut descriptors = unsafe {
-            world.load_descriptors(handler.get_cmd_buf_context())
-        };
+        let graph = frame_graph();
+        let renderpass = unsafe { graph.create_renderpass(handler.device.clone(), format.0, format.1) };
         let swpc = unsafe {
-            SwapchainManager::new(debug_visibility, handler.vi.clone(), handler.device.clone(), shader.renderpass(), shader.attachments(), prsnt_inp)
+            SwapchainManager::new(
+                debug_visibility, handler.vi.clone(), handler.alloc.clone(), handler.deletion.clone(), handler.device.clone(), renderpass,
+                graph.framebuffer_attachments(), graph.swapchain_usage().contains(vk::ImageUsageFlags::INPUT_ATTACHMENT),
+                present_setting, initial_extent
+            )
         };
-        descriptors.append(&mut unsafe { swpc.fbm.get_input_attachment_descriptors() });
-        unsafe { shader.write_descriptors(descriptors); }
+        unsafe {
+            load_shaders(&mut handler, &mut world, graph, renderpass, swpc.extent);
+            handler.load_swapchain(swpc);
+        }
 
-        handler.load_swapchain(swpc);
-        handler.load_shader(shader);
+        let shader_watcher = shader_hot_reload.then(|| {
+            let watcher = ShaderWatcher::new(PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader")));
+            if watcher.is_none() {
+                println!("Shader hot reload is disabled, glslc was not found at build time or in GLSLC");
+            }
+            watcher
+        }).flatten();
 
         MatrixagonApp {
             debug_visibility,
@@ -248,8 +218,13 @@ impl MatrixagonApp {
             window,
             window_render: false,
             mouse_lock,
+            settings_open: false,
+            display,
             world,
             handler,
+            shader_watcher,
+            screenshot_dir,
+            screenshot_requested: false,
             frame_time: Instant::now(),
         }
     }
@@ -269,13 +244,28 @@ impl MatrixagonApp {
                 app.frame_time = now;
             }
             Event::MainEventsCleared => {
-                if app.mouse_lock {
+                if app.mouse_lock && !app.settings_open {
                     let _ = app.window.set_cursor_position(PhysicalPosition::new(
                         app.handler.swapchain.as_ref().unwrap().extent.width as f32 / 2.0,
                         app.handler.swapchain.as_ref().unwrap().extent.height as f32 / 2.0
                     ));
                 }
 
+                if let Some(watcher) = &mut app.shader_watcher {
+                    let changed = watcher.changed_shaders();
+                    if !changed.is_empty() {
+                        let reloaded = changed.iter()
+                            .map(|name| watcher.compile(name).map(|code| (name.clone(), code)))
+                            .collect::<Result<Vec<_>, _>>()
+                            .and_then(|shaders| unsafe { app.handler.reload_shaders(shaders) })
+                            .map(|_| changed);
+                        if let Err(err) = &reloaded {
+                            println!("Failed to reload shaders, keeping the old pipelines:\n{err}");
+                        }
+                        app.world.add_window_event(WorldEvent::ShadersReloaded(reloaded));
+                    }
+                }
+
                 // update app state
                 app.world.update();
             }
@@ -287,11 +277,24 @@ impl MatrixagonApp {
             Event::RedrawEventsCleared => unsafe {
                 // final event (for drawing and benchmarking)
                 if app.window_render {
-                    app.world.render(app.handler.obtain_shader_mut_ref());
+                    let frame = app.handler.begin_frame();
+                    app.world.write_uniforms(frame);
+
+                    app.handler.recreate_buffers(app.world.render());
 
                     // app.ui_handler.handle_output();
 
                     app.handler.draw_frame();
+
+                    if app.screenshot_requested {
+                        app.screenshot_requested = false;
+                        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+                        let path = app.screenshot_dir.join(format!("screenshot_{millis}.png"));
+                        match app.handler.capture_frame().and_then(|screenshot| screenshot.save_png(&path)) {
+                            Ok(()) => println!("Saved screenshot {}", path.display()),
+                            Err(err) => println!("Failed to take screenshot: {err}"),
+                        }
+                    }
                 }
             }
             Event::WindowEvent {event: win_event, window_id: _} => {
@@ -312,8 +315,16 @@ impl MatrixagonApp {
                             app.window_render = false;
                         } else {
                             app.window_render = true;
+
+                            let extent = unsafe {
+                                app.handler.resize(vk::Extent2D {width: size.width, height: size.height})
+                            };
+                            app.world.add_window_event(WorldEvent::WindowResized((extent.width, extent.height)));
                         }
                     }
+                    WindowEvent::CursorMoved {position, ..} if app.settings_open => {
+                        app.world.add_window_event(WorldEvent::CursorMoved((position.x, position.y)));
+                    }
                     WindowEvent::MouseInput {state, button, ..} => match state {
                         ElementState::Pressed => match button {
                             MouseButton::Left => {app.world.add_window_event(WorldEvent::LeftButtonPressed)}
@@ -333,7 +344,7 @@ impl MatrixagonApp {
             }
             Event::DeviceEvent {device_id: _, event: dev_event} => {
                 match dev_event {
-                    DeviceEvent::MouseMotion {delta} => {
+                    DeviceEvent::MouseMotion {delta} if !app.settings_open => {
                         app.world.add_window_event(WorldEvent::MouseMotion(delta));
                     }
                     DeviceEvent::Key(KeyboardInput {state, virtual_keycode, ..}) => {
@@ -352,6 +363,29 @@ impl MatrixagonApp {
                                         VirtualKeyCode::T => {
                                             app.mouse_lock = !app.mouse_lock;
                                         }
+                                        VirtualKeyCode::V => {
+                                            let prsnt_setting = app.handler.swapchain.as_ref().unwrap().present_setting();
+                                            unsafe { app.handler.set_present_setting(prsnt_setting.next()); }
+                                        }
+                                        VirtualKeyCode::F2 => {
+                                            app.screenshot_requested = true;
+                                        }
+                                        VirtualKeyCode::F1 => {
+                                            app.settings_open = !app.settings_open;
+                                            app.world.add_window_event(WorldEvent::SettingsMenu(app.settings_open));
+                                        }
+                                        VirtualKeyCode::F11 => {
+                                            app.display.mode = app.display.mode.next();
+                                            app.display.apply(app.debug_visibility, &app.window);
+                                        }
+                                        VirtualKeyCode::F10 => {
+                                            app.display.monitor = Some(app.display.next_monitor(&app.window));
+                                            app.display.apply(app.debug_visibility, &app.window);
+                                        }
+                                        VirtualKeyCode::F9 => {
+                                            app.display.resolution = app.display.next_resolution(&app.window);
+                                            app.display.apply(app.debug_visibility, &app.window);
+                                        }
                                         _ => {}
                                     };
                                 }
@@ -378,15 +412,210 @@ impl MatrixagonApp {
 }
 
 
+// everything in the world but the debug ui, seen from a camera starting above the origin
+fn world_components(
+    handler: &VulkanHandler, settings: Settings, seed: u32, lod_rings: Vec<LodRing>, chunk_cache_mib: usize,
+    texture_dir: &Path, ratio: f32,
+) -> Vec<Box<dyn Component>> {
+    let frames_in_flight = handler.frames_in_flight();
+    vec![
+        Box::new(TickSync::new(
+            handler.alloc.clone(), handler.device.clone(), frames_in_flight, settings.tick_speed,
+        )),
+        Box::new(WorldFlags::new()),
+        Box::new(TextureHandler::new(handler.vi.clone(), handler.alloc.clone(), handler.device.clone(), handler.uploads.clone(), vec![
+            texture_dir.join("null.png"),
+            texture_dir.join("stone.png"),
+            texture_dir.join("grass_top.png"),
+            texture_dir.join("grass_side.png"),
+            texture_dir.join("dirt.png"),
+            texture_dir.join("sand.png"),
+            texture_dir.join("grass_flora.png"),
+            texture_dir.join("grass_top.png"),
+            texture_dir.join("flower.png"),
+            texture_dir.join("water.png"),
+        ])),
+        Box::new(CameraComponent::new(
+            handler.alloc.clone(), handler.device.clone(), frames_in_flight, ratio, settings,
+            Length3D::new(
+                Length::new::<blox>(0.0),
+                Length::new::<blox>(40.0),
+                Length::new::<blox>(0.0),
+            )
+        )),
+        Box::new(Terrain::new(handler.device.clone(), handler.uploads.clone(), seed, settings.render_distance, lod_rings, chunk_cache_mib*1024*1024, vec![
+            BlockData {
+                ident: "grass_block",
+                texture_id: TextureMapper::Lateral("grass_top", "dirt", "grass_side"),
+                mesh: MeshType::Cube,
+                transparency: TransparencyType::Opaque,
+            },
+            BlockData {
+                ident: "dirt",
+                texture_id: TextureMapper::All("dirt"),
+                mesh: MeshType::Cube,
+                transparency: TransparencyType::Opaque,
+            },
+            BlockData {
+                ident: "stone",
+                texture_id: TextureMapper::All("stone"),
+                mesh: MeshType::Cube,
+                transparency: TransparencyType::Opaque,
+            },
+            BlockData {
+                ident: "sand",
+                texture_id: TextureMapper::All("sand"),
+                mesh: MeshType::Cube,
+                transparency: TransparencyType::Opaque,
+            },
+            BlockData {
+                ident: "grass",
+                texture_id: TextureMapper::All("grass_flora"),
+                mesh: MeshType::XCross,
+                transparency: TransparencyType::Transparent,
+            },
+            BlockData {
+                ident: "flower",
+                texture_id: TextureMapper::All("flower"),
+                mesh: MeshType::XCross,
+                transparency: TransparencyType::Transparent,
+            },
+            BlockData {
+                ident: "water",
+                texture_id: TextureMapper::All("water"),
+                mesh: MeshType::Fluid,
+                transparency: TransparencyType::Translucent,
+            },
+            BlockData {
+                ident: "air",
+                texture_id: TextureMapper::All("null"),
+                mesh: MeshType::Empty,
+                transparency: TransparencyType::Transparent,
+            },
+        ])),
+    ]
+}
+
+// the shaders draw the frame graph into framebuffers of the extent, and get the world's descriptors
+unsafe fn load_shaders(handler: &mut VulkanHandler, world: &mut World, graph: RenderGraph, renderpass: vk::RenderPass, extent: vk::Extent2D) {
+    let frames_in_flight = handler.frames_in_flight();
+    handler.load_shader(ChunkRasterizer::new(
+        handler.deletion.clone(), handler.device.clone(), handler.pipeline_cache.cache(), renderpass, &graph, frames_in_flight, extent
+    ));
+    handler.load_shader(DebugUIRasterizer::new(
+        handler.deletion.clone(), handler.device.clone(), handler.pipeline_cache.cache(), renderpass, &graph, frames_in_flight, extent
+    ));
+
+    let descriptors = world.load_descriptors(handler.get_cmd_buf_context());
+    handler.write_descriptors(descriptors);
+
+    handler.load_render_graph(graph, renderpass);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::offscreen::Screenshot;
+    use crate::swapchain::best_depth_format_support;
+
+    // the terrain of a seeded world without the debug ui, from the camera's starting position
+    unsafe fn render_fixed_camera(seed: u32, extent: vk::Extent2D) -> Screenshot {
+        let dbv = DebugVisibility {vk_setup_output: false, mtxg_output: false, vk_swapchain_output: false, mtxg_render_output: false};
+        let pipeline_cache_path = std::env::temp_dir().join(format!("mtxg2_render_test_{}.bin", std::process::id()));
+        let mut handler = VulkanHandler::init_headless(false, dbv, 1, pipeline_cache_path.clone());
+
+        let config = AppConfig::default();
+        let settings = Settings {render_distance: 2, ..Settings::default()};
+        let mut world = World::new(dbv, world_components(
+            &handler, settings, seed, vec![LodRing::default_rings().remove(0)], 64,
+            &config.asset_root.join("block_textures"), extent.width as f32/extent.height as f32,
+        ));
+
+        let format = (vk::Format::R8G8B8A8_SRGB, best_depth_format_support(dbv, &handler.vi));
+        let graph = frame_graph();
+        let renderpass = graph.create_renderpass(handler.device.clone(), format.0, format.1);
+        load_shaders(&mut handler, &mut world, graph, renderpass, extent);
+        let target = handler.create_offscreen_target(format, extent);
+
+        // the chunks are meshed over several updates and only handed over once their uploads completed, waiting
+        // for the device every update the terrain is complete after a few updates without any
+        let mut quiet_updates = 0;
+        for _ in 0..1000 {
+            let frame = handler.begin_frame();
+            world.update();
+            world.write_uniforms(frame);
+            let render_data = world.render();
+            quiet_updates = if render_data.is_empty() {quiet_updates+1} else {0};
+            handler.recreate_buffers(render_data);
+            handler.device.device_wait_idle().unwrap();
+            if quiet_updates == 10 {
+                break;
+            }
+        }
+        assert_eq!(quiet_updates, 10, "The terrain did not finish loading");
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//
-//     #[test]
-//     fn test_fps_clock() {
-//         loop {
-//             thread
-//         }
-//     }
-// }
+        let screenshot = handler.render_offscreen(&target).expect("Failed to read back the frame");
+
+        handler.device.device_wait_idle().unwrap();
+        target.destroy();
+        world.destroy_descriptors();
+        handler.destroy();
+        let _ = fs::remove_file(pipeline_cache_path);
+        screenshot
+    }
+
+    // drivers may round the blending and filtering differently, so each channel may be off by this much
+    const GOLDEN_TOLERANCE: u8 = 8;
+
+    fn load_golden(path: &Path) -> Result<Screenshot, String> {
+        let file = fs::File::open(path).map_err(|err| format!("Failed to open {}: {err}", path.display()))?;
+        let mut reader = png::Decoder::new(file).read_info().map_err(|err| format!("Failed to decode {}: {err}", path.display()))?;
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba).map_err(|err| format!("Failed to decode {}: {err}", path.display()))?;
+        if (info.color_type, info.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+            return Err(format!("{} is not an 8 bit RGBA image", path.display()));
+        }
+        rgba.truncate(info.buffer_size());
+        Ok(Screenshot {width: info.width, height: info.height, rgba})
+    }
+
+    // the first pixel that differs from the golden image by more than the tolerance
+    fn golden_mismatch(golden: &Screenshot, actual: &Screenshot) -> Option<String> {
+        if (golden.width, golden.height) != (actual.width, actual.height) {
+            return Some(format!("the golden image is {}x{}, the frame {}x{}", golden.width, golden.height, actual.width, actual.height));
+        }
+        let (i, (expected, got)) = golden.rgba.chunks_exact(4).zip(actual.rgba.chunks_exact(4)).enumerate()
+            .find(|(_, (expected, got))| expected.iter().zip(*got).any(|(e, g)| e.abs_diff(*g) > GOLDEN_TOLERANCE))?;
+        let (x, y) = (i as u32 % actual.width, i as u32 / actual.width);
+        Some(format!("pixel ({x}, {y}) is {got:?} instead of {expected:?}"))
+    }
+
+    // compared against a committed image, set MTXG2_UPDATE_GOLDEN to overwrite it after an intended change
+    #[test]
+    #[cfg_attr(not(vulkan_driver), ignore = "no Vulkan driver was found when building")]
+    fn fixed_camera_render() {
+        let extent = vk::Extent2D {width: 160, height: 90};
+        let actual = unsafe { render_fixed_camera(AppConfig::DEFAULT_SEED, extent) };
+        assert_eq!((actual.width, actual.height), (160, 90));
+
+        let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        let golden_path = golden_dir.join("fixed_camera.png");
+        let actual_path = golden_dir.join("fixed_camera.actual.png");
+        if std::env::var_os("MTXG2_UPDATE_GOLDEN").is_some() {
+            actual.save_png(&golden_path).unwrap();
+            return;
+        }
+
+        let mismatch = match load_golden(&golden_path) {
+            Ok(golden) => golden_mismatch(&golden, &actual),
+            Err(reason) => Some(reason),
+        };
+        if let Some(mismatch) = mismatch {
+            actual.save_png(&actual_path).unwrap();
+            panic!("The frame does not match {}: {mismatch}, it was saved to {}", golden_path.display(), actual_path.display());
+        }
+        let _ = fs::remove_file(actual_path);
+    }
+}
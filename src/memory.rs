@@ -0,0 +1,352 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi;
+use std::rc::Rc;
+use ash::{Device, vk};
+use crate::debug::DebugVisibility;
+use crate::handler::VulkanInstance;
+use crate::util::find_memory_type;
+
+
+// every memory type grows in blocks of this size, anything bigger than half a block gets its own
+const MEMORY_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+// buffers and optimal images never share a block, so bufferImageGranularity never has to be honoured
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum AllocationKind {
+    Buffer,
+    Image,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Allocation {
+    pub(crate) memory: vk::DeviceMemory,
+    pub(crate) offset: vk::DeviceSize,
+    pub(crate) size: vk::DeviceSize,
+    pub(crate) mapped_ptr: *mut ffi::c_void,  // null if the memory type is not host visible
+    block_id: u64,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct MemoryStats {
+    pub(crate) block_count: usize,
+    pub(crate) dedicated_block_count: usize,
+    pub(crate) allocation_count: usize,
+    pub(crate) reserved_bytes: vk::DeviceSize,
+    pub(crate) used_bytes: vk::DeviceSize,
+    pub(crate) free_region_count: usize,
+    pub(crate) largest_free_region: vk::DeviceSize,
+}
+
+impl MemoryStats {
+    // 0.0 when all the free space is contiguous, approaching 1.0 as it gets scattered into small holes
+    pub(crate) fn fragmentation(&self) -> f32 {
+        let free_bytes = self.reserved_bytes - self.used_bytes;
+        if free_bytes == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_region as f32 / free_bytes as f32
+        }
+    }
+}
+
+// sorted list of the free (offset, size) regions inside a block
+#[derive(Debug)]
+struct FreeList {
+    regions: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl FreeList {
+    fn new(size: vk::DeviceSize) -> Self {
+        Self { regions: vec![(0, size)] }
+    }
+
+    // first fit, the alignment padding in front of the allocation stays as its own free region
+    fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        for i in 0..self.regions.len() {
+            let (region_ofs, region_size) = self.regions[i];
+            let aligned_ofs = align_up(region_ofs, alignment);
+            let padding = aligned_ofs - region_ofs;
+
+            if padding + size > region_size {
+                continue;
+            }
+
+            let remainder = region_size - padding - size;
+            self.regions.remove(i);
+            if remainder > 0 {
+                self.regions.insert(i, (aligned_ofs + size, remainder));
+            }
+            if padding > 0 {
+                self.regions.insert(i, (region_ofs, padding));
+            }
+            return Some(aligned_ofs);
+        }
+        None
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let i = self.regions.partition_point(|(ofs, _)| *ofs < offset);
+        self.regions.insert(i, (offset, size));
+
+        // merge with the next region, then with the previous one
+        if i + 1 < self.regions.len() && self.regions[i].0 + self.regions[i].1 == self.regions[i+1].0 {
+            self.regions[i].1 += self.regions[i+1].1;
+            self.regions.remove(i+1);
+        }
+        if i > 0 && self.regions[i-1].0 + self.regions[i-1].1 == self.regions[i].0 {
+            self.regions[i-1].1 += self.regions[i].1;
+            self.regions.remove(i);
+        }
+    }
+
+    fn largest_region(&self) -> vk::DeviceSize {
+        self.regions.iter().map(|(_, size)| *size).max().unwrap_or(0)
+    }
+}
+
+pub(crate) fn align_up(offset: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    if alignment <= 1 {
+        offset
+    } else {
+        offset.div_ceil(alignment) * alignment
+    }
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    kind: AllocationKind,
+    dedicated: bool,
+    size: vk::DeviceSize,
+    mapped_ptr: *mut ffi::c_void,
+
+    free_list: FreeList,
+    allocation_count: usize,
+    used_bytes: vk::DeviceSize,
+}
+
+// sub-allocates buffers and images out of large device memory blocks, one set of blocks per memory type
+pub(crate) struct MemoryAllocator {
+    dbgv: DebugVisibility,
+    vi: Rc<VulkanInstance>,
+    device: Rc<Device>,
+
+    blocks: RefCell<BTreeMap<u64, MemoryBlock>>,
+    next_block_id: Cell<u64>,
+}
+
+impl MemoryAllocator {
+    pub(crate) fn new(dbgv: DebugVisibility, vi: Rc<VulkanInstance>, device: Rc<Device>) -> Self {
+        Self {
+            dbgv, vi, device,
+            blocks: RefCell::new(BTreeMap::new()),
+            next_block_id: Cell::new(0),
+        }
+    }
+
+    pub(crate) unsafe fn allocate(
+        &self, mem_req: vk::MemoryRequirements, props: vk::MemoryPropertyFlags, kind: AllocationKind
+    ) -> Allocation {
+        let memory_type = find_memory_type(self.vi.clone(), mem_req, props);
+        let mut blocks = self.blocks.borrow_mut();
+
+        if mem_req.size <= MEMORY_BLOCK_SIZE / 2 {
+            for (block_id, block) in blocks.iter_mut() {
+                if block.dedicated || block.memory_type != memory_type || block.kind != kind {
+                    continue;
+                }
+                if let Some(offset) = block.free_list.allocate(mem_req.size, mem_req.alignment) {
+                    block.allocation_count += 1;
+                    block.used_bytes += mem_req.size;
+                    return block.allocation(*block_id, offset, mem_req.size);
+                }
+            }
+        }
+
+        let dedicated = mem_req.size > MEMORY_BLOCK_SIZE / 2;
+        let block_size = if dedicated { mem_req.size } else { MEMORY_BLOCK_SIZE };
+        let mut block = self.allocate_block(memory_type, kind, dedicated, block_size);
+
+        let offset = block.free_list.allocate(mem_req.size, mem_req.alignment)
+            .expect("Failed to sub-allocate from a fresh memory block");
+        block.allocation_count += 1;
+        block.used_bytes += mem_req.size;
+
+        let block_id = self.next_block_id.get();
+        self.next_block_id.set(block_id + 1);
+        let allocation = block.allocation(block_id, offset, mem_req.size);
+        blocks.insert(block_id, block);
+
+        allocation
+    }
+
+    unsafe fn allocate_block(
+        &self, memory_type: u32, kind: AllocationKind, dedicated: bool, size: vk::DeviceSize
+    ) -> MemoryBlock {
+        let alloc_info = vk::MemoryAllocateInfo {
+            allocation_size: size,
+            memory_type_index: memory_type,
+            ..Default::default()
+        };
+        let memory = self.device.allocate_memory(&alloc_info, None)
+            .expect("Failed to allocate device memory block");
+
+        // host visible blocks stay persistently mapped for their whole lifetime
+        let mem_props = self.vi.get_physical_device_memory_properties();
+        let mapped_ptr = if mem_props.memory_types[memory_type as usize].property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .expect("Failed to map device memory block")
+        } else {
+            std::ptr::null_mut()
+        };
+
+        if self.dbgv.vk_setup_output {
+            println!("Allocated {} memory block of {size} bytes (memory type {memory_type}, {kind:?})", if dedicated {"dedicated"} else {"shared"});
+        }
+
+        MemoryBlock {
+            memory, memory_type, kind, dedicated, size, mapped_ptr,
+            free_list: FreeList::new(size),
+            allocation_count: 0,
+            used_bytes: 0,
+        }
+    }
+
+    pub(crate) unsafe fn free(&self, allocation: Allocation) {
+        let mut blocks = self.blocks.borrow_mut();
+        let block = blocks.get_mut(&allocation.block_id)
+            .expect("Freed an allocation whose memory block no longer exists");
+
+        block.free_list.free(allocation.offset, allocation.size);
+        block.allocation_count -= 1;
+        block.used_bytes -= allocation.size;
+
+        if block.allocation_count > 0 {
+            return;
+        }
+
+        // keep a single empty shared block around per memory type to avoid thrashing vkAllocateMemory
+        let (memory_type, kind, dedicated) = (block.memory_type, block.kind, block.dedicated);
+        let spare_exists = blocks.iter().any(|(id, b)| {
+            *id != allocation.block_id && !b.dedicated && b.memory_type == memory_type && b.kind == kind && b.allocation_count == 0
+        });
+        if dedicated || spare_exists {
+            let block = blocks.remove(&allocation.block_id).unwrap();
+            self.free_block(block);
+        }
+    }
+
+    unsafe fn free_block(&self, block: MemoryBlock) {
+        if !block.mapped_ptr.is_null() {
+            self.device.unmap_memory(block.memory);
+        }
+        self.device.free_memory(block.memory, None);
+    }
+
+    pub(crate) unsafe fn destroy_buffer(&self, buf: vk::Buffer, allocation: Allocation) {
+        self.device.destroy_buffer(buf, None);
+        self.free(allocation);
+    }
+
+    pub(crate) unsafe fn destroy_image(&self, img: vk::Image, allocation: Allocation) {
+        self.device.destroy_image(img, None);
+        self.free(allocation);
+    }
+
+    pub(crate) fn stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for block in self.blocks.borrow().values() {
+            stats.block_count += 1;
+            if block.dedicated {
+                stats.dedicated_block_count += 1;
+            }
+            stats.allocation_count += block.allocation_count;
+            stats.reserved_bytes += block.size;
+            stats.used_bytes += block.used_bytes;
+            stats.free_region_count += block.free_list.regions.len();
+            stats.largest_free_region = stats.largest_free_region.max(block.free_list.largest_region());
+        }
+        stats
+    }
+
+    pub(crate) unsafe fn destroy(&self) {
+        if self.dbgv.vk_setup_output {
+            let stats = self.stats();
+            println!("Memory allocator at shutdown: {stats:?} (fragmentation {:.3})", stats.fragmentation());
+        }
+
+        // anything still alive at this point was never handed back, report it before pulling the memory
+        for (block_id, block) in self.blocks.borrow().iter() {
+            if block.allocation_count > 0 {
+                println!(
+                    "MEMORY LEAK: {} allocation(s) totalling {} bytes still alive in block {block_id} (memory type {}, {:?})",
+                    block.allocation_count, block.used_bytes, block.memory_type, block.kind
+                );
+            }
+        }
+
+        let blocks = std::mem::take(&mut *self.blocks.borrow_mut());
+        for block in blocks.into_values() {
+            self.free_block(block);
+        }
+    }
+}
+
+impl MemoryBlock {
+    fn allocation(&self, block_id: u64, offset: vk::DeviceSize, size: vk::DeviceSize) -> Allocation {
+        Allocation {
+            memory: self.memory,
+            offset,
+            size,
+            mapped_ptr: if self.mapped_ptr.is_null() {
+                self.mapped_ptr
+            } else {
+                unsafe { self.mapped_ptr.add(offset as usize) }
+            },
+            block_id,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alignment_padding_is_kept_free() {
+        let mut free_list = FreeList::new(1024);
+        assert_eq!(free_list.allocate(10, 1), Some(0));
+        assert_eq!(free_list.allocate(100, 256), Some(256));
+        assert_eq!(free_list.regions, vec![(10, 246), (356, 668)]);
+        assert_eq!(free_list.allocate(200, 4), Some(12));
+    }
+
+    #[test]
+    fn freed_regions_coalesce() {
+        let mut free_list = FreeList::new(300);
+        let a = free_list.allocate(100, 1).unwrap();
+        let b = free_list.allocate(100, 1).unwrap();
+        let c = free_list.allocate(100, 1).unwrap();
+        assert_eq!(free_list.allocate(1, 1), None);
+
+        free_list.free(a, 100);
+        free_list.free(c, 100);
+        assert_eq!(free_list.regions.len(), 2);
+        assert_eq!(free_list.largest_region(), 100);
+
+        free_list.free(b, 100);
+        assert_eq!(free_list.regions, vec![(0, 300)]);
+    }
+
+    #[test]
+    fn fragmentation_ratio() {
+        let stats = MemoryStats {
+            reserved_bytes: 1000, used_bytes: 600, largest_free_region: 100,
+            ..Default::default()
+        };
+        assert_eq!(stats.fragmentation(), 0.75);
+        assert_eq!(MemoryStats::default().fragmentation(), 0.0);
+    }
+}
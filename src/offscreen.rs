@@ -0,0 +1,189 @@
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+use std::rc::Rc;
+use ash::{Device, vk};
+use crate::debug::DebugVisibility;
+use crate::framebuffer::FramebufferManager;
+use crate::memory::{Allocation, MemoryAllocator};
+use crate::render_graph::RenderGraph;
+use crate::util::{allocate_buffer, create_local_image};
+
+
+// a frame read back from the gpu, as 8 bit rgba rows from the top
+#[derive(Clone, Debug, PartialEq)]
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Screenshot {
+    // the raw pixels of an image in the given format, only 8 bit rgba and bgra formats are supported
+    pub(crate) fn from_pixels(extent: vk::Extent2D, format: vk::Format, pixels: &[u8]) -> Result<Self, String> {
+        let bgra = match format {
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => true,
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => false,
+            _ => return Err(format!("Screenshots of {format:?} images are not supported")),
+        };
+        let mut rgba = pixels[..(4*extent.width*extent.height) as usize].to_vec();
+        if bgra {
+            rgba.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+        Ok(Self {width: extent.width, height: extent.height, rgba})
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("Failed to create {}: {err}", dir.display()))?;
+        }
+        let file = fs::File::create(path).map_err(|err| format!("Failed to create {}: {err}", path.display()))?;
+
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.rgba))
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+    }
+}
+
+
+// stands in for the swapchain: a frame is rendered into its image and copied back to host memory
+// instead of being presented
+pub(crate) struct OffscreenTarget {
+    device: Rc<Device>,
+    alloc: Rc<MemoryAllocator>,
+
+    img: vk::Image,
+    img_alloc: Allocation,
+    pub(crate) fbm: FramebufferManager,  // with a single framebuffer
+    readback: vk::Buffer,
+    readback_alloc: Allocation,
+
+    format: vk::Format,
+    pub(crate) extent: vk::Extent2D,
+}
+
+impl OffscreenTarget {
+    // the formats are the ones the render pass was created with
+    pub(crate) unsafe fn new(
+        dbv: DebugVisibility, alloc: Rc<MemoryAllocator>, device: Rc<Device>, renderpass: vk::RenderPass, graph: &RenderGraph,
+        (color_fmt, depth_fmt): (vk::Format, vk::Format), extent: vk::Extent2D,
+    ) -> Self {
+        let swapchain_usage = graph.swapchain_usage();
+        let (img, img_alloc) = create_local_image(
+            alloc.clone(), device.clone(),
+            vk::Extent3D {width: extent.width, height: extent.height, depth: 1},
+            1, color_fmt, swapchain_usage | vk::ImageUsageFlags::TRANSFER_SRC, None
+        );
+
+        // the image takes the place of the swapchain images, the other attachments are created as usual
+        let fbm = FramebufferManager::new_swapchain_bounded(
+            dbv, alloc.clone(), device.clone(), renderpass, graph.framebuffer_attachments(), vec![img],
+            color_fmt, depth_fmt, extent,
+        );
+
+        let (readback, readback_alloc, _) = allocate_buffer(
+            alloc.clone(), device.clone(), (4*extent.width*extent.height) as vk::DeviceSize, vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+        );
+
+        Self {device, alloc, img, img_alloc, fbm, readback, readback_alloc, format: color_fmt, extent}
+    }
+
+    // copies the rendered image into the readback buffer, recorded after the render pass which left it ready to present
+    pub(crate) unsafe fn record_readback(&self, cmd_buf: vk::CommandBuffer) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_transfer = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: self.img,
+            subresource_range,
+            ..Default::default()
+        };
+        self.device.cmd_pipeline_barrier(
+            cmd_buf, vk::PipelineStageFlags::ALL_COMMANDS, vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(), &[], &[], &[to_transfer]
+        );
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,  // tightly packed
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {x: 0, y: 0, z: 0},
+            image_extent: vk::Extent3D {width: self.extent.width, height: self.extent.height, depth: 1},
+        };
+        self.device.cmd_copy_image_to_buffer(cmd_buf, self.img, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, self.readback, &[region]);
+
+        let to_host = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: self.readback,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+        self.device.cmd_pipeline_barrier(
+            cmd_buf, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(), &[], &[to_host], &[]
+        );
+    }
+
+    // only valid once the commands recorded by record_readback have finished
+    pub(crate) unsafe fn screenshot(&self) -> Result<Screenshot, String> {
+        let pixels = std::slice::from_raw_parts(
+            self.readback_alloc.mapped_ptr as *const u8, (4*self.extent.width*self.extent.height) as usize
+        );
+        Screenshot::from_pixels(self.extent, self.format, pixels)
+    }
+
+    pub(crate) unsafe fn destroy(&self) {
+        self.fbm.destroy();
+        self.alloc.destroy_image(self.img, self.img_alloc);
+        self.alloc.destroy_buffer(self.readback, self.readback_alloc);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screenshot_png_round_trip() {
+        let extent = vk::Extent2D {width: 2, height: 1};
+        let bgra = [10, 20, 30, 255, 40, 50, 60, 128];
+        let screenshot = Screenshot::from_pixels(extent, vk::Format::B8G8R8A8_SRGB, &bgra).unwrap();
+        assert_eq!(screenshot.rgba, vec![30, 20, 10, 255, 60, 50, 40, 128]);
+        assert!(Screenshot::from_pixels(extent, vk::Format::R16G16B16A16_SFLOAT, &bgra).is_err());
+
+        let path = std::env::temp_dir().join(format!("mtxg2_screenshot_{}.png", std::process::id()));
+        screenshot.save_png(&path).unwrap();
+        let mut reader = png::Decoder::new(fs::File::open(&path).unwrap()).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height, info.color_type), (2, 1, png::ColorType::Rgba));
+        assert_eq!(decoded, screenshot.rgba);
+    }
+}
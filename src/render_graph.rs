@@ -0,0 +1,365 @@
+use std::rc::Rc;
+use ash::{Device, vk};
+use crate::framebuffer::FBAttachmentRef;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum AttachmentSource {
+    Swapchain,  // the presented image, always the first attachment
+    Depth,
+}
+
+pub(crate) struct GraphAttachment {
+    pub(crate) name: &'static str,
+    pub(crate) source: AttachmentSource,
+    pub(crate) clear: Option<vk::ClearValue>,  // the contents are undefined at the first use otherwise
+}
+
+// a subpass, by the attachments it reads and writes
+pub(crate) struct GraphPass {
+    pub(crate) name: &'static str,
+    pub(crate) input: Vec<&'static str>,
+    pub(crate) color: Vec<&'static str>,
+    pub(crate) depth: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SubpassAttachments {
+    pub(crate) input: Vec<vk::AttachmentReference>,
+    pub(crate) color: Vec<vk::AttachmentReference>,
+    pub(crate) depth: Option<vk::AttachmentReference>,
+    pub(crate) preserve: Vec<u32>,
+}
+
+// everything needed to create the render pass, derived from the passes
+#[derive(Clone, Debug)]
+pub(crate) struct RenderPassDescription {
+    pub(crate) attachments: Vec<vk::AttachmentDescription>,
+    pub(crate) subpasses: Vec<SubpassAttachments>,
+    pub(crate) dependencies: Vec<vk::SubpassDependency>,
+}
+
+#[derive(Copy, Clone)]
+struct AttachmentUse {
+    pass: usize,
+    input: bool,
+    written: bool,  // as a color or depth attachment
+}
+
+// the passes run as subpasses of a single render pass in the order they are given. attachments other
+// than the swapchain image only live within the render pass, so they are transient
+pub(crate) struct RenderGraph {
+    attachments: Vec<GraphAttachment>,
+    passes: Vec<GraphPass>,
+}
+
+impl RenderGraph {
+    pub(crate) fn new(attachments: Vec<GraphAttachment>, passes: Vec<GraphPass>) -> Self {
+        assert!(attachments.first().is_some_and(|a| a.source == AttachmentSource::Swapchain), "The swapchain image has to be the first attachment");
+        assert_eq!(attachments.iter().filter(|a| a.source == AttachmentSource::Swapchain).count(), 1, "Only one swapchain attachment is allowed");
+
+        let graph = Self {attachments, passes};
+        for pass in &graph.passes {
+            for name in pass.input.iter().chain(&pass.color).chain(&pass.depth) {
+                graph.attachment_index(name);
+            }
+            if let Some(depth) = pass.depth {
+                assert_eq!(graph.attachments[graph.attachment_index(depth)].source, AttachmentSource::Depth, "{depth} is not a depth attachment");
+            }
+            for color in &pass.color {
+                assert_ne!(graph.attachments[graph.attachment_index(color)].source, AttachmentSource::Depth, "{color} is a depth attachment");
+            }
+        }
+        graph
+    }
+
+    fn attachment_index(&self, name: &str) -> usize {
+        self.attachments.iter().position(|a| a.name == name)
+            .unwrap_or_else(|| panic!("Unknown render graph attachment {name}"))
+    }
+
+    pub(crate) fn subpass(&self, name: &str) -> u32 {
+        self.passes.iter().position(|p| p.name == name)
+            .unwrap_or_else(|| panic!("Unknown render graph pass {name}")) as u32
+    }
+
+    pub(crate) fn pass_names(&self) -> impl Iterator<Item=&'static str> + '_ {
+        self.passes.iter().map(|p| p.name)
+    }
+
+    pub(crate) fn clear_values(&self) -> Vec<vk::ClearValue> {
+        self.attachments.iter()
+            .map(|a| a.clear.unwrap_or_default())
+            .collect()
+    }
+
+    // every pass using the attachment, in order
+    fn uses(&self, attachment: usize) -> Vec<AttachmentUse> {
+        let name = self.attachments[attachment].name;
+        self.passes.iter().enumerate()
+            .map(|(pass, p)| AttachmentUse {
+                pass,
+                input: p.input.contains(&name),
+                written: p.color.contains(&name) || p.depth == Some(name),
+            })
+            .filter(|u| u.input || u.written)
+            .collect()
+    }
+
+    fn usage(&self, attachment: usize) -> vk::ImageUsageFlags {
+        let a = &self.attachments[attachment];
+        let mut usage = match a.source {
+            AttachmentSource::Depth => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            _ => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        };
+        if self.uses(attachment).iter().any(|u| u.input) {
+            usage |= vk::ImageUsageFlags::INPUT_ATTACHMENT;
+        }
+        if a.source != AttachmentSource::Swapchain {
+            usage |= vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+        }
+        usage
+    }
+
+    // the attachments the framebuffers are made of, after the swapchain image
+    pub(crate) fn framebuffer_attachments(&self) -> Vec<FBAttachmentRef> {
+        (1..self.attachments.len())
+            .map(|i| match self.attachments[i].source {
+                AttachmentSource::Depth => FBAttachmentRef::Depth(self.usage(i)),
+                _ => FBAttachmentRef::Color(self.usage(i)),
+            })
+            .collect()
+    }
+
+    pub(crate) fn swapchain_usage(&self) -> vk::ImageUsageFlags {
+        self.usage(0)
+    }
+
+    // the attachments the pass reads and the layout they are read in, in the order the subpass references them
+    pub(crate) fn input_attachments(&self, pass: &str) -> Vec<(usize, vk::ImageLayout)> {
+        let pass = self.subpass(pass) as usize;
+        let mut inputs = (0..self.attachments.len())
+            .filter_map(|i| {
+                let u = self.uses(i).into_iter().find(|u| u.pass == pass && u.input)?;
+                Some((i, self.layout(i, u)))
+            })
+            .collect::<Vec<_>>();
+        inputs.sort_by_key(|&(i, _)| self.passes[pass].input.iter().position(|name| self.attachment_index(name) == i));
+        inputs
+    }
+
+    fn layout(&self, attachment: usize, u: AttachmentUse) -> vk::ImageLayout {
+        let depth = self.attachments[attachment].source == AttachmentSource::Depth;
+        match (u.input, u.written, depth) {
+            (true, true, _) => vk::ImageLayout::GENERAL,  // read and written in the same subpass
+            (false, _, false) => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            (false, _, true) => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            (true, false, false) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            (true, false, true) => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    fn stage_access(&self, attachment: usize, u: AttachmentUse) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+        let mut stages = vk::PipelineStageFlags::empty();
+        let mut access = vk::AccessFlags::empty();
+        if u.input {
+            stages |= vk::PipelineStageFlags::FRAGMENT_SHADER;
+            access |= vk::AccessFlags::INPUT_ATTACHMENT_READ;
+        }
+        if u.written {
+            if self.attachments[attachment].source == AttachmentSource::Depth {
+                stages |= vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+                access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+            } else {
+                stages |= vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+                access |= vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+            }
+        }
+        (stages, access)
+    }
+
+    pub(crate) fn describe(&self, color_fmt: vk::Format, depth_fmt: vk::Format) -> RenderPassDescription {
+        let mut attachments = Vec::new();
+        let mut subpasses = vec![SubpassAttachments::default(); self.passes.len()];
+        // (src subpass, dst subpass) -> dependency
+        let mut dependencies: Vec<vk::SubpassDependency> = Vec::new();
+        let mut depend = |src: u32, dst: u32, (src_stage, src_access), (dst_stage, dst_access), flags| {
+            let dependency = match dependencies.iter_mut().find(|d| d.src_subpass == src && d.dst_subpass == dst) {
+                Some(dependency) => dependency,
+                None => {
+                    dependencies.push(vk::SubpassDependency {src_subpass: src, dst_subpass: dst, dependency_flags: flags, ..Default::default()});
+                    dependencies.last_mut().unwrap()
+                }
+            };
+            dependency.src_stage_mask |= src_stage;
+            dependency.src_access_mask |= src_access;
+            dependency.dst_stage_mask |= dst_stage;
+            dependency.dst_access_mask |= dst_access;
+        };
+
+        for (i, a) in self.attachments.iter().enumerate() {
+            let uses = self.uses(i);
+            let swapchain = a.source == AttachmentSource::Swapchain;
+            let load = if a.clear.is_some() {vk::AttachmentLoadOp::CLEAR} else {vk::AttachmentLoadOp::DONT_CARE};
+            let store = if swapchain {vk::AttachmentStoreOp::STORE} else {vk::AttachmentStoreOp::DONT_CARE};
+            let final_layout = match uses.last() {
+                _ if swapchain => vk::ImageLayout::PRESENT_SRC_KHR,
+                Some(last) => self.layout(i, *last),
+                None => vk::ImageLayout::GENERAL,
+            };
+            attachments.push(vk::AttachmentDescription {
+                format: if a.source == AttachmentSource::Depth {depth_fmt} else {color_fmt},
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: load, store_op: store,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE, stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout,
+                ..Default::default()
+            });
+
+            for u in &uses {
+                let reference = vk::AttachmentReference {attachment: i as u32, layout: self.layout(i, *u)};
+                let pass = &self.passes[u.pass];
+                let subpass = &mut subpasses[u.pass];
+                if u.input {
+                    subpass.input.push(reference);
+                }
+                if pass.color.contains(&a.name) {
+                    subpass.color.push(reference);
+                }
+                if pass.depth == Some(a.name) {
+                    subpass.depth = Some(reference);
+                }
+            }
+            // kept intact through the passes in between that do not touch it
+            if let (Some(first), Some(last)) = (uses.first(), uses.last()) {
+                for (pass, subpass) in subpasses.iter_mut().enumerate().take(last.pass).skip(first.pass+1) {
+                    if !uses.iter().any(|u| u.pass == pass) {
+                        subpass.preserve.push(i as u32);
+                    }
+                }
+            }
+
+            // attachments are shared by the frames in flight, so the first use waits on the previous frame's last one
+            if let (Some(first), Some(last)) = (uses.first(), uses.last()) {
+                depend(vk::SUBPASS_EXTERNAL, first.pass as u32, self.stage_access(i, *last), self.stage_access(i, *first), vk::DependencyFlags::empty());
+            }
+            for pair in uses.windows(2) {
+                depend(pair[0].pass as u32, pair[1].pass as u32, self.stage_access(i, pair[0]), self.stage_access(i, pair[1]), vk::DependencyFlags::BY_REGION);
+            }
+        }
+
+        // input and color attachments are referenced in the order the pass lists them
+        for (pass, subpass) in self.passes.iter().zip(&mut subpasses) {
+            subpass.input.sort_by_key(|r| pass.input.iter().position(|name| self.attachment_index(name) == r.attachment as usize));
+            subpass.color.sort_by_key(|r| pass.color.iter().position(|name| self.attachment_index(name) == r.attachment as usize));
+        }
+
+        RenderPassDescription {attachments, subpasses, dependencies}
+    }
+
+    pub(crate) unsafe fn create_renderpass(&self, device: Rc<Device>, color_fmt: vk::Format, depth_fmt: vk::Format) -> vk::RenderPass {
+        let description = self.describe(color_fmt, depth_fmt);
+        let subpasses = description.subpasses.iter()
+            .map(|subpass| {
+                let mut builder = vk::SubpassDescription::builder()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .input_attachments(&subpass.input)
+                    .color_attachments(&subpass.color)
+                    .preserve_attachments(&subpass.preserve);
+                if let Some(depth) = &subpass.depth {
+                    builder = builder.depth_stencil_attachment(depth);
+                }
+                builder.build()
+            })
+            .collect::<Vec<_>>();
+
+        let renderpass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&description.attachments)
+            .subpasses(&subpasses)
+            .dependencies(&description.dependencies)
+            .build();
+        device.create_render_pass(&renderpass_info, None)
+            .expect("Failed to create render pass")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &'static str, source: AttachmentSource, clear: bool) -> GraphAttachment {
+        GraphAttachment {name, source, clear: clear.then(vk::ClearValue::default)}
+    }
+
+    #[test]
+    fn depth_read_by_later_pass() {
+        // the terrain depth is read by a fog pass drawing onto the swapchain image, with the ui on top
+        let graph = RenderGraph::new(vec![
+            attachment("present", AttachmentSource::Swapchain, true),
+            attachment("depth", AttachmentSource::Depth, true),
+        ], vec![
+            GraphPass {name: "terrain", input: vec![], color: vec!["present"], depth: Some("depth")},
+            GraphPass {name: "fog", input: vec!["depth"], color: vec!["present"], depth: None},
+            GraphPass {name: "ui", input: vec![], color: vec!["present"], depth: None},
+        ]);
+        assert_eq!(graph.subpass("ui"), 2);
+
+        let description = graph.describe(vk::Format::B8G8R8A8_SRGB, vk::Format::D32_SFLOAT);
+        let [present, depth] = description.attachments[..] else { panic!("Expected two attachments") };
+        assert_eq!((present.load_op, present.store_op, present.final_layout),
+                   (vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::STORE, vk::ImageLayout::PRESENT_SRC_KHR));
+        assert_eq!((depth.load_op, depth.store_op, depth.final_layout),
+                   (vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::DONT_CARE, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL));
+        assert_eq!(depth.format, vk::Format::D32_SFLOAT);
+
+        let fog = &description.subpasses[1];
+        assert_eq!(fog.input.iter().map(|r| (r.attachment, r.layout)).collect::<Vec<_>>(), vec![(1, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)]);
+        assert_eq!(fog.color.iter().map(|r| r.attachment).collect::<Vec<_>>(), vec![0]);
+        assert!(fog.depth.is_none() && fog.preserve.is_empty());
+        assert_eq!(graph.input_attachments("fog"), vec![(1, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)]);
+        assert!(graph.input_attachments("ui").is_empty());
+
+        let dependency = |src, dst| description.dependencies.iter()
+            .find(|d| d.src_subpass == src && d.dst_subpass == dst)
+            .unwrap_or_else(|| panic!("Missing dependency {src}->{dst}"));
+        let terrain_to_fog = dependency(0, 1);
+        assert!(terrain_to_fog.src_access_mask.contains(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE));
+        assert!(terrain_to_fog.dst_access_mask.contains(vk::AccessFlags::INPUT_ATTACHMENT_READ));
+        assert_eq!(terrain_to_fog.dependency_flags, vk::DependencyFlags::BY_REGION);
+        assert_eq!(dependency(1, 2).dst_stage_mask, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+        assert!(dependency(vk::SUBPASS_EXTERNAL, 0).dst_access_mask.contains(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE));
+
+        assert_eq!(graph.framebuffer_attachments(), vec![
+            FBAttachmentRef::Depth(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT),
+        ]);
+        assert_eq!(graph.swapchain_usage(), vk::ImageUsageFlags::COLOR_ATTACHMENT);
+    }
+
+    #[test]
+    fn preserved_between_passes() {
+        let graph = RenderGraph::new(vec![
+            attachment("present", AttachmentSource::Swapchain, true),
+            attachment("shadow", AttachmentSource::Depth, true),
+        ], vec![
+            GraphPass {name: "shadow", input: vec![], color: vec![], depth: Some("shadow")},
+            GraphPass {name: "terrain", input: vec![], color: vec!["present"], depth: None},
+            GraphPass {name: "lighting", input: vec!["shadow", "present"], color: vec!["present"], depth: None},
+        ]);
+        let description = graph.describe(vk::Format::B8G8R8A8_SRGB, vk::Format::D32_SFLOAT);
+        assert_eq!(description.subpasses[1].preserve, vec![1]);
+        assert_eq!(description.subpasses[2].input.iter().map(|r| r.layout).collect::<Vec<_>>(),
+                   vec![vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, vk::ImageLayout::GENERAL]);
+        assert_eq!(graph.input_attachments("lighting"),
+                   vec![(1, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL), (0, vk::ImageLayout::GENERAL)]);
+    }
+
+    #[test]
+    fn frame_graph_inputs() {
+        // the debug ui reads the presentation image it draws onto
+        let graph = crate::shader::frame_graph();
+        assert_eq!(graph.input_attachments("composition"), vec![(0, vk::ImageLayout::GENERAL)]);
+        assert!(graph.input_attachments("terrain").is_empty());
+    }
+}
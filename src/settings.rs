@@ -0,0 +1,139 @@
+use std::fmt::Write;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+
+// player facing knobs that can be changed while running, persisted as "key = value" lines
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub render_distance: u32,  // in chunks
+    pub fov: f32,  // DEGREES
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    pub move_speed: f32,  // blocks per second
+    pub tick_speed: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            render_distance: 4,
+            fov: 70.0,
+            mouse_sensitivity: 0.05,
+            invert_y: false,
+            move_speed: 1.0,
+            tick_speed: 1.5,
+        }
+    }
+}
+
+impl Settings {
+    pub const RENDER_DISTANCE_RANGE: RangeInclusive<u32> = 1..=32;
+    pub const FOV_RANGE: RangeInclusive<f32> = 30.0..=120.0;
+    pub const MOUSE_SENSITIVITY_RANGE: RangeInclusive<f32> = 0.005..=0.5;
+    pub const MOVE_SPEED_RANGE: RangeInclusive<f32> = 0.1..=100.0;
+
+    // a missing file gives the defaults, it is only created once something is saved
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        if let Err(e) = fs::write(path, self.serialize()) {
+            println!("Failed to save the settings to {path:?}: {e}");
+        }
+    }
+
+    // unknown keys and invalid values are reported and skipped, so an old or hand edited file never
+    // prevents startup
+    pub fn parse(contents: &str) -> Self {
+        let mut settings = Self::default();
+
+        for (ln, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                println!("Settings line {}: expected \"key = value\"", ln+1);
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            let valid = match key {
+                "render_distance" => value.parse().map(|v| settings.render_distance = v).is_ok(),
+                "fov" => value.parse().map(|v| settings.fov = v).is_ok(),
+                "mouse_sensitivity" => value.parse().map(|v| settings.mouse_sensitivity = v).is_ok(),
+                "invert_y" => value.parse().map(|v| settings.invert_y = v).is_ok(),
+                "move_speed" => value.parse().map(|v| settings.move_speed = v).is_ok(),
+                "tick_speed" => value.parse().map(|v| settings.tick_speed = v).is_ok(),
+                _ => {
+                    println!("Settings line {}: unknown setting {key:?}", ln+1);
+                    continue;
+                }
+            };
+            if !valid {
+                println!("Settings line {}: invalid value {value:?} for {key}", ln+1);
+            }
+        }
+
+        settings.clamped()
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = String::from("# Matrixagon 2 settings\n");
+        writeln!(out, "render_distance = {}", self.render_distance).unwrap();
+        writeln!(out, "fov = {}", self.fov).unwrap();
+        writeln!(out, "mouse_sensitivity = {}", self.mouse_sensitivity).unwrap();
+        writeln!(out, "invert_y = {}", self.invert_y).unwrap();
+        writeln!(out, "move_speed = {}", self.move_speed).unwrap();
+        writeln!(out, "tick_speed = {}", self.tick_speed).unwrap();
+        out
+    }
+
+    pub fn clamped(mut self) -> Self {
+        self.render_distance = self.render_distance.clamp(*Self::RENDER_DISTANCE_RANGE.start(), *Self::RENDER_DISTANCE_RANGE.end());
+        self.fov = self.fov.clamp(*Self::FOV_RANGE.start(), *Self::FOV_RANGE.end());
+        self.mouse_sensitivity = self.mouse_sensitivity.clamp(*Self::MOUSE_SENSITIVITY_RANGE.start(), *Self::MOUSE_SENSITIVITY_RANGE.end());
+        self.move_speed = self.move_speed.clamp(*Self::MOVE_SPEED_RANGE.start(), *Self::MOVE_SPEED_RANGE.end());
+        self
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let settings = Settings {
+            render_distance: 12,
+            fov: 90.5,
+            mouse_sensitivity: 0.1,
+            invert_y: true,
+            move_speed: 4.0,
+            tick_speed: 2.0,
+        };
+        assert_eq!(Settings::parse(&settings.serialize()), settings);
+    }
+
+    #[test]
+    fn invalid_entries_fall_back() {
+        let settings = Settings::parse("\
+            # comment\n\
+            fov = wide\n\
+            unknown = 3\n\
+            render_distance = 1000\n\
+            invert_y=true\n\
+            garbage\n");
+        assert_eq!(settings.fov, Settings::default().fov);
+        assert_eq!(settings.render_distance, *Settings::RENDER_DISTANCE_RANGE.end());
+        assert!(settings.invert_y);
+        assert_eq!(Settings::parse(""), Settings::default());
+    }
+}
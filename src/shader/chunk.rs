@@ -1,221 +1,249 @@
+use std::collections::HashMap;
+use std::mem;
 use std::rc::Rc;
 use ash::{Device, vk};
-use crate::component::{RenderData, RenderDataPurpose};
-use crate::framebuffer::FBAttachmentRef;
+use crate::component::{DrawRange, RenderData, RenderDataPurpose};
+use crate::component::terrain::FaceDir;
+use crate::deletion::{DeletionQueue, RetiredResource};
+use crate::framebuffer::PassInputs;
+use crate::memory::Allocation;
+use crate::render_graph::RenderGraph;
+use crate::shader::reflect::check_descriptors;
 use crate::shader::{ColorBlendKind, DescriptorManager, Shader, create_graphics_pipeline, StandardGraphicsPipelineInfo, VBOFS};
-use matrixagon_util::{Vertex, VulkanVertexState, create_renderpass, IndexedBuffer};
+use matrixagon_util::{Vertex, VulkanVertexState, IndexedBuffer};
 
 
-#[derive(Copy, Clone, Debug, Vertex)]
+// the vertex as the mesher builds it, only packed right before it is stored in a chunk
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ChunkVertex {
-    pub(crate) pos: [f32; 3],
+    pub(crate) pos: [f32; 3],  // relative to the chunk origin
     pub(crate) uv: [f32; 2],
     pub(crate) txtr: f32,
 }
 
-// emulating the structure of the EguiVertex
-#[derive(Copy, Clone, Debug, Vertex)]
-pub struct EguiVertex {
-    pub(crate) pos: [f32; 2],
-    pub(crate) uv: [f32; 2],
-    pub(crate) color: [u8; 3],
+impl ChunkVertex {
+    // positions are stored with an offset of one block, since faces reach one block past either end
+    const POS_BITS: u32 = 10;
+    const POS_MASK: u32 = (1 << Self::POS_BITS)-1;
+    pub(crate) const MAX_CHUNK_SIZE: u32 = Self::POS_MASK-2;
+    pub(crate) const FLUID_INSET: f32 = 0.1;
+
+    pub fn pack(&self, face: Option<FaceDir>) -> PackedChunkVertex {
+        // fluid surfaces are the only vertices that sit between two blocks
+        let inset = self.pos[1].fract().abs() > f32::EPSILON;
+        let pos = [self.pos[0], if inset {self.pos[1].ceil()} else {self.pos[1]}, self.pos[2]]
+            .map(|p| {
+                let p = p as i32+1;
+                assert!(0 <= p && p <= Self::POS_MASK as i32, "Chunk vertex position out of packable range: {:?}", self.pos);
+                p as u32
+            });
+        let txtr = self.txtr as u32;
+        assert!(txtr <= u16::MAX as u32, "Texture layer out of packable range: {txtr}");
+
+        PackedChunkVertex {
+            data: [
+                pos[0] | pos[1] << Self::POS_BITS | pos[2] << (2*Self::POS_BITS)
+                    | (self.uv[0] as u32) << 30 | (self.uv[1] as u32) << 31,
+                txtr | face.map_or(7, |f| f as u32) << 16 | (inset as u32) << 21,
+            ]
+        }
+    }
+}
+
+// 8 bytes per vertex, decoded in chunk.vert and chunk_fluid.vert. the chunk origin is given per draw
+//  [0]: x, y, z (10 bits each, offset by one), u, v (1 bit each)
+//  [1]: texture layer (16 bits), face direction (3 bits, 7 if none), ambient occlusion (2 bits,
+//       not generated yet), fluid inset (1 bit)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Vertex)]
+pub struct PackedChunkVertex {
+    pub(crate) data: [u32; 2],
 }
 
+impl PackedChunkVertex {
+    pub fn unpack(&self) -> (ChunkVertex, Option<FaceDir>) {
+        let [lo, hi] = self.data;
+        let axis = |i: u32| ((lo >> (i*ChunkVertex::POS_BITS)) & ChunkVertex::POS_MASK) as f32-1.0;
+        let inset = if (hi >> 21) & 1 == 1 {ChunkVertex::FLUID_INSET} else {0.0};
+        let face = match (hi >> 16) & 7 {
+            0 => Some(FaceDir::FRONT),
+            1 => Some(FaceDir::RIGHT),
+            2 => Some(FaceDir::BACK),
+            3 => Some(FaceDir::LEFT),
+            4 => Some(FaceDir::TOP),
+            5 => Some(FaceDir::BOTTOM),
+            _ => None,
+        };
+
+        (
+            ChunkVertex {
+                pos: [axis(0), axis(1)-inset, axis(2)],
+                uv: [((lo >> 30) & 1) as f32, ((lo >> 31) & 1) as f32],
+                txtr: (hi & 0xFFFF) as f32,
+            },
+            face
+        )
+    }
+}
 
 pub struct ChunkRasterizer {
     device: Rc<Device>,
+    deletion: Rc<DeletionQueue>,
+    pipeline_cache: vk::PipelineCache,  // owned by the handler
+    renderpass: vk::RenderPass,  // owned by the handler
 
     extent: vk::Extent2D,
     descriptor: DescriptorManager,
-    renderpass: vk::RenderPass,
-    clear_values: Vec<vk::ClearValue>,
+    subpass: u32,
 
     terrain_pipeline: vk::Pipeline,
     transparent_pipeline: vk::Pipeline,
     translucent_fluid_pipeline: vk::Pipeline,
 
-    terrain_ivbo: IndexedBuffer,
-    transparent_ivbo: IndexedBuffer,
-    translucent_fluid_ivbo: IndexedBuffer,
+    terrain_ivbo: IndexedBuffer<DeletionQueue>,
+    transparent_ivbo: IndexedBuffer<DeletionQueue>,
+    translucent_fluid_ivbo: IndexedBuffer<DeletionQueue>,
+    terrain_draws: Vec<DrawRange>,
+    transparent_draws: Vec<DrawRange>,
+    translucent_fluid_draws: Vec<DrawRange>,
 
-    // TODO: EGUI debug pipeline extension for this shader
-    debug_scissors: Option<[vk::Rect2D; 1]>,
-    debug_pipeline: vk::Pipeline,
-    debug_ivbo: IndexedBuffer,
+    reloaded_shaders: HashMap<String, Vec<u32>>,  // replace the compiled ones when rebuilding pipelines
 
-    vbo: Option<([vk::Buffer; 1], vk::DeviceMemory)>,
-    ibo: Option<(vk::Buffer, vk::DeviceMemory, u32)>,
+    vbo: Option<([vk::Buffer; 1], Allocation)>,
+    ibo: Option<(vk::Buffer, Allocation, u32)>,
 }
 
 impl ChunkRasterizer {
-    pub(crate) unsafe fn new(device: Rc<Device>, extent: vk::Extent2D, color_format: vk::Format,
-                             depth_format: vk::Format) -> Self {
-        let descriptor = DescriptorManager::new(device.clone(), vec![
+    const PASS: &'static str = "terrain";
+
+    // the sets and bindings the shaders declare, checked against the compiled shaders in the tests
+    pub(crate) fn descriptor_layout() -> Vec<Vec<(vk::DescriptorType, vk::ShaderStageFlags)>> {
+        vec![
             vec![  // set 0 for shader
                 (vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX),  // proj-view
                 (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT),  // textures
             ],
-            vec![  // set 1 for ui  TODO: EGUI debug descriptor-set extension
-                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT), // egui debug ui texture
-                (vk::DescriptorType::INPUT_ATTACHMENT, vk::ShaderStageFlags::FRAGMENT), // input attachment from previous
-            ],
-            vec![  // set 2 for animations
+            vec![  // set 1 for animations
                 (vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX)  // time
             ]
-        ]);
+        ]
+    }
 
-        /* LIMIT IMAGE LAYOUT Most likely the only valid image layout in a graphics pipeline in subpass
-            VK_IMAGE_LAYOUT_GENERAL                             = If same refs are used between input and color/depth
-            VK_IMAGE_LAYOUT_COLOR_ATTACHMENT_OPTIMAL            = Writing color/generic attachments
-            VK_IMAGE_LAYOUT_DEPTH_STENCIL_ATTACHMENT_OPTIMAL    = Writing depth attachment
-            VK_IMAGE_LAYOUT_DEPTH_STENCIL_READ_ONLY_OPTIMAL     = Reading depth attachment
-            VK_IMAGE_LAYOUT_SHADER_READ_ONLY_OPTIMAL            = Reading color/generic attachments
-
-            LIMIT SRC/DST MASK
-            https://github.com/David-DiGioia/vulkan-diagrams?tab=readme-ov-file#pipeline-stages-and-access-types
-            .src_mask(COLOR_ATTACHMENT_OUTPUT(COLOR_ATTACHMENT_WRITE) | EARLY_FRAGMENT_TEST(DEPTH_STENCIL_ATTACHMENT_WRITE))
-            .src_mask(COLOR_ATTACHMENT_OUTPUT(COLOR_ATTACHMENT_WRITE) | EARLY_FRAGMENT_TEST(DEPTH_STENCIL_ATTACHMENT_WRITE))
-            .src_mask(COLOR_ATTACHMENT_OUTPUT(WRITE) | EARLY_FRAGMENT_TEST(WRITE))
-            .src_mask(COLOR_ATTACHMENT_OUTPUT(WRITE) | EARLY_FRAGMENT_TEST(WRITE))
-         */
-
-        let renderpass = create_renderpass!{ [device];
-            Attachments {
-                presentation: {
-                    format: color_format, samples: TYPE_1,
-                    load: CLEAR, store: STORE,
-                    stencil_load: DONT_CARE, stencil_store: DONT_CARE,
-                    initial: UNDEFINED, final: PRESENT_SRC_KHR,
-                }
-                depth: {
-                    format: depth_format, samples: TYPE_1,
-                    load: CLEAR, store: DONT_CARE,
-                    stencil_load: DONT_CARE, stencil_store: DONT_CARE,
-                    initial: UNDEFINED, final: DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                }
-            }
-            Subpasses {
-                terrain: {
-                    input:,
-                    color: presentation~COLOR_ATTACHMENT_OPTIMAL,
-                    resolve:,
-                    preserve:,
-                    depth: depth~DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                }
-                composition: {  // TODO: EGUI debug subpass extension (omittable)
-                    input: presentation~GENERAL,
-                    color: presentation~GENERAL,
-                    resolve:,
-                    preserve:,
-                    depth:,
-                }
-            }
-            Dependencies {
-                ->terrain: {
-                    src_stage:  COLOR_ATTACHMENT_OUTPUT | EARLY_FRAGMENT_TESTS,
-                    dst_stage:  COLOR_ATTACHMENT_OUTPUT | EARLY_FRAGMENT_TESTS,
-                    src_access: ,
-                    dst_access: COLOR_ATTACHMENT_WRITE | DEPTH_STENCIL_ATTACHMENT_WRITE,
-                }
-                terrain->composition: {  // TODO: EGUI debug subpass extension (omittable)
-                    src_stage:  COLOR_ATTACHMENT_OUTPUT,
-                    dst_stage:  FRAGMENT_SHADER,
-                    src_access: COLOR_ATTACHMENT_WRITE,
-                    dst_access: INPUT_ATTACHMENT_READ,
-                }
+    // the opaque, transparent and translucent pipelines, in the order of their fields
+    fn terrain_pipeline_infos(subpass: u32) -> Vec<StandardGraphicsPipelineInfo<'static>> {
+        vec![
+            StandardGraphicsPipelineInfo {  // opaque pipeline
+                shaders: vec![
+                    ("chunk.vert", vk::ShaderStageFlags::VERTEX),
+                    ("chunk.frag", vk::ShaderStageFlags::FRAGMENT),
+                ],
+                vertex_input_state: PackedChunkVertex::VERTEX_INPUT_STATE,
+                back_face_culling: true, depth_testing: true,
+                color_blend_attachment_state: vec![ColorBlendKind::disabled()],
+                subpass_index: subpass,
+            },
+            StandardGraphicsPipelineInfo {  // transparent pipeline
+                shaders: vec![
+                    ("chunk.vert", vk::ShaderStageFlags::VERTEX),
+                    ("chunk_transparent.frag", vk::ShaderStageFlags::FRAGMENT),
+                ],
+                vertex_input_state: PackedChunkVertex::VERTEX_INPUT_STATE,
+                back_face_culling: false, depth_testing: true,
+                color_blend_attachment_state: vec![ColorBlendKind::transparent()],
+                subpass_index: subpass,
+            },
+            StandardGraphicsPipelineInfo {  // translucent pipeline
+                shaders: vec![
+                    ("chunk_fluid.vert", vk::ShaderStageFlags::VERTEX),
+                    ("chunk_translucent.frag", vk::ShaderStageFlags::FRAGMENT),
+                ],
+                vertex_input_state: PackedChunkVertex::VERTEX_INPUT_STATE,
+                back_face_culling: false, depth_testing: true,
+                color_blend_attachment_state: vec![ColorBlendKind::transparent()],
+                subpass_index: subpass,
+            },
+        ]
+    }
+
+    pub(crate) unsafe fn new(deletion: Rc<DeletionQueue>, device: Rc<Device>, pipeline_cache: vk::PipelineCache,
+                             renderpass: vk::RenderPass, graph: &RenderGraph, frames_in_flight: usize, extent: vk::Extent2D) -> Self {
+        let descriptor = DescriptorManager::new(device.clone(), frames_in_flight, Self::descriptor_layout(), vec![
+            vk::PushConstantRange {  // chunk origin
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<[i32; 3]>() as u32,
             }
-        };
+        ]);
 
+        let subpass = graph.subpass(Self::PASS);
         let graphics_pipelines = create_graphics_pipeline(
-            device.clone(),
-            vec![
-                StandardGraphicsPipelineInfo {  // opaque pipeline
-                    shaders: vec![
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/chunk.vert", vk::ShaderStageFlags::VERTEX),
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/chunk.frag", vk::ShaderStageFlags::FRAGMENT),
-                    ],
-                    vertex_input_state: ChunkVertex::VERTEX_INPUT_STATE,
-                    back_face_culling: true, depth_testing: true,
-                    color_blend_attachment_state: vec![ColorBlendKind::disabled()],
-                    subpass_index: 0,
-                },
-                StandardGraphicsPipelineInfo {  // transparent pipeline
-                    shaders: vec![
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/chunk.vert", vk::ShaderStageFlags::VERTEX),
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/chunk_transparent.frag", vk::ShaderStageFlags::FRAGMENT),
-                    ],
-                    vertex_input_state: ChunkVertex::VERTEX_INPUT_STATE,
-                    back_face_culling: false, depth_testing: true,
-                    color_blend_attachment_state: vec![ColorBlendKind::transparent()],
-                    subpass_index: 0,
-                },
-                StandardGraphicsPipelineInfo {  // translucent pipeline
-                    shaders: vec![
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/chunk_fluid.vert", vk::ShaderStageFlags::VERTEX),
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/chunk_translucent.frag", vk::ShaderStageFlags::FRAGMENT),
-                    ],
-                    vertex_input_state: ChunkVertex::VERTEX_INPUT_STATE,
-                    back_face_culling: false, depth_testing: true,
-                    color_blend_attachment_state: vec![ColorBlendKind::transparent()],
-                    subpass_index: 0,
-                },
-            ],
-            descriptor.pipeline_layout, renderpass,
-        );
-
-        // multi-pipeline creation does not like different vertex input, so it's in a separate group
-        let debug_graphics_pipeline = create_graphics_pipeline(
-            device.clone(),
-            vec![
-                StandardGraphicsPipelineInfo {  // TODO: EGUI debug pipeline extension
-                    shaders: vec![
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/debug_ui.vert", vk::ShaderStageFlags::VERTEX),
-                        ("C:/Users/andrewshen/documents/matrixagon2/src/shader/debug_ui.frag", vk::ShaderStageFlags::FRAGMENT),
-                    ],
-                    vertex_input_state: EguiVertex::VERTEX_INPUT_STATE,
-                    back_face_culling: false, depth_testing: false,
-                    color_blend_attachment_state: vec![ColorBlendKind::transparent()],
-                    subpass_index: 1,
-                },
-            ],
-            descriptor.pipeline_layout, renderpass,
-        );
+            device.clone(), Self::terrain_pipeline_infos(subpass), descriptor.pipeline_layout, renderpass, pipeline_cache, &HashMap::new(),
+        ).expect("Failed to create the terrain pipelines");
 
         Self {
             device: device.clone(),
+            deletion: deletion.clone(),
+            pipeline_cache,
+            renderpass,
             extent,
             descriptor,
-            renderpass,
-            clear_values: vec![
-                vk::ClearValue { color: vk::ClearColorValue {float32: [0.2, 0.3, 0.9, 1.0]} },
-                vk::ClearValue { color: vk::ClearColorValue {float32: [0.0, 0.0, 0.0, 0.0]} },
-            ],
+            subpass,
 
             terrain_pipeline: graphics_pipelines[0],
             transparent_pipeline: graphics_pipelines[1],
             translucent_fluid_pipeline: graphics_pipelines[2],
-            terrain_ivbo: IndexedBuffer::new(device.clone()),
-            transparent_ivbo: IndexedBuffer::new(device.clone()),
-            translucent_fluid_ivbo: IndexedBuffer::new(device.clone()),
+            terrain_ivbo: IndexedBuffer::new(deletion.clone()),
+            transparent_ivbo: IndexedBuffer::new(deletion.clone()),
+            translucent_fluid_ivbo: IndexedBuffer::new(deletion.clone()),
+            terrain_draws: Vec::new(),
+            transparent_draws: Vec::new(),
+            translucent_fluid_draws: Vec::new(),
 
-            // TODO: EGUI debug pipeline extension
-            debug_scissors: None,
-            debug_pipeline: debug_graphics_pipeline[0],
-            debug_ivbo: IndexedBuffer::new(device.clone()),
+            reloaded_shaders: HashMap::new(),
 
             vbo: None, ibo: None
         }
     }
-}
 
-impl Shader for ChunkRasterizer {
-    fn renderpass(&self) -> vk::RenderPass {
-        self.renderpass
+    // the packed vertices are relative to their chunk, so each chunk is drawn with its own origin
+    unsafe fn draw_chunks(&self, cmd_buf: vk::CommandBuffer, draws: &[DrawRange]) {
+        for draw in draws {
+            let origin = std::slice::from_raw_parts(draw.origin.as_ptr() as *const u8, std::mem::size_of::<[i32; 3]>());
+            self.device.cmd_push_constants(cmd_buf, self.descriptor.pipeline_layout(), vk::ShaderStageFlags::VERTEX, 0, origin);
+            self.device.cmd_draw_indexed(cmd_buf, draw.index_count, 1, draw.first_index, 0, 0);
+        }
+    }
+
+    unsafe fn draw_terrain(&self, cmd_buf: vk::CommandBuffer) {
+        if let Some((terrain_vbo, terrain_ibo, _)) = self.terrain_ivbo.obtain_indexed_vbo() {
+            // opaque objects
+            self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.terrain_pipeline);
+            self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &terrain_vbo, &VBOFS);
+            self.device.cmd_bind_index_buffer(cmd_buf, terrain_ibo, 0, vk::IndexType::UINT32);
+            self.draw_chunks(cmd_buf, &self.terrain_draws);
+        }
+        if let Some((transparent_vbo, transparent_ibo, _)) = self.transparent_ivbo.obtain_indexed_vbo() {
+            // transparent objects
+            self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.transparent_pipeline);
+            self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &transparent_vbo, &VBOFS);
+            self.device.cmd_bind_index_buffer(cmd_buf, transparent_ibo, 0, vk::IndexType::UINT32);
+            self.draw_chunks(cmd_buf, &self.transparent_draws);
+        }
+        if let Some((translucent_fluid_vbo, translucent_fluid_ibo, _)) = self.translucent_fluid_ivbo.obtain_indexed_vbo() {
+            // translucent objects
+            self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.translucent_fluid_pipeline);
+            self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &translucent_fluid_vbo, &VBOFS);
+            self.device.cmd_bind_index_buffer(cmd_buf, translucent_fluid_ibo, 0, vk::IndexType::UINT32);
+            self.draw_chunks(cmd_buf, &self.translucent_fluid_draws);
+        }
     }
 
-    fn attachments(&self) -> Vec<FBAttachmentRef> {
-        vec![  // TODO: EGUI debug extension
-            FBAttachmentRef::Depth,
+}
+
+impl Shader for ChunkRasterizer {
+    fn purposes(&self) -> &'static [RenderDataPurpose] {
+        &[
+            RenderDataPurpose::CameraViewProjection, RenderDataPurpose::BlockTextures, RenderDataPurpose::Time,
+            RenderDataPurpose::TerrainOpaque, RenderDataPurpose::TerrainTransparent, RenderDataPurpose::TerrainTranslucent,
         ]
     }
 
@@ -223,20 +251,13 @@ impl Shader for ChunkRasterizer {
         for render_data in descriptor_buffers {
             match render_data {
                 RenderData::InitialDescriptorBuffer(buf, RenderDataPurpose::CameraViewProjection) => {
-                    self.descriptor.write_buffer(0, 0, buf);
+                    self.descriptor.write_frame_buffers(0, 0, buf);
                 },
                 RenderData::InitialDescriptorImage(img, RenderDataPurpose::BlockTextures) => {
                     self.descriptor.write_image(0, 1, img);
                 },
-                RenderData::InitialDescriptorImage(img, RenderDataPurpose::DebugUI) => {
-                    // TODO: EGUI debug extension
-                    self.descriptor.write_image(1, 0, img);  // egui debug ui textures
-                }
-                RenderData::InitialDescriptorImage(img, RenderDataPurpose::PresentationInpAttachment) => {
-                    self.descriptor.write_image(1, 1, img);
-                }
                 RenderData::InitialDescriptorBuffer(buf, RenderDataPurpose::Time) => {
-                    self.descriptor.write_buffer(2, 0, buf);
+                    self.descriptor.write_frame_buffers(1, 0, buf);
                 }
                 _ => {},
             }
@@ -273,34 +294,28 @@ impl Shader for ChunkRasterizer {
                 println!("RECREATE [TRANSLUCENT] IBO");
                 self.translucent_fluid_ivbo.recreate_ibo(buf, mem, len);
             }
-            // TODO: EGUI debug data extension
-            RenderData::RecreateVertexBuffer(buf, mem, RenderDataPurpose::DebugUI) => unsafe {
-                // println!("RECREATE [DEBUG UI] VERTEX BUFFER");
-                self.debug_ivbo.recreate_vbo([buf], mem);
+            RenderData::SetDrawRanges(draws, RenderDataPurpose::TerrainOpaque) => {
+                self.terrain_draws = draws;
             }
-            RenderData::RecreateIndexBuffer(buf, mem, len, RenderDataPurpose::DebugUI) => unsafe {
-                // println!("RECREATE [DEBUG UI] INDEX BUFFER");
-                self.debug_ivbo.recreate_ibo(buf, mem, len);
+            RenderData::SetDrawRanges(draws, RenderDataPurpose::TerrainTransparent) => {
+                self.transparent_draws = draws;
             }
-            RenderData::SetScissorDynamicState(scissor, RenderDataPurpose::DebugUI) => unsafe {
-                self.debug_scissors.replace([scissor]);
+            RenderData::SetDrawRanges(draws, RenderDataPurpose::TerrainTranslucent) => {
+                self.translucent_fluid_draws = draws;
             }
             _ => {},
         }
     }
 
-    unsafe fn draw_command(&self, cmd_buf: vk::CommandBuffer, framebuffer: vk::Framebuffer) {
-        let renderpass_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.renderpass)
-            .framebuffer(framebuffer)
-            .render_area(vk::Rect2D { offset: vk::Offset2D {x:0, y:0}, extent: self.extent})
-            .clear_values(&self.clear_values)
-            .build();
+    unsafe fn write_input_attachments(&mut self, _inputs: &PassInputs) {}
 
-        self.device.cmd_begin_render_pass(cmd_buf, &renderpass_info, vk::SubpassContents::INLINE);
+    unsafe fn draw_pass(&self, cmd_buf: vk::CommandBuffer, pass: &str, frame: usize, _image: usize) {
+        if pass != Self::PASS {
+            return;
+        }
 
         self.device.cmd_bind_descriptor_sets(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.descriptor.pipeline_layout(),
-                                             0, &self.descriptor.descriptor_sets(&[0, 1, 2]), &[]);
+                                             0, &self.descriptor.descriptor_sets(frame, &[0, 1]), &[]);
 
         let viewports = [vk::Viewport {
             x: 0.0,
@@ -314,54 +329,60 @@ impl Shader for ChunkRasterizer {
         self.device.cmd_set_viewport(cmd_buf, 0, &viewports);
         self.device.cmd_set_scissor(cmd_buf, 0, &scissors);
 
-        {
-            if let Some((terrain_vbo, terrain_ibo, ibo_len)) = self.terrain_ivbo.obtain_indexed_vbo() {
-                // opaque objects
-                self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.terrain_pipeline);
-                self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &terrain_vbo, &VBOFS);
-                self.device.cmd_bind_index_buffer(cmd_buf, terrain_ibo, 0, vk::IndexType::UINT32);
-                self.device.cmd_draw_indexed(cmd_buf, ibo_len, 1, 0, 0, 0);
-            }
-            if let Some((transparent_vbo, transparent_ibo, ibo_len)) = self.transparent_ivbo.obtain_indexed_vbo() {
-                // transparent objects
-                self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.transparent_pipeline);
-                self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &transparent_vbo, &VBOFS);
-                self.device.cmd_bind_index_buffer(cmd_buf, transparent_ibo, 0, vk::IndexType::UINT32);
-                self.device.cmd_draw_indexed(cmd_buf, ibo_len, 1, 0, 0, 0);
-            }
-            if let Some((translucent_fluid_vbo, translucent_fluid_ibo, ibo_len)) = self.translucent_fluid_ivbo.obtain_indexed_vbo() {
-                // translucent objects
-                self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.translucent_fluid_pipeline);
-                self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &translucent_fluid_vbo, &VBOFS);
-                self.device.cmd_bind_index_buffer(cmd_buf, translucent_fluid_ibo, 0, vk::IndexType::UINT32);
-                self.device.cmd_draw_indexed(cmd_buf, ibo_len, 1, 0, 0, 0);
-            }
-        }
+        self.draw_terrain(cmd_buf);
+    }
 
-        // TODO: EGUI debug draw extension
-        self.device.cmd_next_subpass(cmd_buf, vk::SubpassContents::INLINE);
+    unsafe fn reload_shaders(&mut self, shaders: Vec<(String, Vec<u32>)>) -> Result<(), String> {
+        let layout = Self::descriptor_layout();
+        let infos = Self::terrain_pipeline_infos(self.subpass);
 
-        {
-            if let Some(scissors) = self.debug_scissors {
-                self.device.cmd_set_scissor(cmd_buf, 0, &scissors);
+        // nothing is replaced unless every shader fits the layout in each stage it is used in
+        for (name, code) in &shaders {
+            for (_, stage) in infos.iter().flat_map(|info| &info.shaders).filter(|(shader, _)| shader == name) {
+                check_descriptors(name, code, *stage, &layout)?;
             }
+        }
 
-            if let Some((ui_vbo, ui_ibo, ibo_len)) = self.debug_ivbo.obtain_indexed_vbo() {
-                self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.debug_pipeline);
-                self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &ui_vbo, &VBOFS);
-                self.device.cmd_bind_index_buffer(cmd_buf, ui_ibo, 0, vk::IndexType::UINT32);
-                self.device.cmd_draw_indexed(cmd_buf, ibo_len, 1, 0, 0, 0);
-            }
+        let changed = shaders.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+        let mut reloaded_shaders = self.reloaded_shaders.clone();
+        reloaded_shaders.extend(shaders);
+
+        // the affected pipelines are all rebuilt before any of them is replaced
+        let (replaced, infos): (Vec<usize>, Vec<StandardGraphicsPipelineInfo>) = infos.into_iter().enumerate()
+            .filter(|(_, info)| info.shaders.iter().any(|(shader, _)| changed.iter().any(|name| name == shader)))
+            .unzip();
+        if infos.is_empty() {
+            return Ok(());
         }
+        let rebuilt = create_graphics_pipeline(
+            self.device.clone(), infos, self.descriptor.pipeline_layout, self.renderpass, self.pipeline_cache, &reloaded_shaders,
+        ).map_err(|err| format!("Failed to create the terrain pipelines with {}: {err}", changed.join(", ")))?;
+        self.reloaded_shaders = reloaded_shaders;
+
+        let pipelines = [
+            &mut self.terrain_pipeline, &mut self.transparent_pipeline, &mut self.translucent_fluid_pipeline,
+        ];
+        for (index, rebuilt) in replaced.into_iter().zip(rebuilt) {
+            self.deletion.retire(RetiredResource::Pipeline(mem::replace(pipelines[index], rebuilt)));
+        }
+        Ok(())
+    }
 
-        self.device.cmd_end_render_pass(cmd_buf);
+    unsafe fn set_renderpass(&mut self, renderpass: vk::RenderPass) {
+        self.renderpass = renderpass;
+        let rebuilt = create_graphics_pipeline(
+            self.device.clone(), Self::terrain_pipeline_infos(self.subpass), self.descriptor.pipeline_layout, renderpass,
+            self.pipeline_cache, &self.reloaded_shaders,
+        ).expect("Failed to create the terrain pipelines");
+        let pipelines = [
+            &mut self.terrain_pipeline, &mut self.transparent_pipeline, &mut self.translucent_fluid_pipeline,
+        ];
+        for (pipeline, rebuilt) in pipelines.into_iter().zip(rebuilt) {
+            self.deletion.retire(RetiredResource::Pipeline(mem::replace(pipeline, rebuilt)));
+        }
     }
 
     unsafe fn destroy(&self) {
-        // TODO: EGUI debug extension
-        self.debug_ivbo.destroy();
-        self.device.destroy_pipeline(self.debug_pipeline, None);
-
         self.terrain_ivbo.destroy();
         self.transparent_ivbo.destroy();
         self.translucent_fluid_ivbo.destroy();
@@ -371,6 +392,72 @@ impl Shader for ChunkRasterizer {
         self.device.destroy_pipeline(self.translucent_fluid_pipeline, None);
 
         self.descriptor.destroy();
-        self.device.destroy_render_pass(self.renderpass, None);
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_vertex_round_trip() {
+        assert_eq!(std::mem::size_of::<PackedChunkVertex>(), 8);
+
+        let size = ChunkVertex::MAX_CHUNK_SIZE as f32;
+        let verts = [
+            (ChunkVertex {pos: [0.0, 0.0, -1.0], uv: [0.0, 1.0], txtr: 0.0}, Some(FaceDir::BACK)),
+            (ChunkVertex {pos: [size, size, size-1.0], uv: [1.0, 0.0], txtr: 65535.0}, Some(FaceDir::BOTTOM)),
+            (ChunkVertex {pos: [3.0, 5.0-ChunkVertex::FLUID_INSET, 7.0], uv: [1.0, 1.0], txtr: 42.0}, Some(FaceDir::TOP)),
+            (ChunkVertex {pos: [1.0, -1.0, 2.0], uv: [0.0, 0.0], txtr: 3.0}, None),
+        ];
+        for (vert, face) in verts {
+            let (unpacked, unpacked_face) = vert.pack(face).unpack();
+            assert_eq!(unpacked, vert);
+            assert_eq!(unpacked_face.map(|f| f as u32), face.map(|f| f as u32));
+        }
+    }
+
+    #[test]
+    fn glsl_inputs_match_vertex_structs() {
+        // the vertex shaders include this file, rewrite it from GLSL_INPUT whenever PackedChunkVertex changes
+        let normalized = |glsl: &str| glsl.replace("\r\n", "\n");
+        assert_eq!(normalized(include_str!("include/packed_chunk_vertex.glsl")), PackedChunkVertex::GLSL_INPUT);
+
+        for shader in [include_str!("chunk.vert"), include_str!("chunk_fluid.vert")] {
+            assert!(shader.contains("#include \"include/packed_chunk_vertex.glsl\""));
+        }
+    }
+
+    #[test]
+    fn shader_descriptors_match_layout() {
+        use matrixagon_util::UniformBlock;
+        use crate::component::camera::CameraUBO;
+        use crate::component::tick::TimeUBO;
+        use crate::shader::compiled_shader;
+
+        let layout = ChunkRasterizer::descriptor_layout();
+        let block_offsets = |offsets: &[(&str, usize)]| offsets.iter()
+            .map(|(name, offset)| (name.to_string(), *offset))
+            .collect::<Vec<_>>();
+
+        for (name, stage) in ChunkRasterizer::terrain_pipeline_infos(0).iter().flat_map(|info| info.shaders.clone()) {
+            let descriptors = check_descriptors(name, &compiled_shader(name), stage, &layout).unwrap();
+            for descriptor in descriptors {
+                match (descriptor.set, descriptor.binding) {
+                    (0, 0) => {
+                        assert_eq!(descriptor.type_name.as_deref(), Some("UniformBufferObject"), "{name}");
+                        assert_eq!(descriptor.members, block_offsets(CameraUBO::OFFSETS), "{name}");
+                    }
+                    (0, 1) => assert_eq!(descriptor.image, Some((1, true)), "{name} textures are not a sampler2DArray"),
+                    (1, 0) => {
+                        assert_eq!(descriptor.type_name.as_deref(), Some("TimeObject"), "{name}");
+                        assert_eq!(descriptor.members, block_offsets(TimeUBO::OFFSETS), "{name}");
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+}
@@ -1,23 +1,34 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
 layout(set = 0, binding = 0) uniform UniformBufferObject {
     mat4 view;
     mat4 proj;
+    ivec4 origin;
 } mvp;
 
-layout(set = 2, binding = 0) uniform TimeObject {
+layout(set = 1, binding = 0) uniform TimeObject {
     float time;
 };
 
-layout(location = 0) in vec3 position;
-layout(location = 1) in vec2 uv;
-layout(location = 2) in float ind;
+layout(push_constant) uniform ChunkOrigin {
+    ivec3 origin;
+} chunk;
+
+// see PackedChunkVertex for the layout of data
+#include "include/packed_chunk_vertex.glsl"
 
 layout(location = 0) out float out_ind;
 layout(location = 1) out vec2 tex_coord;
 
 void main() {
-    gl_Position = mvp.proj * mvp.view * vec4(position + vec3(0.0, sin(time + position.x)*0.1, 0.0), 1.0);
-    tex_coord = uv;
-    out_ind = ind;
+    vec3 position = vec3(ivec3(data.x, data.x >> 10, data.x >> 20) & 1023) - 1.0;
+    position.y -= float((data.y >> 21) & 1u)*0.1;
+
+    vec3 view_position = vec3(chunk.origin - mvp.origin.xyz) + position;
+    // the wave phase only depends on the chunk, so it does not jump when the view origin moves
+    float phase = time + position.x + float(chunk.origin.x & 1023);
+    gl_Position = mvp.proj * mvp.view * vec4(view_position + vec3(0.0, sin(phase)*0.1, 0.0), 1.0);
+    tex_coord = vec2((data.x >> 30) & 1u, (data.x >> 31) & 1u);
+    out_ind = float(data.y & 0xFFFFu);
 }
@@ -6,8 +6,8 @@ layout(location = 1) in vec3 in_color;
 
 layout(location = 0) out vec4 out_color;  // renderpass: color attachment #0
 
-layout(set = 1, binding = 0) uniform sampler2D font_sampler;
-layout(input_attachment_index = 0, set = 1, binding = 1) uniform subpassInput inputColor;
+layout(set = 0, binding = 0) uniform sampler2D font_sampler;
+layout(input_attachment_index = 0, set = 1, binding = 0) uniform subpassInput inputColor;
 
 void main() {
 //    out_color = vec4(subpassLoad(inputColor).rgb, 1.0);
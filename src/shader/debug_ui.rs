@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+use ash::{Device, vk};
+use crate::component::{ClippedDraw, RenderData, RenderDataPurpose};
+use crate::deletion::{DeletionQueue, RetiredResource};
+use crate::framebuffer::PassInputs;
+use crate::render_graph::RenderGraph;
+use crate::shader::reflect::check_descriptors;
+use crate::shader::{ColorBlendKind, DescriptorManager, Shader, create_graphics_pipeline, StandardGraphicsPipelineInfo, VBOFS};
+use matrixagon_util::{Vertex, VulkanVertexState, IndexedBuffer};
+
+
+// emulating the structure of the EguiVertex
+#[derive(Copy, Clone, Debug, Vertex)]
+pub struct EguiVertex {
+    pub(crate) pos: [f32; 2],
+    pub(crate) uv: [f32; 2],
+    pub(crate) color: [u8; 3],
+}
+
+
+// draws the egui debug overlay on top of whatever the earlier passes drew
+pub struct DebugUIRasterizer {
+    device: Rc<Device>,
+    deletion: Rc<DeletionQueue>,
+    pipeline_cache: vk::PipelineCache,  // owned by the handler
+    renderpass: vk::RenderPass,  // owned by the handler
+
+    extent: vk::Extent2D,
+    descriptor: DescriptorManager,
+    subpass: u32,
+
+    pipeline: vk::Pipeline,
+    ivbo: IndexedBuffer<DeletionQueue>,
+    draws: Vec<ClippedDraw>,
+    texture_sets: HashMap<u64, (vk::DescriptorPool, vk::DescriptorSet)>,  // by the texture of the draws
+    input_sets: Vec<(vk::DescriptorPool, vk::DescriptorSet)>,  // by framebuffer
+
+    reloaded_shaders: HashMap<String, Vec<u32>>,  // replace the compiled ones when rebuilding pipelines
+}
+
+impl DebugUIRasterizer {
+    const PASS: &'static str = "composition";
+
+    // set 0 is written once for every texture, set 1 once for every framebuffer
+    pub(crate) fn descriptor_layout() -> Vec<Vec<(vk::DescriptorType, vk::ShaderStageFlags)>> {
+        vec![
+            vec![
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT), // egui debug ui texture
+            ],
+            vec![
+                (vk::DescriptorType::INPUT_ATTACHMENT, vk::ShaderStageFlags::FRAGMENT), // input attachment from previous
+            ],
+        ]
+    }
+
+    fn pipeline_info(subpass: u32) -> StandardGraphicsPipelineInfo<'static> {
+        StandardGraphicsPipelineInfo {
+            shaders: vec![
+                ("debug_ui.vert", vk::ShaderStageFlags::VERTEX),
+                ("debug_ui.frag", vk::ShaderStageFlags::FRAGMENT),
+            ],
+            vertex_input_state: EguiVertex::VERTEX_INPUT_STATE,
+            back_face_culling: false, depth_testing: false,
+            color_blend_attachment_state: vec![ColorBlendKind::transparent()],
+            subpass_index: subpass,
+        }
+    }
+
+    pub(crate) unsafe fn new(deletion: Rc<DeletionQueue>, device: Rc<Device>, pipeline_cache: vk::PipelineCache,
+                             renderpass: vk::RenderPass, graph: &RenderGraph, frames_in_flight: usize, extent: vk::Extent2D) -> Self {
+        let descriptor = DescriptorManager::new(device.clone(), frames_in_flight, Self::descriptor_layout(), vec![]);
+        let subpass = graph.subpass(Self::PASS);
+
+        let pipeline = create_graphics_pipeline(
+            device.clone(), vec![Self::pipeline_info(subpass)], descriptor.pipeline_layout, renderpass, pipeline_cache, &HashMap::new(),
+        ).expect("Failed to create the debug UI pipeline")[0];
+
+        Self {
+            device: device.clone(),
+            deletion: deletion.clone(),
+            pipeline_cache,
+            renderpass,
+            extent,
+            descriptor,
+            subpass,
+            pipeline,
+            ivbo: IndexedBuffer::new(deletion),
+            draws: Vec::new(),
+            texture_sets: HashMap::new(),
+            input_sets: Vec::new(),
+            reloaded_shaders: HashMap::new(),
+        }
+    }
+}
+
+impl Shader for DebugUIRasterizer {
+    fn purposes(&self) -> &'static [RenderDataPurpose] {
+        &[RenderDataPurpose::DebugUI]
+    }
+
+    unsafe fn write_descriptors(&mut self, _descriptor_buffers: Vec<RenderData>) {}
+
+    fn update_extent(&mut self, new_extent: vk::Extent2D) {
+        self.extent = new_extent;
+    }
+
+    fn recreate_buffer(&mut self, render_data: RenderData) {
+        match render_data {
+            RenderData::RecreateVertexBuffer(buf, mem, RenderDataPurpose::DebugUI) => unsafe {
+                self.ivbo.recreate_vbo([buf], mem);
+            }
+            RenderData::RecreateIndexBuffer(buf, mem, len, RenderDataPurpose::DebugUI) => unsafe {
+                self.ivbo.recreate_ibo(buf, mem, len);
+            }
+            RenderData::SetClippedDraws(draws, RenderDataPurpose::DebugUI) => unsafe {
+                // a texture is never rewritten in place, a replaced one comes with a new id and gets a set of its
+                // own. the sets of textures that are no longer drawn may still be used by frames in flight
+                for draw in &draws {
+                    if !self.texture_sets.contains_key(&draw.texture) {
+                        let texture_set = self.descriptor.create_image_set(0, &[draw.texture_info]);
+                        self.texture_sets.insert(draw.texture, texture_set);
+                    }
+                }
+                let deletion = &self.deletion;
+                self.texture_sets.retain(|texture, (pool, _)| {
+                    let drawn = draws.iter().any(|draw| draw.texture == *texture);
+                    if !drawn {
+                        deletion.retire(RetiredResource::DescriptorPool(*pool));
+                    }
+                    drawn
+                });
+                self.draws = draws;
+            }
+            _ => {},
+        }
+    }
+
+    unsafe fn write_input_attachments(&mut self, inputs: &PassInputs) {
+        // the sets of the old framebuffers may still be used by frames in flight
+        for (pool, _) in self.input_sets.drain(..) {
+            self.deletion.retire(RetiredResource::DescriptorPool(pool));
+        }
+        self.input_sets = inputs[Self::PASS].iter()
+            .map(|imgs| self.descriptor.create_image_set(1, imgs))
+            .collect();
+    }
+
+    unsafe fn draw_pass(&self, cmd_buf: vk::CommandBuffer, pass: &str, _frame: usize, image: usize) {
+        if pass != Self::PASS {
+            return;
+        }
+        let Some((ui_vbo, ui_ibo, _)) = self.ivbo.obtain_indexed_vbo() else { return };
+
+        self.device.cmd_bind_descriptor_sets(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.descriptor.pipeline_layout(),
+                                             1, &[self.input_sets[image].1], &[]);
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        self.device.cmd_set_viewport(cmd_buf, 0, &viewports);
+
+        self.device.cmd_bind_pipeline(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        self.device.cmd_bind_vertex_buffers(cmd_buf, 0, &ui_vbo, &VBOFS);
+        self.device.cmd_bind_index_buffer(cmd_buf, ui_ibo, 0, vk::IndexType::UINT32);
+
+        // every egui primitive is drawn with its own texture and clip rect
+        for draw in &self.draws {
+            let Some((_, texture_set)) = self.texture_sets.get(&draw.texture) else { continue };
+            self.device.cmd_bind_descriptor_sets(cmd_buf, vk::PipelineBindPoint::GRAPHICS, self.descriptor.pipeline_layout(),
+                                                 0, &[*texture_set], &[]);
+            self.device.cmd_set_scissor(cmd_buf, 0, &[draw.scissor]);
+            self.device.cmd_draw_indexed(cmd_buf, draw.index_count, 1, draw.first_index, draw.vertex_offset, 0);
+        }
+    }
+
+    unsafe fn reload_shaders(&mut self, shaders: Vec<(String, Vec<u32>)>) -> Result<(), String> {
+        let info = Self::pipeline_info(self.subpass);
+        let shaders = shaders.into_iter()
+            .filter(|(name, _)| info.shaders.iter().any(|(shader, _)| shader == name))
+            .collect::<Vec<_>>();
+        if shaders.is_empty() {
+            return Ok(());
+        }
+
+        let layout = Self::descriptor_layout();
+        for (name, code) in &shaders {
+            for (_, stage) in info.shaders.iter().filter(|(shader, _)| shader == name) {
+                check_descriptors(name, code, *stage, &layout)?;
+            }
+        }
+        let mut reloaded_shaders = self.reloaded_shaders.clone();
+        reloaded_shaders.extend(shaders);
+
+        let rebuilt = create_graphics_pipeline(
+            self.device.clone(), vec![info], self.descriptor.pipeline_layout, self.renderpass, self.pipeline_cache,
+            &reloaded_shaders,
+        ).map_err(|err| format!("Failed to create the debug UI pipeline: {err}"))?[0];
+        self.reloaded_shaders = reloaded_shaders;
+        self.deletion.retire(RetiredResource::Pipeline(mem::replace(&mut self.pipeline, rebuilt)));
+        Ok(())
+    }
+
+    unsafe fn set_renderpass(&mut self, renderpass: vk::RenderPass) {
+        self.renderpass = renderpass;
+        let rebuilt = create_graphics_pipeline(
+            self.device.clone(), vec![Self::pipeline_info(self.subpass)], self.descriptor.pipeline_layout, renderpass,
+            self.pipeline_cache, &self.reloaded_shaders,
+        ).expect("Failed to create the debug UI pipeline")[0];
+        self.deletion.retire(RetiredResource::Pipeline(mem::replace(&mut self.pipeline, rebuilt)));
+    }
+
+    unsafe fn destroy(&self) {
+        self.ivbo.destroy();
+        for (pool, _) in self.texture_sets.values().chain(&self.input_sets) {
+            self.device.destroy_descriptor_pool(*pool, None);
+        }
+        self.device.destroy_pipeline(self.pipeline, None);
+        self.descriptor.destroy();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glsl_input_matches_vertex_struct() {
+        // the vertex shader includes this file, rewrite it from GLSL_INPUT whenever EguiVertex changes
+        let normalized = |glsl: &str| glsl.replace("\r\n", "\n");
+        assert_eq!(normalized(include_str!("include/egui_vertex.glsl")), EguiVertex::GLSL_INPUT);
+        assert!(include_str!("debug_ui.vert").contains("#include \"include/egui_vertex.glsl\""));
+    }
+
+    #[test]
+    fn shader_descriptors_match_layout() {
+        use crate::shader::compiled_shader;
+
+        let layout = DebugUIRasterizer::descriptor_layout();
+        for (name, stage) in DebugUIRasterizer::pipeline_info(0).shaders {
+            let descriptors = check_descriptors(name, &compiled_shader(name), stage, &layout).unwrap();
+            if let Some(font) = descriptors.iter().find(|d| (d.set, d.binding) == (0, 0)) {
+                assert_eq!(font.image, Some((1, false)), "{name} font texture is not a sampler2D");
+            }
+        }
+    }
+}
@@ -1,15 +1,14 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
-layout(location = 0) in vec2 pos;
-layout(location = 1) in vec2 uv;
-layout(location = 2) in vec3 color;
+// pos is already in NDC
+#include "include/egui_vertex.glsl"
 
 layout(location = 0) out vec2 tex_coord;
 layout(location = 1) out vec3 out_color;
 
 void main() {
-    gl_Position = vec4(pos.x*.002-1.0, pos.y*.002*1.6-1.0, 0.0, 1.0);
-//    gl_Position = vec4(pos.x, pos.y, 0.0, 1.0);
+    gl_Position = vec4(pos, 0.0, 1.0);
     tex_coord = uv;
     out_color = color;
 }
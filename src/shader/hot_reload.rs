@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
+use ash::util::read_spv;
+
+
+// watches the shader sources during development, by polling their modification times so no
+// file system notifications are needed. the shaders are recompiled with the glslc build.rs found
+pub(crate) struct ShaderWatcher {
+    glslc: PathBuf,
+    dir: PathBuf,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    // None if there is no glslc to recompile the shaders with
+    pub(crate) fn new(dir: PathBuf) -> Option<Self> {
+        let glslc = std::env::var_os("GLSLC").map(PathBuf::from)
+            .or(option_env!("MTXG2_GLSLC").map(PathBuf::from))?;
+        let mut watcher = Self {
+            glslc, dir,
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.modified = watcher.scan();
+        Some(watcher)
+    }
+
+    fn is_shader(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "vert" || ext == "frag")
+    }
+
+    // every shader and include file along with its modification time
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        [self.dir.clone(), self.dir.join("include")].iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| Self::is_shader(path) || path.extension().is_some_and(|ext| ext == "glsl"))
+            .filter_map(|path| Some((path.clone(), fs::metadata(&path).ok()?.modified().ok()?)))
+            .collect()
+    }
+
+    // names of the shaders that changed since the last poll, all of them once an include file changes
+    pub(crate) fn changed_shaders(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < Self::POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let modified = self.scan();
+        let changed = modified.iter()
+            .filter(|(path, time)| self.modified.get(*path) != Some(time))
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        self.modified = modified;
+
+        let mut shaders = if changed.iter().any(|path| !Self::is_shader(path)) {
+            self.modified.keys().filter(|path| Self::is_shader(path)).cloned().collect()
+        } else {
+            changed
+        };
+        shaders.sort();
+        shaders.iter()
+            .filter_map(|path| Some(path.file_name()?.to_str()?.to_string()))
+            .collect()
+    }
+
+    // the spir-v of the shader, or the compile errors of glslc
+    pub(crate) fn compile(&self, name: &str) -> Result<Vec<u32>, String> {
+        let output = Command::new(&self.glslc)
+            .arg(self.dir.join(name))
+            .arg("-o")
+            .arg("-")
+            .output()
+            .map_err(|err| format!("Failed to run glslc: {err}"))?;
+        if !output.status.success() {
+            let errors = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(if errors.is_empty() {format!("glslc failed on {name} with {}", output.status)} else {errors});
+        }
+        read_spv(&mut Cursor::new(output.stdout)).map_err(|err| format!("Failed to read compiled shader {name}: {err}"))
+    }
+}
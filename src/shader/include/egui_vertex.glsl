@@ -0,0 +1,4 @@
+// generated by #[derive(Vertex)] from EguiVertex, do not edit
+layout(location = 0) in vec2 pos;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec3 color;
@@ -0,0 +1,2 @@
+// generated by #[derive(Vertex)] from PackedChunkVertex, do not edit
+layout(location = 0) in uvec2 data;
@@ -1,30 +1,63 @@
 pub mod chunk;
+pub mod debug_ui;
+pub(crate) mod hot_reload;
+pub(crate) mod pipeline_cache;
+mod reflect;
 
+use std::collections::HashMap;
 use std::ffi::CStr;
-use std::fs::File;
-use std::{fs, mem, process};
+use std::io::Cursor;
 use std::rc::Rc;
 use ash::{Device, vk};
 use ash::util::read_spv;
-use ash::vk::{AccessFlags, ImageLayout, PipelineStageFlags, SubpassDependencyBuilder};
-use crate::component::{RenderData};
-use crate::framebuffer::FBAttachmentRef;
+use crate::component::{RenderData, RenderDataPurpose};
+use crate::framebuffer::PassInputs;
+use crate::render_graph::{AttachmentSource, GraphAttachment, GraphPass, RenderGraph};
 
+// the handler draws every shader in the render pass of the frame graph, in the order they are loaded
 pub trait Shader {
-    fn renderpass(&self) -> vk::RenderPass;
-    fn attachments(&self) -> Vec<FBAttachmentRef>;
+    // only the render data addressed to these purposes is handed to the shader
+    fn purposes(&self) -> &'static [RenderDataPurpose];
     unsafe fn write_descriptors(&mut self, descriptor_buffers: Vec<RenderData>);
     fn update_extent(&mut self, new_extent: vk::Extent2D);
     fn recreate_buffer(&mut self, render_data: RenderData);
-    unsafe fn draw_command(&self, cmd_buf: vk::CommandBuffer, framebuffer: vk::Framebuffer);
+    // the framebuffers were recreated, the inputs of the passes the shader reads attachments in point at the new ones
+    unsafe fn write_input_attachments(&mut self, inputs: &PassInputs);
+    // called in every pass of the frame graph, the shader records its draws in the passes it draws in. image is
+    // the framebuffer drawn into
+    unsafe fn draw_pass(&self, cmd_buf: vk::CommandBuffer, pass: &str, frame: usize, image: usize);
+    // swaps in hot reloaded shaders (by file name) and rebuilds the pipelines using them, the old
+    // pipelines are kept if the shaders do not fit
+    unsafe fn reload_shaders(&mut self, shaders: Vec<(String, Vec<u32>)>) -> Result<(), String>;
+    // the render pass was replaced (the surface format changed), every pipeline is recreated in the new one
+    unsafe fn set_renderpass(&mut self, renderpass: vk::RenderPass);
     unsafe fn destroy(&self);
 }
 
 
 const VBOFS: [vk::DeviceSize; 1] = [0 as vk::DeviceSize];  // vertex buffer offsets
 
-// C:/VulkanSDK/1.3.261.1/bin/glslc.exe src/shader/cube.frag -o src/shader/cube.frag.spv
+// the passes the shaders draw in, referred to by name: the terrain is drawn onto the swapchain image, and the
+// debug ui composed on top of it reading it as an input
+pub(crate) fn frame_graph() -> RenderGraph {
+    RenderGraph::new(vec![
+        GraphAttachment {
+            name: "presentation", source: AttachmentSource::Swapchain,
+            clear: Some(vk::ClearValue {color: vk::ClearColorValue {float32: [0.2, 0.3, 0.9, 1.0]}}),
+        },
+        GraphAttachment {
+            name: "depth", source: AttachmentSource::Depth,
+            clear: Some(vk::ClearValue {depth_stencil: vk::ClearDepthStencilValue {depth: 0.0, stencil: 0}}),  // reversed depth
+        },
+    ], vec![
+        GraphPass {name: "terrain", input: vec![], color: vec!["presentation"], depth: Some("depth")},
+        GraphPass {name: "composition", input: vec!["presentation"], color: vec!["presentation"], depth: None},
+    ])
+}
+
+// the shaders are compiled by build.rs, see COMPILED_SHADERS
 // glslc has an option to compile shader to human readable bytecode
+include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
 
 
 #[non_exhaustive]
@@ -57,6 +90,7 @@ impl ColorBlendKind {
 }
 
 
+#[derive(Clone)]
 pub(crate) struct StandardGraphicsPipelineInfo<'s> {
     shaders: Vec<(&'s str, vk::ShaderStageFlags)>,
     vertex_input_state: vk::PipelineVertexInputStateCreateInfo,
@@ -71,8 +105,10 @@ pub(crate) unsafe fn create_graphics_pipeline(
     device: Rc<Device>,
     pipeline_infos: Vec<StandardGraphicsPipelineInfo>,
     pipeline_layout: vk::PipelineLayout,
-    renderpass: vk::RenderPass
-) -> Vec<vk::Pipeline> {
+    renderpass: vk::RenderPass,
+    pipeline_cache: vk::PipelineCache,
+    reloaded_shaders: &HashMap<String, Vec<u32>>,
+) -> Result<Vec<vk::Pipeline>, vk::Result> {
     let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
         topology: vk::PrimitiveTopology::TRIANGLE_LIST,
         primitive_restart_enable: vk::FALSE,
@@ -134,7 +170,13 @@ pub(crate) unsafe fn create_graphics_pipeline(
     let mut all_shader_stages = vec![];
 
     for info in pipeline_infos {
-        let (shader_stages, mut shader_modules) = gen_shader_modules_info(device.clone(), info.shaders);
+        let (shader_stages, mut shader_modules) = match gen_shader_modules_info(device.clone(), info.shaders, reloaded_shaders) {
+            Ok(stages) => stages,
+            Err(err) => {
+                destroy_shader_modules(device.clone(), all_shader_modules);
+                return Err(err);
+            }
+        };
 
         let color_blend_info = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
@@ -166,12 +208,17 @@ pub(crate) unsafe fn create_graphics_pipeline(
         all_shader_stages.push(shader_stages);
     }
 
-    let gp = device.create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_create_infos, None)
-        .unwrap();
+    let gp = device.create_graphics_pipelines(pipeline_cache, &pipeline_create_infos, None);
 
     destroy_shader_modules(device.clone(), all_shader_modules);
 
-    gp
+    // the pipelines that were created before the failing one are of no use on their own
+    gp.map_err(|(pipelines, err)| {
+        for pipeline in pipelines.into_iter().filter(|pipeline| *pipeline != vk::Pipeline::null()) {
+            device.destroy_pipeline(pipeline, None);
+        }
+        err
+    })
 }
 
 
@@ -182,11 +229,13 @@ pub(crate) struct DescriptorManager {
     descriptor_layout: Vec<Vec<(vk::DescriptorType, vk::ShaderStageFlags)>>,
     descriptor_set_layout: Vec<vk::DescriptorSetLayout>,
     descriptor_pool: vk::DescriptorPool,
-    descriptor_set: Vec<vk::DescriptorSet>,
+    descriptor_set: Vec<Vec<vk::DescriptorSet>>,  // a copy of every set per frame in flight
 }
 
 impl DescriptorManager {
-    pub(crate) unsafe fn new(device: Rc<Device>, descriptors: Vec<Vec<(vk::DescriptorType, vk::ShaderStageFlags)>>) -> Self {
+    pub(crate) unsafe fn new(device: Rc<Device>, frames_in_flight: usize,
+                             descriptors: Vec<Vec<(vk::DescriptorType, vk::ShaderStageFlags)>>,
+                             push_constants: Vec<vk::PushConstantRange>) -> Self {
         // assumes descriptor count of 1 always
 
         let mut set_layouts = Vec::new();
@@ -205,7 +254,7 @@ impl DescriptorManager {
 
                 let pool_size = vk::DescriptorPoolSize {
                     ty: *binding_type,
-                    descriptor_count: 1,
+                    descriptor_count: frames_in_flight as u32,
                 };
                 pool_sizes.push(pool_size);
             }
@@ -219,12 +268,13 @@ impl DescriptorManager {
         }
         let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
             .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constants)
             .build();
         let pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None).unwrap();
 
         let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
             .pool_sizes(&pool_sizes)
-            .max_sets(set_layouts.len() as u32)
+            .max_sets((set_layouts.len()*frames_in_flight) as u32)
             .build();
         let descriptor_pool = device.create_descriptor_pool(&descriptor_pool_info, None)
             .expect("Failed to create descriptor pool");
@@ -234,8 +284,11 @@ impl DescriptorManager {
             .set_layouts(&set_layouts)
             .build();
 
-        let descriptor_set = device.allocate_descriptor_sets(&descriptor_set_alloc)
-            .expect("Failed to allocate descriptor sets");
+        let mut descriptor_set = Vec::new();
+        for _ in 0..frames_in_flight {
+            descriptor_set.push(device.allocate_descriptor_sets(&descriptor_set_alloc)
+                .expect("Failed to allocate descriptor sets"));
+        }
 
         Self {
             device,
@@ -247,36 +300,81 @@ impl DescriptorManager {
         }
     }
 
-    pub(crate) unsafe fn write_buffer(&mut self, set: u32, binding: u32, buf: Vec<vk::DescriptorBufferInfo>) {
-        self.device.update_descriptor_sets(&[
-            vk::WriteDescriptorSet::builder()
-                .dst_set(self.descriptor_set[set as usize])
-                .dst_binding(binding)
-                .dst_array_element(0)
-                .descriptor_type(self.descriptor_layout[set as usize][binding as usize].0)
-                .buffer_info(&buf)
-                .build()
-        ], &[]);
+    // one buffer per frame in flight, each written to the sets of its frame
+    pub(crate) unsafe fn write_frame_buffers(&mut self, set: u32, binding: u32, buf: Vec<vk::DescriptorBufferInfo>) {
+        assert_eq!(buf.len(), self.descriptor_set.len(), "Expected a buffer for every frame in flight");
+        for (frame_sets, frame_buf) in self.descriptor_set.iter().zip(&buf) {
+            self.device.update_descriptor_sets(&[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(frame_sets[set as usize])
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(self.descriptor_layout[set as usize][binding as usize].0)
+                    .buffer_info(std::slice::from_ref(frame_buf))
+                    .build()
+            ], &[]);
+        }
     }
 
+    // images are shared by all frames
     pub(crate) unsafe fn write_image(&mut self, set: u32, binding: u32, img: Vec<vk::DescriptorImageInfo>) {
-        self.device.update_descriptor_sets(&[
-            vk::WriteDescriptorSet::builder()
-                .dst_set(self.descriptor_set[set as usize])
-                .dst_binding(binding)
-                .dst_array_element(0)
-                .descriptor_type(self.descriptor_layout[set as usize][binding as usize].0)
-                .image_info(&img)
-                .build()
-        ], &[]);
+        for frame_sets in &self.descriptor_set {
+            self.device.update_descriptor_sets(&[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(frame_sets[set as usize])
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(self.descriptor_layout[set as usize][binding as usize].0)
+                    .image_info(&img)
+                    .build()
+            ], &[]);
+        }
+    }
+
+    // a copy of a set outside of the per frame ones with an image for each binding (e.g. one per texture). every
+    // copy has a pool of its own, so that it can be retired on its own once no frame uses it anymore
+    pub(crate) unsafe fn create_image_set(&self, set: u32, img: &[vk::DescriptorImageInfo]) -> (vk::DescriptorPool, vk::DescriptorSet) {
+        let layout = &self.descriptor_layout[set as usize];
+        assert_eq!(img.len(), layout.len(), "Expected an image for every binding of set {set}");
+
+        let pool_sizes = layout.iter()
+            .map(|(binding_type, _)| vk::DescriptorPoolSize {ty: *binding_type, descriptor_count: 1})
+            .collect::<Vec<_>>();
+        let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(1)
+            .build();
+        let pool = self.device.create_descriptor_pool(&descriptor_pool_info, None)
+            .expect("Failed to create descriptor pool");
+
+        let set_layouts = [self.descriptor_set_layout[set as usize]];
+        let descriptor_set_alloc = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts)
+            .build();
+        let descriptor_set = self.device.allocate_descriptor_sets(&descriptor_set_alloc)
+            .expect("Failed to allocate descriptor sets")[0];
+
+        for (binding, (img, (binding_type, _))) in img.iter().zip(layout).enumerate() {
+            self.device.update_descriptor_sets(&[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .dst_array_element(0)
+                    .descriptor_type(*binding_type)
+                    .image_info(std::slice::from_ref(img))
+                    .build()
+            ], &[]);
+        }
+        (pool, descriptor_set)
     }
 
     pub(crate) unsafe fn pipeline_layout(&self) -> vk::PipelineLayout {self.pipeline_layout}
 
-    pub(crate) unsafe fn descriptor_sets(&self, indices: &[usize]) -> Vec<vk::DescriptorSet> {
+    pub(crate) unsafe fn descriptor_sets(&self, frame: usize, indices: &[usize]) -> Vec<vk::DescriptorSet> {
         let mut result = Vec::new();
         for ind in indices {
-            result.push(self.descriptor_set[*ind]);
+            result.push(self.descriptor_set[frame][*ind]);
         }
         result
     }
@@ -290,21 +388,22 @@ impl DescriptorManager {
     }
 }
 
-pub(crate) unsafe fn gen_shader_modules_info(device: Rc<Device>, shaders: Vec<(&str, vk::ShaderStageFlags)>)
-    -> (Vec<vk::PipelineShaderStageCreateInfo>, Vec<vk::ShaderModule>) {
+// reloaded_shaders take the place of the ones compiled at build time
+pub(crate) unsafe fn gen_shader_modules_info(device: Rc<Device>, shaders: Vec<(&str, vk::ShaderStageFlags)>,
+                                             reloaded_shaders: &HashMap<String, Vec<u32>>)
+    -> Result<(Vec<vk::PipelineShaderStageCreateInfo>, Vec<vk::ShaderModule>), vk::Result> {
     let mut pipeline = vec![];
     let mut modules = vec![];
 
-    for (shader_fpath, shader_stage) in shaders {
-        let status = process::Command::new("C:/VulkanSDK/1.3.261.1/bin/glslc.exe")
-            .arg(shader_fpath)
-            .arg("-o")
-            .arg(format!("{shader_fpath}.spv"))
-            .status()
-            .expect(&*format!("Failed to compile shader {shader_fpath}"));
-        println!("Compiled shader <{shader_fpath}> with status of {status}");
-
-        let shader_module = create_shader_module(device.clone(), &*format!("{shader_fpath}.spv"));
+    for (shader_name, shader_stage) in shaders {
+        let code = reloaded_shaders.get(shader_name).cloned().unwrap_or_else(|| compiled_shader(shader_name));
+        let shader_module = match create_shader_module(device.clone(), code) {
+            Ok(shader_module) => shader_module,
+            Err(err) => {
+                destroy_shader_modules(device.clone(), modules);
+                return Err(err);
+            }
+        };
 
         let shader_info = vk::PipelineShaderStageCreateInfo::builder()
             .stage(shader_stage)
@@ -314,12 +413,9 @@ pub(crate) unsafe fn gen_shader_modules_info(device: Rc<Device>, shaders: Vec<(&
 
         pipeline.push(shader_info);
         modules.push(shader_module);
-
-        fs::remove_file(format!("{shader_fpath}.spv"))
-            .expect(&*format!("Failed to delete the temp file for the compiled shader {shader_fpath}.spv"));
     }
 
-    (pipeline, modules)
+    Ok((pipeline, modules))
 }
 
 pub(crate) unsafe fn destroy_shader_modules(device: Rc<Device>, shader_modules: Vec<vk::ShaderModule>) {
@@ -328,9 +424,15 @@ pub(crate) unsafe fn destroy_shader_modules(device: Rc<Device>, shader_modules:
     }
 }
 
-unsafe fn create_shader_module(device: Rc<Device>, fpath: &str) -> vk::ShaderModule {
-    let mut fobj = File::open(fpath).unwrap();
-    let code = read_spv(&mut fobj).unwrap();
+// the spir-v of a shader in src/shader by its file name, e.g. "chunk.vert"
+pub(crate) fn compiled_shader(name: &str) -> Vec<u32> {
+    let (_, bytes) = COMPILED_SHADERS.iter()
+        .find(|(shader_name, _)| *shader_name == name)
+        .unwrap_or_else(|| panic!("Unknown shader {name}"));
+    read_spv(&mut Cursor::new(bytes)).expect("Failed to read compiled shader")
+}
+
+unsafe fn create_shader_module(device: Rc<Device>, code: Vec<u32>) -> Result<vk::ShaderModule, vk::Result> {
 
     let create_info = vk::ShaderModuleCreateInfo {
         // code size are in bytes, but code data is aligned to u32 (4 bytes)
@@ -339,5 +441,48 @@ unsafe fn create_shader_module(device: Rc<Device>, fpath: &str) -> vk::ShaderMod
         ..Default::default()
     };
 
-    device.create_shader_module(&create_info, None).unwrap()
+    device.create_shader_module(&create_info, None)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::FBAttachmentRef;
+
+    #[test]
+    fn frame_graph_matches_previous_renderpass() {
+        let graph = frame_graph();
+        assert_eq!(graph.subpass("composition"), 1);
+        assert_eq!(graph.framebuffer_attachments(), vec![
+            FBAttachmentRef::Depth(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT),
+        ]);
+        assert_eq!(graph.swapchain_usage(), vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT);
+
+        let description = graph.describe(vk::Format::B8G8R8A8_SRGB, vk::Format::D32_SFLOAT);
+        let ops = description.attachments.iter()
+            .map(|a| (a.load_op, a.store_op, a.final_layout))
+            .collect::<Vec<_>>();
+        assert_eq!(ops, vec![
+            (vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::STORE, vk::ImageLayout::PRESENT_SRC_KHR),
+            (vk::AttachmentLoadOp::CLEAR, vk::AttachmentStoreOp::DONT_CARE, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        ]);
+
+        let refs = |refs: &[vk::AttachmentReference]| refs.iter().map(|r| (r.attachment, r.layout)).collect::<Vec<_>>();
+        let [terrain, composition] = &description.subpasses[..] else { panic!("Expected two subpasses") };
+        assert_eq!(refs(&terrain.color), vec![(0, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)]);
+        assert_eq!(terrain.depth.map(|r| (r.attachment, r.layout)), Some((1, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)));
+        assert_eq!(refs(&composition.input), vec![(0, vk::ImageLayout::GENERAL)]);
+        assert_eq!(refs(&composition.color), vec![(0, vk::ImageLayout::GENERAL)]);
+
+        // the hand written dependencies were external->terrain and terrain->composition
+        let external = description.dependencies.iter().find(|d| d.src_subpass == vk::SUBPASS_EXTERNAL).unwrap();
+        assert_eq!(external.dst_subpass, 0);
+        assert!(external.src_stage_mask.contains(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::FRAGMENT_SHADER));
+        assert!(external.dst_access_mask.contains(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE));
+        let composition_dependency = description.dependencies.iter().find(|d| d.src_subpass == 0).unwrap();
+        assert_eq!(composition_dependency.dst_subpass, 1);
+        assert!(composition_dependency.dst_access_mask.contains(vk::AccessFlags::INPUT_ATTACHMENT_READ));
+        assert_eq!(description.dependencies.len(), 2);
+    }
 }
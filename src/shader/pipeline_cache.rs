@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use ash::{Device, vk};
+use crate::debug::DebugVisibility;
+
+
+// the device and driver a pipeline cache was made with, it is useless (or worse) with any other
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CacheIdentity {
+    vendor_id: u32,
+    device_id: u32,
+    driver_version: u32,
+    pipeline_cache_uuid: [u8; vk::UUID_SIZE],
+}
+
+impl CacheIdentity {
+    pub(crate) fn new(props: &vk::PhysicalDeviceProperties) -> Self {
+        Self {
+            vendor_id: props.vendor_id,
+            device_id: props.device_id,
+            driver_version: props.driver_version,
+            pipeline_cache_uuid: props.pipeline_cache_uuid,
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        [self.vendor_id, self.device_id, self.driver_version].iter()
+            .flat_map(|word| word.to_le_bytes())
+            .chain(self.pipeline_cache_uuid)
+            .collect()
+    }
+}
+
+const MAGIC: &[u8; 8] = b"MTXG2PC\0";
+const IDENTITY_SIZE: usize = 12 + vk::UUID_SIZE;
+const HEADER_SIZE: usize = MAGIC.len() + IDENTITY_SIZE + 16;  // + data length and checksum
+
+// FNV-1a, only there to catch truncated or corrupted files
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// file layout: magic, identity, data length (u64), checksum (u64), vulkan cache data
+fn serialize(identity: CacheIdentity, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend(identity.to_bytes());
+    bytes.extend((data.len() as u64).to_le_bytes());
+    bytes.extend(checksum(data).to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+// the vulkan cache data if the file was written for this device and driver and is intact
+fn validate(identity: CacheIdentity, bytes: &[u8]) -> Result<&[u8], String> {
+    if bytes.len() < HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(String::from("not a pipeline cache"));
+    }
+    let header = &bytes[MAGIC.len()..HEADER_SIZE];
+    if header[..IDENTITY_SIZE] != identity.to_bytes() {
+        return Err(String::from("written by a different device or driver version"));
+    }
+    let data_len = u64::from_le_bytes(header[IDENTITY_SIZE..IDENTITY_SIZE+8].try_into().unwrap());
+    let data = &bytes[HEADER_SIZE..];
+    if data.len() as u64 != data_len {
+        return Err(format!("expected {data_len} bytes of cache data, found {}", data.len()));
+    }
+    if checksum(data) != u64::from_le_bytes(header[IDENTITY_SIZE+8..].try_into().unwrap()) {
+        return Err(String::from("checksum mismatch"));
+    }
+
+    // the header vulkan puts in front of its own data has to agree as well
+    let word = |i: usize| data.get(4*i..4*i+4).map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+    let header_version_one = vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32;
+    if word(0).is_none_or(|len| len < 32) || word(1) != Some(header_version_one) ||
+        word(2) != Some(identity.vendor_id) || word(3) != Some(identity.device_id) ||
+        data.get(16..32) != Some(identity.pipeline_cache_uuid.as_slice()) {
+        return Err(String::from("invalid vulkan pipeline cache header"));
+    }
+    Ok(data)
+}
+
+
+// pipeline cache kept on disk across launches, so the pipelines are not compiled from scratch every time
+pub(crate) struct PipelineCache {
+    device: Rc<Device>,
+    cache: vk::PipelineCache,
+    identity: CacheIdentity,
+    path: PathBuf,
+}
+
+impl PipelineCache {
+    // falls back to an empty cache if the file is missing, stale or corrupt
+    pub(crate) unsafe fn load(dbgv: DebugVisibility, device: Rc<Device>, props: &vk::PhysicalDeviceProperties, path: PathBuf) -> Self {
+        let identity = CacheIdentity::new(props);
+        let bytes = fs::read(&path).unwrap_or_default();
+        let data = match validate(identity, &bytes) {
+            Ok(data) => {
+                if dbgv.vk_setup_output {
+                    println!("Loaded pipeline cache {} ({} bytes)", path.display(), data.len());
+                }
+                data
+            }
+            Err(reason) => {
+                if !bytes.is_empty() {
+                    println!("Ignoring pipeline cache {}: {reason}", path.display());
+                }
+                &[]
+            }
+        };
+
+        let create_info = vk::PipelineCacheCreateInfo::builder()
+            .initial_data(data)
+            .build();
+        let cache = device.create_pipeline_cache(&create_info, None)
+            .expect("Failed to create pipeline cache");
+
+        Self {device, cache, identity, path}
+    }
+
+    pub(crate) fn cache(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    // a failed save only costs the next launch some time, so it is not fatal
+    pub(crate) unsafe fn save(&self) {
+        let data = match self.device.get_pipeline_cache_data(self.cache) {
+            Ok(data) => data,
+            Err(err) => {
+                println!("Failed to get pipeline cache data: {err}");
+                return;
+            }
+        };
+
+        // written next to the old file first, so an interrupted save never leaves a partial cache behind
+        let tmp_path = self.path.with_extension("tmp");
+        let saved = fs::write(&tmp_path, serialize(self.identity, &data))
+            .and_then(|_| fs::rename(&tmp_path, &self.path));
+        if let Err(err) = saved {
+            println!("Failed to save pipeline cache {}: {err}", self.path.display());
+        }
+    }
+
+    pub(crate) unsafe fn destroy(&self) {
+        self.device.destroy_pipeline_cache(self.cache, None);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> CacheIdentity {
+        CacheIdentity {vendor_id: 0x10de, device_id: 0x2484, driver_version: 7, pipeline_cache_uuid: [3; vk::UUID_SIZE]}
+    }
+
+    // a vulkan cache header followed by some driver specific data
+    fn vulkan_data(identity: CacheIdentity) -> Vec<u8> {
+        [32, 1, identity.vendor_id, identity.device_id].iter()
+            .flat_map(|word: &u32| word.to_le_bytes())
+            .chain(identity.pipeline_cache_uuid)
+            .chain([42; 100])
+            .collect()
+    }
+
+    #[test]
+    fn stale_or_corrupt_caches_are_rejected() {
+        let data = vulkan_data(identity());
+        let bytes = serialize(identity(), &data);
+        assert_eq!(validate(identity(), &bytes), Ok(data.as_slice()));
+
+        let updated_driver = CacheIdentity {driver_version: 8, ..identity()};
+        assert!(validate(updated_driver, &bytes).is_err());
+        let other_device = CacheIdentity {pipeline_cache_uuid: [4; vk::UUID_SIZE], ..identity()};
+        assert!(validate(other_device, &bytes).is_err());
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(validate(identity(), &corrupt).is_err());
+        assert!(validate(identity(), &bytes[..bytes.len()-1]).is_err());
+        assert!(validate(identity(), &[]).is_err());
+
+        // intact file, but vulkan's own header does not match the device
+        let foreign = vulkan_data(CacheIdentity {vendor_id: 0x1002, ..identity()});
+        assert!(validate(identity(), &serialize(identity(), &foreign)).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use ash::vk;
+
+
+// just enough of a SPIR-V parser to list the descriptors a shader declares, to check them against
+// the layouts the rust side creates. https://registry.khronos.org/SPIR-V/specs/unified1/SPIRV.html
+const OP_NAME: u32 = 5;
+const OP_MEMBER_NAME: u32 = 6;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_SUBPASS_DATA: u32 = 6;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ReflectedDescriptor {
+    pub(crate) set: u32,
+    pub(crate) binding: u32,
+    pub(crate) descriptor_type: vk::DescriptorType,
+    pub(crate) type_name: Option<String>,  // the block name of buffers
+    pub(crate) image: Option<(u32, bool)>,  // dimensionality and whether it is arrayed
+    pub(crate) members: Vec<(String, usize)>,  // member names and byte offsets of buffers
+}
+
+#[derive(Copy, Clone)]
+enum SpvType {
+    Image {dim: u32, arrayed: bool, sampled: u32},
+    Sampler,
+    SampledImage(u32),
+    Array(u32),
+    Struct,
+    Pointer(u32),
+}
+
+fn literal_string(words: &[u32]) -> String {
+    let bytes = words.iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|byte| *byte != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8(bytes).expect("Failed to read a SPIR-V literal string")
+}
+
+pub(crate) fn reflect_descriptors(code: &[u32]) -> Vec<ReflectedDescriptor> {
+    assert_eq!(code.first(), Some(&0x07230203), "Not a SPIR-V module");
+
+    let mut names = HashMap::new();
+    let mut member_names = HashMap::new();
+    let mut member_offsets = HashMap::new();
+    let mut decorations: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+    let mut types = HashMap::new();
+    let mut variables = Vec::new();
+
+    let mut words = &code[5..];  // after the header
+    while !words.is_empty() {
+        let (word_count, opcode) = ((words[0] >> 16) as usize, words[0] & 0xFFFF);
+        assert!(word_count > 0 && word_count <= words.len(), "Malformed SPIR-V instruction");
+        let ops = &words[1..word_count];
+
+        match opcode {
+            OP_NAME => {
+                names.insert(ops[0], literal_string(&ops[1..]));
+            }
+            OP_MEMBER_NAME => {
+                member_names.insert((ops[0], ops[1]), literal_string(&ops[2..]));
+            }
+            OP_TYPE_IMAGE => {
+                types.insert(ops[0], SpvType::Image {dim: ops[2], arrayed: ops[4] == 1, sampled: ops[6]});
+            }
+            OP_TYPE_SAMPLER => {
+                types.insert(ops[0], SpvType::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                types.insert(ops[0], SpvType::SampledImage(ops[1]));
+            }
+            OP_TYPE_ARRAY => {
+                types.insert(ops[0], SpvType::Array(ops[1]));
+            }
+            OP_TYPE_STRUCT => {
+                types.insert(ops[0], SpvType::Struct);
+            }
+            OP_TYPE_POINTER => {
+                types.insert(ops[0], SpvType::Pointer(ops[2]));
+            }
+            OP_VARIABLE => {
+                variables.push((ops[1], ops[0], ops[2]));  // id, pointer type, storage class
+            }
+            OP_DECORATE => {
+                decorations.entry(ops[0]).or_default().push((ops[1], ops.get(2).copied().unwrap_or(0)));
+            }
+            OP_MEMBER_DECORATE if ops[2] == DECORATION_OFFSET => {
+                member_offsets.insert((ops[0], ops[1]), ops[3] as usize);
+            }
+            _ => {}
+        }
+
+        words = &words[word_count..];
+    }
+
+    let decoration = |id: u32, kind: u32| decorations.get(&id)
+        .and_then(|decorations| decorations.iter().find(|(decoration, _)| *decoration == kind))
+        .map(|(_, value)| *value);
+
+    let mut descriptors = variables.into_iter()
+        .filter(|(_, _, storage)| [STORAGE_UNIFORM_CONSTANT, STORAGE_UNIFORM, STORAGE_STORAGE_BUFFER].contains(storage))
+        .filter_map(|(id, pointer, storage)| {
+            let (set, binding) = (decoration(id, DECORATION_DESCRIPTOR_SET)?, decoration(id, DECORATION_BINDING)?);
+
+            // arrays of descriptors share the type of their element
+            let mut type_id = match types.get(&pointer) {
+                Some(SpvType::Pointer(pointee)) => *pointee,
+                _ => panic!("Variable {id} is not a pointer"),
+            };
+            while let Some(SpvType::Array(element)) = types.get(&type_id) {
+                type_id = *element;
+            }
+
+            let image = |image_id: u32| match types.get(&image_id) {
+                Some(SpvType::Image {dim, arrayed, sampled}) => (*dim, *arrayed, *sampled),
+                _ => panic!("Sampled image {image_id} does not refer to an image"),
+            };
+            let (descriptor_type, image) = match (storage, types.get(&type_id)) {
+                (STORAGE_UNIFORM, Some(SpvType::Struct)) if decoration(type_id, DECORATION_BUFFER_BLOCK).is_some() =>
+                    (vk::DescriptorType::STORAGE_BUFFER, None),
+                (STORAGE_UNIFORM, Some(SpvType::Struct)) if decoration(type_id, DECORATION_BLOCK).is_some() =>
+                    (vk::DescriptorType::UNIFORM_BUFFER, None),
+                (STORAGE_STORAGE_BUFFER, Some(SpvType::Struct)) => (vk::DescriptorType::STORAGE_BUFFER, None),
+                (_, Some(SpvType::SampledImage(image_id))) => {
+                    let (dim, arrayed, _) = image(*image_id);
+                    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, Some((dim, arrayed)))
+                }
+                (_, Some(SpvType::Image {dim: DIM_SUBPASS_DATA, ..})) => (vk::DescriptorType::INPUT_ATTACHMENT, None),
+                (_, Some(SpvType::Image {dim, arrayed, sampled})) => {
+                    let descriptor_type = if *sampled == 2 {vk::DescriptorType::STORAGE_IMAGE} else {vk::DescriptorType::SAMPLED_IMAGE};
+                    (descriptor_type, Some((*dim, *arrayed)))
+                }
+                (_, Some(SpvType::Sampler)) => (vk::DescriptorType::SAMPLER, None),
+                _ => panic!("Unknown descriptor type of variable {id}"),
+            };
+
+            let mut members = member_offsets.iter()
+                .filter(|((struct_id, _), _)| *struct_id == type_id)
+                .map(|(&(_, member), &offset)| {
+                    let name = member_names.get(&(type_id, member)).cloned().unwrap_or_else(|| member.to_string());
+                    (name, offset)
+                })
+                .collect::<Vec<_>>();
+            members.sort_by_key(|(_, offset)| *offset);
+
+            Some(ReflectedDescriptor {
+                set, binding, descriptor_type,
+                type_name: image.is_none().then(|| names.get(&type_id).cloned()).flatten(),
+                image, members,
+            })
+        })
+        .collect::<Vec<_>>();
+    descriptors.sort_by_key(|descriptor| (descriptor.set, descriptor.binding));
+    descriptors
+}
+
+
+
+// checks the descriptors of a shader for the given stage against a pipeline layout, returns them if they fit
+pub(crate) fn check_descriptors(name: &str, code: &[u32], stage: vk::ShaderStageFlags,
+                                layout: &[Vec<(vk::DescriptorType, vk::ShaderStageFlags)>]) -> Result<Vec<ReflectedDescriptor>, String> {
+    let descriptors = reflect_descriptors(code);
+    for descriptor in &descriptors {
+        let (set, binding) = (descriptor.set, descriptor.binding);
+        let (descriptor_type, stages) = layout.get(set as usize).and_then(|set| set.get(binding as usize))
+            .ok_or(format!("{name} declares set {set} binding {binding} which is not in the layout"))?;
+        if descriptor.descriptor_type != *descriptor_type {
+            return Err(format!("{name} declares set {set} binding {binding} as {:?}, the layout has {descriptor_type:?}", descriptor.descriptor_type));
+        }
+        if !stages.contains(stage) {
+            return Err(format!("{name} uses set {set} binding {binding} which is not visible to its stage"));
+        }
+    }
+    Ok(descriptors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    fn string(s: &str) -> Vec<u32> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize(s.len()/4*4 + 4, 0);
+        bytes.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn reflects_blocks_and_images() {
+        // uniform Ubo {float a; vec4 b;} at set 2 binding 0, sampler2DArray at set 0 binding 1
+        // and a subpassInput at set 1 binding 1, the types themselves are left out as they are not read
+        let mut code = vec![0x07230203, 0x00010000, 0, 100, 0];
+        for words in [
+            instruction(OP_NAME, &[[10].as_slice(), &string("Ubo")].concat()),
+            instruction(OP_MEMBER_NAME, &[[10, 0].as_slice(), &string("a")].concat()),
+            instruction(OP_MEMBER_NAME, &[[10, 1].as_slice(), &string("b")].concat()),
+            instruction(OP_DECORATE, &[10, DECORATION_BLOCK]),
+            instruction(OP_MEMBER_DECORATE, &[10, 1, DECORATION_OFFSET, 16]),
+            instruction(OP_MEMBER_DECORATE, &[10, 0, DECORATION_OFFSET, 0]),
+            instruction(OP_DECORATE, &[12, DECORATION_DESCRIPTOR_SET, 2]),
+            instruction(OP_DECORATE, &[12, DECORATION_BINDING, 0]),
+            instruction(OP_DECORATE, &[22, DECORATION_DESCRIPTOR_SET, 0]),
+            instruction(OP_DECORATE, &[22, DECORATION_BINDING, 1]),
+            instruction(OP_DECORATE, &[32, DECORATION_DESCRIPTOR_SET, 1]),
+            instruction(OP_DECORATE, &[32, DECORATION_BINDING, 1]),
+            instruction(OP_TYPE_STRUCT, &[10, 1, 2]),
+            instruction(OP_TYPE_POINTER, &[11, STORAGE_UNIFORM, 10]),
+            instruction(OP_VARIABLE, &[11, 12, STORAGE_UNIFORM]),
+            instruction(OP_TYPE_IMAGE, &[20, 1, 1, 0, 1, 0, 1, 0]),
+            instruction(OP_TYPE_SAMPLED_IMAGE, &[21, 20]),
+            instruction(OP_TYPE_POINTER, &[23, STORAGE_UNIFORM_CONSTANT, 21]),
+            instruction(OP_VARIABLE, &[23, 22, STORAGE_UNIFORM_CONSTANT]),
+            instruction(OP_TYPE_IMAGE, &[30, 1, DIM_SUBPASS_DATA, 0, 0, 0, 2, 0]),
+            instruction(OP_TYPE_POINTER, &[31, STORAGE_UNIFORM_CONSTANT, 30]),
+            instruction(OP_VARIABLE, &[31, 32, STORAGE_UNIFORM_CONSTANT]),
+            instruction(OP_TYPE_POINTER, &[41, 3, 10]),
+            instruction(OP_VARIABLE, &[41, 42, 3]),  // a function local, not a descriptor
+        ] {
+            code.extend(words);
+        }
+
+        let descriptors = reflect_descriptors(&code);
+        assert_eq!(descriptors, vec![
+            ReflectedDescriptor {
+                set: 0, binding: 1, descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                type_name: None, image: Some((1, true)), members: vec![],
+            },
+            ReflectedDescriptor {
+                set: 1, binding: 1, descriptor_type: vk::DescriptorType::INPUT_ATTACHMENT,
+                type_name: None, image: None, members: vec![],
+            },
+            ReflectedDescriptor {
+                set: 2, binding: 0, descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                type_name: Some("Ubo".to_string()), image: None,
+                members: vec![("a".to_string(), 0), ("b".to_string(), 16)],
+            },
+        ]);
+    }
+}
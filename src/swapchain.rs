@@ -2,23 +2,58 @@ use std::rc::Rc;
 use ash::{Device, vk};
 use ash::extensions::khr::Swapchain;
 use crate::debug::DebugVisibility;
+use crate::deletion::{DeletionQueue, RetiredResource};
 use crate::framebuffer::{FBAttachmentRef, FramebufferManager};
 use crate::handler::VulkanInstance;
+use crate::memory::MemoryAllocator;
+
+// user facing presentation setting, each maps onto a ranked list of present modes so that it still
+// works on drivers that lack the preferred one (FIFO is always available)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PresentSetting {
+    VSync,       // FIFO, never tears, up to a frame of extra latency
+    LowLatency,  // MAILBOX, never tears, the newest frame replaces the queued one
+    Uncapped,    // IMMEDIATE, may tear
+}
+
+impl PresentSetting {
+    fn ranked_present_modes(&self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            PresentSetting::VSync => &[vk::PresentModeKHR::FIFO],
+            PresentSetting::LowLatency => &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::FIFO],
+            PresentSetting::Uncapped => &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            PresentSetting::VSync => PresentSetting::LowLatency,
+            PresentSetting::LowLatency => PresentSetting::Uncapped,
+            PresentSetting::Uncapped => PresentSetting::VSync,
+        }
+    }
+}
 
 pub(crate) struct SwapchainManager {
     dbv: DebugVisibility,
     vi: Rc<VulkanInstance>,
+    alloc: Rc<MemoryAllocator>,
+    deletion: Rc<DeletionQueue>,
     device: Rc<Device>,
 
     pub(crate) loader: Swapchain,
     pub(crate) swapchain: [vk::SwapchainKHR; 1],
     pub(crate) extent: vk::Extent2D,
+    window_extent: vk::Extent2D,  // only used when the surface lets the swapchain decide its extent
     pub(crate) capb: vk::SurfaceCapabilitiesKHR,
     pub(crate) fmt: vk::SurfaceFormatKHR,
+    pub(crate) depth_fmt: vk::Format,
     pub(crate) prsnt: vk::PresentModeKHR,
+    images: Vec<vk::Image>,
     pub(crate) fbm: FramebufferManager,
 
     prsnt_inp: bool,
+    prsnt_setting: PresentSetting,
 
     // per renderpass
     renderpass: vk::RenderPass,
@@ -27,13 +62,16 @@ pub(crate) struct SwapchainManager {
 
 impl SwapchainManager {
     pub(crate) unsafe fn new(
-        dbv: DebugVisibility, vi: Rc<VulkanInstance>, device: Rc<Device>,
-        renderpass: vk::RenderPass, attachments: Vec<FBAttachmentRef>, prsnt_inp: bool,
+        dbv: DebugVisibility, vi: Rc<VulkanInstance>, alloc: Rc<MemoryAllocator>, deletion: Rc<DeletionQueue>, device: Rc<Device>,
+        renderpass: vk::RenderPass, attachments: Vec<FBAttachmentRef>, prsnt_inp: bool, prsnt_setting: PresentSetting,
+        window_extent: vk::Extent2D,
     ) -> Self {
         // prsnt_inp: make the presentation attachment also an input attachment
 
         let (capb, fmt, prsnt) = query_swapchain_support(dbv, &vi);
-        let (fmt, prsnt) = select_swapchain_support(fmt, prsnt);
+        let (fmt, prsnt) = select_swapchain_support(dbv, fmt, prsnt, prsnt_setting);
+        let depth_fmt = best_depth_format_support(dbv, &vi);
+        let extent = swap_extent(&capb, window_extent);
 
         let swapchain_loader = Swapchain::new(&vi.inst, &device.clone());
 
@@ -43,7 +81,7 @@ impl SwapchainManager {
             min_image_count: capb.min_image_count+1,
             image_format: fmt.format,
             image_color_space: fmt.color_space,
-            image_extent: capb.current_extent,
+            image_extent: extent,
             image_array_layers: 1,
             image_usage: if prsnt_inp {
                 vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT
@@ -72,20 +110,41 @@ impl SwapchainManager {
             .expect("Failed to get swapchain images");
 
         let fbm = FramebufferManager::new_swapchain_bounded(
-            dbv, vi.clone(), device.clone(), renderpass, attachments.clone(), swapchain_images,
-            fmt.format, best_depth_format_support(), capb.current_extent, prsnt_inp
+            dbv, alloc.clone(), device.clone(), renderpass, attachments.clone(), swapchain_images.clone(),
+            fmt.format, depth_fmt, extent
         );
 
         Self {
-            dbv, vi: vi.clone(), device: device.clone(),
-            loader: swapchain_loader, swapchain, extent: capb.current_extent, capb, fmt, prsnt, fbm,
-            prsnt_inp, renderpass, attachments,
+            dbv, vi: vi.clone(), alloc, deletion, device: device.clone(),
+            loader: swapchain_loader, swapchain, extent, window_extent, capb, fmt, depth_fmt, prsnt, images: swapchain_images, fbm,
+            prsnt_inp, prsnt_setting, renderpass, attachments,
         }
     }
 
-    pub(crate) unsafe fn recreate(&mut self) {
+    pub(crate) fn present_setting(&self) -> PresentSetting {
+        self.prsnt_setting
+    }
+
+    pub(crate) unsafe fn set_present_setting(&mut self, prsnt_setting: PresentSetting) -> bool {
+        self.prsnt_setting = prsnt_setting;
+        self.recreate()
+    }
+
+    pub(crate) unsafe fn resize(&mut self, window_extent: vk::Extent2D) -> bool {
+        self.window_extent = window_extent;
+        self.recreate()
+    }
+
+    // returns whether the surface format changed (e.g. the window moved onto a monitor with another format), the
+    // framebuffers are then left for set_renderpass to recreate with a render pass of the new format
+    pub(crate) unsafe fn recreate(&mut self) -> bool {
         let (capb, fmt, prsnt) = query_swapchain_support(self.dbv, &self.vi);
-        let (fmt, prsnt) = select_swapchain_support(fmt, prsnt);
+        let extent = swap_extent(&capb, self.window_extent);
+        let (fmt, prsnt) = select_swapchain_support(self.dbv, fmt, prsnt, self.prsnt_setting);
+        let format_changed = fmt.format != self.fmt.format;
+        if format_changed && self.dbv.vk_swapchain_output {
+            println!("Surface format changed from {:?} to {:?}", self.fmt.format, fmt.format);
+        }
 
         // assuming graphics and presentation queue families are the same ind.
         let swapchain_create_info = vk::SwapchainCreateInfoKHR {
@@ -93,7 +152,7 @@ impl SwapchainManager {
             min_image_count: capb.min_image_count+1,
             image_format: fmt.format,
             image_color_space: fmt.color_space,
-            image_extent: capb.current_extent,
+            image_extent: extent,
             image_array_layers: 1,
             image_usage: if self.prsnt_inp {
                 vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT
@@ -118,19 +177,39 @@ impl SwapchainManager {
             println!("Swapchain Object: {:?}", swapchain);
         }
 
-        let swapchain_images = self.loader.get_swapchain_images(swapchain[0])
+        self.images = self.loader.get_swapchain_images(swapchain[0])
             .expect("Failed to get swapchain images");
 
+        // the old swapchain may still be in use by frames in flight
+        self.deletion.retire(RetiredResource::Swapchain(self.loader.clone(), self.swapchain[0]));
+
+        self.swapchain = swapchain;
+        self.extent = extent;
+        self.capb = capb;
+        self.fmt = fmt;
+        self.prsnt = prsnt;
+
+        if !format_changed {
+            self.recreate_framebuffers();
+        }
+        format_changed
+    }
+
+    // the render pass has to be compatible with the current formats
+    pub(crate) unsafe fn set_renderpass(&mut self, renderpass: vk::RenderPass) {
+        self.renderpass = renderpass;
+        self.recreate_framebuffers();
+    }
+
+    unsafe fn recreate_framebuffers(&mut self) {
         let fbm = FramebufferManager::new_swapchain_bounded(
-            self.dbv, self.vi.clone(), self.device.clone(), self.renderpass, self.attachments.clone(), swapchain_images,
-            fmt.format, best_depth_format_support(), capb.current_extent, self.prsnt_inp
+            self.dbv, self.alloc.clone(), self.device.clone(), self.renderpass, self.attachments.clone(), self.images.clone(),
+            self.fmt.format, self.depth_fmt, self.extent
         );
 
-        self.device.device_wait_idle().unwrap();
-        self.destroy();
-
-        self.fbm = fbm;
-        self.swapchain = swapchain;
+        // the old framebuffers may still be in use by frames in flight
+        let old_fbm = std::mem::replace(&mut self.fbm, fbm);
+        old_fbm.retire(&self.deletion);
     }
 
     pub(crate) unsafe fn destroy(&self) {
@@ -155,30 +234,118 @@ pub(crate) unsafe fn query_swapchain_support(dbgv: DebugVisibility, vi: &VulkanI
     (capabilities, formats, present_modes)
 }
 
-unsafe fn select_swapchain_support(surf_fmt: Vec<vk::SurfaceFormatKHR>, prsnt_mode: Vec<vk::PresentModeKHR>)
-                                   -> (vk::SurfaceFormatKHR, vk::PresentModeKHR) {
-    // generally, you want to choose the best format, presentation mode, and extent
-    // but we'll just assume :)
+// current_extent is u32::MAX when the surface size follows the swapchain (e.g. Wayland)
+fn swap_extent(capb: &vk::SurfaceCapabilitiesKHR, window_extent: vk::Extent2D) -> vk::Extent2D {
+    if capb.current_extent.width != u32::MAX {
+        capb.current_extent
+    } else {
+        vk::Extent2D {
+            width: window_extent.width.clamp(capb.min_image_extent.width, capb.max_image_extent.width),
+            height: window_extent.height.clamp(capb.min_image_extent.height, capb.max_image_extent.height),
+        }
+    }
+}
 
-    let format = vk::SurfaceFormatKHR {
-        format: vk::Format::B8G8R8A8_SRGB,
-        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
-    };
-    let prsnt_mode = vk::PresentModeKHR::MAILBOX;
+unsafe fn select_swapchain_support(
+    dbv: DebugVisibility, surf_fmts: Vec<vk::SurfaceFormatKHR>, prsnt_modes: Vec<vk::PresentModeKHR>,
+    prsnt_setting: PresentSetting,
+) -> (vk::SurfaceFormatKHR, vk::PresentModeKHR) {
+    let format = rank_surface_formats(&surf_fmts);
+    let prsnt_mode = rank_present_modes(&prsnt_modes, prsnt_setting);
+
+    if dbv.vk_swapchain_output {
+        println!("Selected surface format {format:?} and present mode {prsnt_mode:?} ({prsnt_setting:?})");
+    }
 
     (format, prsnt_mode)
 }
 
-unsafe fn best_depth_format_support() -> vk::Format {
-    // TODO
+// shaders write linear colors and the block textures are sampled as sRGB, so an sRGB swapchain format
+// is preferred, then a plain 8 bit one, then whatever the surface offers first
+fn rank_surface_formats(surf_fmts: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+    const PREFERRED: [vk::Format; 4] = [
+        vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB,
+        vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM,
+    ];
+
+    // a single undefined entry means the surface has no preference
+    if surf_fmts.len() == 1 && surf_fmts[0].format == vk::Format::UNDEFINED {
+        return vk::SurfaceFormatKHR {
+            format: PREFERRED[0],
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+    }
 
-    vk::Format::D32_SFLOAT
+    PREFERRED.iter()
+        .find_map(|preferred| surf_fmts.iter().find(|surf_fmt| {
+            surf_fmt.format == *preferred && surf_fmt.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        }))
+        .or(surf_fmts.first())
+        .copied()
+        .expect("Surface does not support any formats")
+}
+
+fn rank_present_modes(prsnt_modes: &[vk::PresentModeKHR], prsnt_setting: PresentSetting) -> vk::PresentModeKHR {
+    prsnt_setting.ranked_present_modes().iter()
+        .find(|mode| prsnt_modes.contains(mode))
+        .copied()
+        .unwrap_or(vk::PresentModeKHR::FIFO)  // required to be supported
+}
+
+pub(crate) unsafe fn best_depth_format_support(dbv: DebugVisibility, vi: &VulkanInstance) -> vk::Format {
+    const CANDIDATES: [vk::Format; 4] = [
+        vk::Format::D32_SFLOAT, vk::Format::D32_SFLOAT_S8_UINT,
+        vk::Format::D24_UNORM_S8_UINT, vk::Format::D16_UNORM,
+    ];
+
+    let format = CANDIDATES.iter()
+        .find(|fmt| vi.get_physical_device_format_properties(**fmt).optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT))
+        .copied()
+        .expect("No supported depth attachment format");
+
+    if dbv.vk_swapchain_output {
+        println!("Selected depth format {format:?}");
+    }
+
+    format
 }
 
 pub(crate) fn best_surface_color_and_depth_format(dbv: DebugVisibility, vi: Rc<VulkanInstance>) -> (vk::Format, vk::Format) {
     unsafe {
-        let (_, fmt, prsnt) = query_swapchain_support(dbv, &vi);
-        let (fmt, _) = select_swapchain_support(fmt, prsnt);
-        (fmt.format, best_depth_format_support())
+        let (_, fmt, _) = query_swapchain_support(dbv, &vi);
+        (rank_surface_formats(&fmt).format, best_depth_format_support(dbv, &vi))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_setting_falls_back_to_fifo() {
+        let only_fifo = [vk::PresentModeKHR::FIFO];
+        assert_eq!(rank_present_modes(&only_fifo, PresentSetting::LowLatency), vk::PresentModeKHR::FIFO);
+        assert_eq!(rank_present_modes(&only_fifo, PresentSetting::Uncapped), vk::PresentModeKHR::FIFO);
+
+        let all = [vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::FIFO, vk::PresentModeKHR::MAILBOX];
+        assert_eq!(rank_present_modes(&all, PresentSetting::VSync), vk::PresentModeKHR::FIFO);
+        assert_eq!(rank_present_modes(&all, PresentSetting::LowLatency), vk::PresentModeKHR::MAILBOX);
+        assert_eq!(rank_present_modes(&all, PresentSetting::Uncapped), vk::PresentModeKHR::IMMEDIATE);
+    }
+
+    #[test]
+    fn surface_format_ranking() {
+        let srgb = |format| vk::SurfaceFormatKHR {format, color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR};
+
+        let fmts = [srgb(vk::Format::A2B10G10R10_UNORM_PACK32), srgb(vk::Format::R8G8B8A8_UNORM), srgb(vk::Format::R8G8B8A8_SRGB)];
+        assert_eq!(rank_surface_formats(&fmts).format, vk::Format::R8G8B8A8_SRGB);
+
+        let fmts = [srgb(vk::Format::A2B10G10R10_UNORM_PACK32)];
+        assert_eq!(rank_surface_formats(&fmts).format, vk::Format::A2B10G10R10_UNORM_PACK32);
+
+        let fmts = [srgb(vk::Format::UNDEFINED)];
+        assert_eq!(rank_surface_formats(&fmts).format, vk::Format::B8G8R8A8_SRGB);
     }
 }
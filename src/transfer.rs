@@ -0,0 +1,374 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+use ash::{Device, vk};
+use crate::debug::DebugVisibility;
+use crate::memory::{align_up, Allocation, MemoryAllocator};
+use crate::util::{allocate_buffer, allocate_buffer_with_info, allocate_image};
+
+
+const STAGING_RING_SIZE: vk::DeviceSize = 32 * 1024 * 1024;
+// covers the optimal buffer copy offset alignment and texel sizes of any format we upload
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+// which (start, end) regions of the staging ring are still being read from, tagged with the upload
+// ticket that reads them. regions are handed out and released in order, so it can be treated as a queue
+#[derive(Debug)]
+struct RingRegions {
+    size: vk::DeviceSize,
+    head: vk::DeviceSize,
+    regions: VecDeque<(u64, vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl RingRegions {
+    fn new(size: vk::DeviceSize) -> Self {
+        Self { size, head: 0, regions: VecDeque::new() }
+    }
+
+    fn reserve(&mut self, ticket: u64, len: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let start = match self.regions.front() {
+            None if len <= self.size => 0,
+            None => return None,
+            Some(&(_, tail, _)) if tail < self.head => {
+                // [tail, head) is in use, try the end of the ring first and then wrap around
+                let start = align_up(self.head, alignment);
+                if start + len <= self.size {
+                    start
+                } else if len <= tail {
+                    0
+                } else {
+                    return None;
+                }
+            }
+            Some(&(_, tail, _)) => {
+                // wrapped around, only [head, tail) is free
+                let start = align_up(self.head, alignment);
+                if start + len <= tail {
+                    start
+                } else {
+                    return None;
+                }
+            }
+        };
+
+        self.head = start + len;
+        self.regions.push_back((ticket, start, start + len));
+        Some(start)
+    }
+
+    fn release(&mut self, completed_ticket: u64) {
+        while let Some(&(ticket, _, _)) = self.regions.front() {
+            if ticket > completed_ticket {
+                break;
+            }
+            self.regions.pop_front();
+        }
+    }
+
+    fn oldest_ticket(&self) -> Option<u64> {
+        self.regions.front().map(|(ticket, _, _)| *ticket)
+    }
+}
+
+
+// uploads go through a persistently mapped staging ring and are copied on a dedicated transfer queue
+// (when the device has one) without blocking the frame. every submission gets a ticket, which is the
+// value the timeline semaphore reaches once that submission has completed
+pub(crate) struct UploadQueue {
+    device: Rc<Device>,
+    alloc: Rc<MemoryAllocator>,
+
+    queue: vk::Queue,
+    queue_families: Vec<u32>,  // transfer family first, graphics family second (if they differ)
+    cmd_pool: vk::CommandPool,
+    timeline: vk::Semaphore,
+
+    ring_buf: vk::Buffer,
+    ring_alloc: Allocation,
+    ring: RefCell<RingRegions>,
+
+    next_ticket: Cell<u64>,
+    completed_ticket: Cell<u64>,
+    in_flight: RefCell<VecDeque<(u64, vk::CommandBuffer)>>,
+    // uploads that never fit into the ring get their own staging buffer
+    oversized: RefCell<VecDeque<(u64, vk::Buffer, Allocation)>>,
+}
+
+impl UploadQueue {
+    pub(crate) unsafe fn new(
+        dbgv: DebugVisibility, alloc: Rc<MemoryAllocator>, device: Rc<Device>,
+        queue: vk::Queue, transfer_family: u32, gfxs_family: u32,
+    ) -> Self {
+        if dbgv.vk_setup_output {
+            println!("Upload queue family: {transfer_family} (graphics family: {gfxs_family})");
+        }
+
+        let cmd_pool_info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::TRANSIENT,
+            queue_family_index: transfer_family,
+            ..Default::default()
+        };
+        let cmd_pool = device.create_command_pool(&cmd_pool_info, None)
+            .expect("Failed to create upload command pool");
+
+        let mut timeline_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore_info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut timeline_info)
+            .build();
+        let timeline = device.create_semaphore(&semaphore_info, None)
+            .expect("Failed to create upload timeline semaphore");
+
+        let (ring_buf, ring_alloc, _) = allocate_buffer(
+            alloc.clone(), device.clone(), STAGING_RING_SIZE, vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+        );
+
+        let queue_families = if transfer_family == gfxs_family {
+            vec![transfer_family]
+        } else {
+            vec![transfer_family, gfxs_family]
+        };
+
+        Self {
+            device, alloc,
+            queue, queue_families, cmd_pool, timeline,
+            ring_buf, ring_alloc,
+            ring: RefCell::new(RingRegions::new(STAGING_RING_SIZE)),
+            next_ticket: Cell::new(1),
+            completed_ticket: Cell::new(0),
+            in_flight: RefCell::new(VecDeque::new()),
+            oversized: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    pub(crate) fn timeline(&self) -> vk::Semaphore {
+        self.timeline
+    }
+
+    // ticket of the last submitted upload
+    pub(crate) fn submitted(&self) -> u64 {
+        self.next_ticket.get() - 1
+    }
+
+    // ticket of the last upload known to have completed (as of the last poll)
+    pub(crate) fn completed(&self) -> u64 {
+        self.completed_ticket.get()
+    }
+
+    pub(crate) fn is_complete(&self, ticket: u64) -> bool {
+        ticket <= self.completed_ticket.get()
+    }
+
+    // device local buffer that both the transfer and the graphics queue can use without ownership transfers
+    pub(crate) unsafe fn create_local_buffer(&self, size: vk::DeviceSize, usage: vk::BufferUsageFlags)
+        -> (vk::Buffer, Allocation, vk::DeviceSize) {
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage | vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(self.sharing_mode())
+            .queue_family_indices(&self.queue_families)
+            .build();
+
+        allocate_buffer_with_info(self.alloc.clone(), self.device.clone(), &buffer_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+    }
+
+    pub(crate) unsafe fn create_local_image(
+        &self, img_extent: vk::Extent3D, mip_levels: u32, format: vk::Format, usage: vk::ImageUsageFlags,
+        layers: Option<u32>,
+    ) -> (vk::Image, Allocation) {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(img_extent)
+            .mip_levels(mip_levels)
+            .array_layers(layers.unwrap_or(1))
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage | vk::ImageUsageFlags::TRANSFER_DST)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .sharing_mode(self.sharing_mode())
+            .queue_family_indices(&self.queue_families)
+            .build();
+
+        allocate_image(self.alloc.clone(), self.device.clone(), &image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+    }
+
+    // copies the data into staging memory for the next submit, returns the buffer and offset to copy from
+    pub(crate) unsafe fn stage<D: Copy>(&self, data: &[D]) -> (vk::Buffer, vk::DeviceSize) {
+        let size = mem::size_of_val(data) as vk::DeviceSize;
+        let ticket = self.next_ticket.get();
+
+        let mut reserved = self.ring.borrow_mut().reserve(ticket, size, STAGING_ALIGNMENT);
+        if reserved.is_none() {
+            // make room by waiting on the oldest upload still reading from the ring, unless it is
+            // the one being staged right now
+            if let Some(oldest) = self.ring.borrow().oldest_ticket() {
+                if oldest < ticket {
+                    self.wait(self.submitted());
+                }
+            }
+            reserved = self.ring.borrow_mut().reserve(ticket, size, STAGING_ALIGNMENT);
+        }
+
+        match reserved {
+            Some(offset) => {
+                let dst = (self.ring_alloc.mapped_ptr as *mut u8).add(offset as usize);
+                std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, dst, size as usize);
+                (self.ring_buf, offset)
+            }
+            None => {
+                let (buf, buf_alloc, _) = allocate_buffer(
+                    self.alloc.clone(), self.device.clone(), size, vk::BufferUsageFlags::TRANSFER_SRC,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+                );
+                std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, buf_alloc.mapped_ptr as *mut u8, size as usize);
+                self.oversized.borrow_mut().push_back((ticket, buf, buf_alloc));
+                (buf, 0)
+            }
+        }
+    }
+
+    // records the copies from everything staged since the last submit, the returned ticket completes
+    // once the copies are done. anything using the results on the graphics queue has to wait on the timeline
+    pub(crate) unsafe fn submit<C: FnMut(vk::CommandBuffer)>(&self, mut record: C) -> u64 {
+        let ticket = self.next_ticket.get();
+
+        let cmd_alloc_info = vk::CommandBufferAllocateInfo {
+            command_pool: self.cmd_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: 1,
+            ..Default::default()
+        };
+        let cmd_buf = self.device.allocate_command_buffers(&cmd_alloc_info)
+            .expect("Failed to allocate upload command buffer")[0];
+
+        let cmd_begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+        self.device.begin_command_buffer(cmd_buf, &cmd_begin_info)
+            .expect("Failed to begin recording upload command buffer");
+
+        record(cmd_buf);
+
+        self.device.end_command_buffer(cmd_buf)
+            .expect("Failed to record upload command buffer");
+
+        let cmd_bufs = [cmd_buf];
+        let signal_semaphores = [self.timeline];
+        let signal_values = [ticket];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .signal_semaphore_values(&signal_values);
+        let submit_infos = [
+            vk::SubmitInfo::builder()
+                .command_buffers(&cmd_bufs)
+                .signal_semaphores(&signal_semaphores)
+                .push_next(&mut timeline_info)
+                .build()
+        ];
+        self.device.queue_submit(self.queue, &submit_infos, vk::Fence::null())
+            .expect("Failed to submit upload command buffer to transfer queue");
+
+        self.next_ticket.set(ticket + 1);
+        self.in_flight.borrow_mut().push_back((ticket, cmd_buf));
+
+        ticket
+    }
+
+    // frees the staging memory and command buffers of every completed upload, returns the completed ticket
+    pub(crate) unsafe fn poll(&self) -> u64 {
+        let completed = self.device.get_semaphore_counter_value(self.timeline)
+            .expect("Failed to query upload timeline semaphore");
+        self.release(completed);
+        completed
+    }
+
+    pub(crate) unsafe fn wait(&self, ticket: u64) {
+        let semaphores = [self.timeline];
+        let values = [ticket];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values)
+            .build();
+        self.device.wait_semaphores(&wait_info, u64::MAX)
+            .expect("Failed to wait for upload timeline semaphore");
+        self.poll();
+    }
+
+    unsafe fn release(&self, completed: u64) {
+        self.completed_ticket.set(completed);
+        self.ring.borrow_mut().release(completed);
+
+        let mut in_flight = self.in_flight.borrow_mut();
+        while let Some(&(ticket, cmd_buf)) = in_flight.front() {
+            if ticket > completed {
+                break;
+            }
+            self.device.free_command_buffers(self.cmd_pool, &[cmd_buf]);
+            in_flight.pop_front();
+        }
+
+        let mut oversized = self.oversized.borrow_mut();
+        while let Some(&(ticket, buf, buf_alloc)) = oversized.front() {
+            if ticket > completed {
+                break;
+            }
+            self.alloc.destroy_buffer(buf, buf_alloc);
+            oversized.pop_front();
+        }
+    }
+
+    fn sharing_mode(&self) -> vk::SharingMode {
+        if self.queue_families.len() > 1 {vk::SharingMode::CONCURRENT} else {vk::SharingMode::EXCLUSIVE}
+    }
+
+    // only valid once the device is idle
+    pub(crate) unsafe fn destroy(&self) {
+        self.release(u64::MAX);
+
+        self.alloc.destroy_buffer(self.ring_buf, self.ring_alloc);
+        self.device.destroy_semaphore(self.timeline, None);
+        self.device.destroy_command_pool(self.cmd_pool, None);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_wraps_around_released_regions() {
+        let mut ring = RingRegions::new(100);
+        assert_eq!(ring.reserve(1, 40, 1), Some(0));
+        assert_eq!(ring.reserve(2, 40, 1), Some(40));
+        assert_eq!(ring.reserve(3, 40, 1), None);
+
+        ring.release(1);
+        assert_eq!(ring.reserve(3, 40, 1), Some(0));
+        assert_eq!(ring.reserve(3, 1, 1), None);
+
+        ring.release(3);
+        assert_eq!(ring.oldest_ticket(), None);
+        assert_eq!(ring.reserve(4, 100, 1), Some(0));
+    }
+
+    #[test]
+    fn ring_respects_alignment() {
+        let mut ring = RingRegions::new(64);
+        assert_eq!(ring.reserve(1, 3, 1), Some(0));
+        assert_eq!(ring.reserve(1, 8, 16), Some(16));
+        assert_eq!(ring.reserve(1, 40, 16), None);
+        assert_eq!(ring.reserve(1, 32, 16), Some(32));
+    }
+
+    #[test]
+    fn oversized_reservation_is_rejected() {
+        let mut ring = RingRegions::new(64);
+        assert_eq!(ring.reserve(1, 65, 1), None);
+        assert_eq!(ring.oldest_ticket(), None);
+    }
+}
@@ -2,6 +2,9 @@ use std::{ffi, mem};
 use std::rc::Rc;
 use ash::{Device, vk};
 use crate::handler::VulkanInstance;
+use crate::memory::{Allocation, AllocationKind, MemoryAllocator};
+use crate::transfer::UploadQueue;
+use matrixagon_util::UniformBlock;
 
 // column major
 pub type Mat4 = [[f32;4];4];
@@ -48,7 +51,7 @@ pub(crate) fn matrix_ident() -> Mat4 {
 // }
 
 #[derive(Clone)]
-pub struct CmdBufContext(pub(crate) Rc<Device>, pub(crate) vk::CommandPool, pub(crate) vk::Queue);
+pub struct CmdBufContext(pub(crate) Rc<Device>, pub(crate) vk::CommandPool, pub(crate) vk::Queue, pub(crate) Rc<UploadQueue>);
 
 impl CmdBufContext {
     pub(crate) unsafe fn record<C: FnMut(vk::CommandBuffer) -> ()>(&self, mut record: C) {
@@ -73,9 +76,19 @@ impl CmdBufContext {
         self.0.end_command_buffer(cmd_bufs[0])
             .expect("Failed to record command buffers");
 
+        // one-shot graphics work is ordered after every upload submitted so far (e.g. mipmapping after a texture copy)
+        let wait_semaphores = [self.3.timeline()];
+        let wait_values = [self.3.submitted()];
+        let dst_stage_masks = [vk::PipelineStageFlags::ALL_COMMANDS];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values);
         let submit_infos = [
             vk::SubmitInfo::builder()
-                .command_buffers(&cmd_bufs).build()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&dst_stage_masks)
+                .command_buffers(&cmd_bufs)
+                .push_next(&mut timeline_info)
+                .build()
         ];
 
         self.0.queue_submit(self.2, &submit_infos, vk::Fence::null())
@@ -106,9 +119,9 @@ impl CmdBufContext {
 // }
 
 pub(crate) unsafe fn create_local_image(
-    vi: Rc<VulkanInstance>, device: Rc<Device>, img_extent: vk::Extent3D, mip_levels: u32,
+    alloc: Rc<MemoryAllocator>, device: Rc<Device>, img_extent: vk::Extent3D, mip_levels: u32,
     format: vk::Format, usage: vk::ImageUsageFlags, layers: Option<u32>,
-) -> (vk::Image, vk::DeviceMemory) {
+) -> (vk::Image, Allocation) {
     let image_info = vk::ImageCreateInfo {
         image_type: vk::ImageType::TYPE_2D,
         extent: img_extent,
@@ -123,60 +136,41 @@ pub(crate) unsafe fn create_local_image(
         ..Default::default()
     };
 
-    let (img, img_mem) = allocate_image(
-        vi.clone(), device.clone(), &image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL
+    let (img, img_alloc) = allocate_image(
+        alloc.clone(), device.clone(), &image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL
     );
 
-    (img, img_mem)
+    (img, img_alloc)
 }
 
 pub(crate) unsafe fn allocate_image(
-    vi: Rc<VulkanInstance>, device: Rc<Device>, image_info: &vk::ImageCreateInfo,
+    alloc: Rc<MemoryAllocator>, device: Rc<Device>, image_info: &vk::ImageCreateInfo,
     props: vk::MemoryPropertyFlags
-) -> (vk::Image, vk::DeviceMemory) {
+) -> (vk::Image, Allocation) {
     let img = device.create_image(&image_info, None)
         .expect("Failed to create image!");
 
     let mem_req = device.get_image_memory_requirements(img);
+    let img_alloc = alloc.allocate(mem_req, props, AllocationKind::Image);
 
-    let alloc_info = vk::MemoryAllocateInfo {
-        allocation_size: mem_req.size,
-        memory_type_index: find_memory_type(vi.clone(), mem_req, props),
-        ..Default::default()
-    };
-    let img_mem = device.allocate_memory(&alloc_info, None)
-        .expect("Failed to allocate image memory");
-
-    device.bind_image_memory(img, img_mem, 0).unwrap();
+    device.bind_image_memory(img, img_alloc.memory, img_alloc.offset).unwrap();
 
-    (img, img_mem)
+    (img, img_alloc)
 }
 
+// host buffers live in persistently mapped blocks, the returned pointer stays valid until the allocation is freed
 pub(crate) unsafe fn create_host_buffer<D: Copy>(
-    vi: Rc<VulkanInstance>, device: Rc<Device>, data: &[D], usage: vk::BufferUsageFlags, unmap: bool
-) -> (vk::Buffer, vk::DeviceMemory, *mut ffi::c_void, vk::DeviceSize) {
-    let (buf, buf_mem, buf_size) = allocate_buffer(
-        vi.clone(), device.clone(), (mem::size_of::<D>()*data.len()) as vk::DeviceSize, usage, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+    alloc: Rc<MemoryAllocator>, device: Rc<Device>, data: &[D], usage: vk::BufferUsageFlags
+) -> (vk::Buffer, Allocation, *mut ffi::c_void, vk::DeviceSize) {
+    let (buf, buf_alloc, buf_size) = allocate_buffer(
+        alloc.clone(), device.clone(), (mem::size_of::<D>()*data.len()) as vk::DeviceSize, usage, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
     );
 
-    let data_ptr = device.map_memory(buf_mem, 0, buf_size, vk::MemoryMapFlags::empty()).unwrap();
+    let data_ptr = buf_alloc.mapped_ptr;
     let mut data_align = ash::util::Align::new(data_ptr, mem::align_of::<D>() as u64, buf_size);
     data_align.copy_from_slice(data);
-    if unmap {
-        device.unmap_memory(buf_mem);
-    }
-
-    (buf, buf_mem, data_ptr, buf_size)
-}
-
-pub(crate) unsafe fn create_local_buffer(
-    vi: Rc<VulkanInstance>, device: Rc<Device>, size: vk::DeviceSize, usage: vk::BufferUsageFlags
-) -> (vk::Buffer, vk::DeviceMemory, vk::DeviceSize) {
-    let (buf, buf_mem, buf_size) = allocate_buffer(
-        vi.clone(), device.clone(), size, usage, vk::MemoryPropertyFlags::DEVICE_LOCAL
-    );
 
-    (buf, buf_mem, buf_size)
+    (buf, buf_alloc, data_ptr, buf_size)
 }
 
 // pub(crate) unsafe fn create_local_buffer(
@@ -187,15 +181,27 @@ pub(crate) unsafe fn create_local_buffer(
 //     (buf, buf_mem, data_ptr, buf_size)
 // }
 
-pub(crate) unsafe fn update_buffer<D: Copy>(data_ptr: *mut ffi::c_void, data: &[D], buf_size: vk::DeviceSize) {
-    let mut data_align = ash::util::Align::new(data_ptr, mem::align_of::<D>() as u64, buf_size);
-    data_align.copy_from_slice(data);
+// a host buffer laid out by the uniform block's std140/std430 rules rather than by rust
+pub(crate) unsafe fn create_uniform_buffer<U: UniformBlock>(
+    alloc: Rc<MemoryAllocator>, device: Rc<Device>, ubo: &U
+) -> (vk::Buffer, Allocation, *mut ffi::c_void, vk::DeviceSize) {
+    let (buf, buf_alloc, buf_size) = allocate_buffer(
+        alloc.clone(), device.clone(), U::SIZE as vk::DeviceSize, vk::BufferUsageFlags::UNIFORM_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+    );
+    update_uniform_buffer(buf_alloc.mapped_ptr, ubo);
+
+    (buf, buf_alloc, buf_alloc.mapped_ptr, buf_size)
+}
+
+pub(crate) unsafe fn update_uniform_buffer<U: UniformBlock>(data_ptr: *mut ffi::c_void, ubo: &U) {
+    ubo.write_block(std::slice::from_raw_parts_mut(data_ptr as *mut u8, U::SIZE));
 }
 
 pub(crate) unsafe fn allocate_buffer(
-    vi: Rc<VulkanInstance>, device: Rc<Device>, size: vk::DeviceSize, usage: vk::BufferUsageFlags,
+    alloc: Rc<MemoryAllocator>, device: Rc<Device>, size: vk::DeviceSize, usage: vk::BufferUsageFlags,
     props: vk::MemoryPropertyFlags
-) -> (vk::Buffer, vk::DeviceMemory, vk::DeviceSize) {
+) -> (vk::Buffer, Allocation, vk::DeviceSize) {
     let buffer_info = vk::BufferCreateInfo {
         size,
         usage,
@@ -203,21 +209,22 @@ pub(crate) unsafe fn allocate_buffer(
         ..Default::default()
     };
 
-    let buffer = device.create_buffer(&buffer_info, None).unwrap();
+    allocate_buffer_with_info(alloc, device, &buffer_info, props)
+}
+
+pub(crate) unsafe fn allocate_buffer_with_info(
+    alloc: Rc<MemoryAllocator>, device: Rc<Device>, buffer_info: &vk::BufferCreateInfo,
+    props: vk::MemoryPropertyFlags
+) -> (vk::Buffer, Allocation, vk::DeviceSize) {
+    let buffer = device.create_buffer(buffer_info, None).unwrap();
 
     let mem_req = device.get_buffer_memory_requirements(buffer);
     // println!("Memory requirements {mem_req:?}");
 
-    let mem_alloc_info = vk::MemoryAllocateInfo {
-        allocation_size: mem_req.size,
-        memory_type_index: find_memory_type(vi.clone(), mem_req, props),
-        ..Default::default()
-    };
-
-    let buffer_mem = device.allocate_memory(&mem_alloc_info, None).unwrap();
-    device.bind_buffer_memory(buffer, buffer_mem, 0).unwrap();
+    let buffer_alloc = alloc.allocate(mem_req, props, AllocationKind::Buffer);
+    device.bind_buffer_memory(buffer, buffer_alloc.memory, buffer_alloc.offset).unwrap();
 
-    (buffer, buffer_mem, buffer_info.size)
+    (buffer, buffer_alloc, buffer_info.size)
 }
 
 pub(crate) unsafe fn find_memory_type(vi: Rc<VulkanInstance>, mem_req: vk::MemoryRequirements,
@@ -3,10 +3,11 @@ use std::time::Duration;
 use winit::event::{VirtualKeyCode};
 use crate::component::{Component, RenderData};
 use crate::component::camera::Length3D;
+use crate::component::terrain::chunk_cache::CacheStats;
 use crate::component::texture::TextureIDMapper;
 use crate::debug::DebugVisibility;
+use crate::settings::Settings;
 use crate::util::CmdBufContext;
-use crate::shader::Shader;
 
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -35,12 +36,18 @@ pub(crate) enum WorldEvent {
     MiddleButtonPressed,
     MiddleButtonReleased,
     MouseMotion((f64, f64)),
+    CursorMoved((f64, f64)),  // physical pixels, only sent while the cursor is free
     KeyPressed(VirtualKeyCode),
     KeyReleased(VirtualKeyCode),
+    WindowResized((u32, u32)),  // swapchain extent after the resize
     // app events
     UserFaceDir(CardinalDir),
     UserPosition(Length3D),
     SpectatorMode(bool),
+    SettingsMenu(bool),
+    SettingsChanged(Settings),
+    ChunkCacheStats(CacheStats),
+    ShadersReloaded(Result<Vec<String>, String>),  // the reloaded shaders or the errors, the old pipelines stay on errors
 
     // TODO: request events? to reduce constant events emission
 }
@@ -72,6 +79,12 @@ impl World {
         descriptor_infos
     }
 
+    pub(crate) unsafe fn write_uniforms(&mut self, frame: usize) {
+        for component in &mut self.components {
+            component.write_uniforms(frame);
+        }
+    }
+
     pub(crate) unsafe fn destroy_descriptors(&mut self) {
         for component in &mut self.components {
             component.destroy();
@@ -97,7 +110,7 @@ impl World {
         self.events_buffer.clear();
     }
 
-    pub(crate) fn render(&self, shader: &mut Box<dyn Shader>) {
+    pub(crate) fn render(&self) -> Vec<RenderData> {
         let mut shader_data = Vec::new();
         for component in &self.components {
             let mut render_data = component.render();
@@ -107,8 +120,6 @@ impl World {
         if self.dbgv.mtxg_render_output {
             println!("RENDER DATA {:?}", shader_data.len());
         }
-        for rd in shader_data {
-            shader.recreate_buffer(rd);
-        }
+        shader_data
     }
 }